@@ -0,0 +1,49 @@
+//! Rough timing of writing a big tree, with and without percentages.
+//! Run with `cargo bench`.
+use sofidu::{DisplayOptions, Node};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const DIRS: u64 = 1_000;
+const FILES_PER_DIR: u64 = 100;
+const RUNS: u32 = 10;
+
+fn main() {
+    let dirs: Vec<_> = (0..DIRS)
+        .map(|d| {
+            let files: Vec<_> = (0..FILES_PER_DIR)
+                .map(|f| {
+                    // Pseudo random sizes
+                    let size = (d * FILES_PER_DIR + f).wrapping_mul(2_654_435_761) % 50_000;
+                    Node::new(PathBuf::from(format!("big/{}/{}", d, f)), size, vec![])
+                })
+                .collect();
+            let size = files.iter().map(|f| f.size).sum();
+            Node::new(PathBuf::from(format!("big/{}", d)), size, files)
+        })
+        .collect();
+    let size = dirs.iter().map(|d| d.size).sum();
+    let node = Node::new(PathBuf::from("big"), size, dirs);
+
+    for no_percent in [false, true] {
+        let opts = DisplayOptions {
+            no_percent,
+            ..Default::default()
+        };
+        let mut total = Duration::ZERO;
+        for _ in 0..RUNS {
+            let mut output = vec![];
+            let start = Instant::now();
+            node.write_tree(&mut output, 0, None, None, &opts)
+                .expect("Writing to a Vec can't fail");
+            total += start.elapsed();
+        }
+        println!(
+            "tree of {} entries, no_percent {}: {:?} per run ({} runs)",
+            DIRS * (FILES_PER_DIR + 1),
+            no_percent,
+            total / RUNS,
+            RUNS
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! Rough timing of sorting a tree with a very wide directory.
+//! Run with `cargo bench`.
+use sofidu::Node;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const CHILDREN: u64 = 100_000;
+const RUNS: u32 = 10;
+
+fn main() {
+    let children: Vec<_> = (0..CHILDREN)
+        .map(|i| {
+            // Pseudo random sizes, with some ties
+            let size = i.wrapping_mul(2_654_435_761) % 50_000;
+            Node::new(PathBuf::from(format!("wide/{}", i)), size, vec![])
+        })
+        .collect();
+    let node = Node::new(PathBuf::from("wide"), 0, children);
+
+    let mut total = std::time::Duration::ZERO;
+    for _ in 0..RUNS {
+        let mut node = node.clone();
+        let start = Instant::now();
+        node.sort();
+        total += start.elapsed();
+    }
+    println!(
+        "sort of {} children: {:?} per run ({} runs)",
+        CHILDREN,
+        total / RUNS,
+        RUNS
+    );
+}
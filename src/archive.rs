@@ -0,0 +1,255 @@
+//! Reading archives (tar, optionally gzipped, and zip) as virtual trees of nodes,
+//! using sizes of entries as if they were extracted.
+
+use crate::{tree_from_entries, Node};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// An entry read from an archive: path inside the archive, size and whether it's a directory
+pub type ArchiveEntry = (PathBuf, u64, bool);
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the archive at `path` into a tree of nodes, rooted at `path`.
+/// Format is detected from the contents, not from the extension.
+pub fn read_archive(path: &Path, depth: i32) -> io::Result<Node> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let entries = if read == 4 && magic == ZIP_MAGIC {
+        zip_entries(file)?
+    } else if read >= 2 && magic[..2] == GZIP_MAGIC {
+        tar_entries(GzDecoder::new(BufReader::new(file)))?
+    } else {
+        tar_entries(BufReader::new(file))?
+    };
+    Ok(tree_from_entries(path, entries, depth))
+}
+
+/// Gets a nul-terminated string from a tar header field
+fn tar_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Parses a numeric tar header field (octal, or base-256 for big GNU values)
+fn tar_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7f), |acc, &b| {
+                (acc << 8) | u64::from(b)
+            }));
+    }
+    let string = tar_str(field);
+    let string = string.trim_matches(|c: char| c == ' ' || c == '\0');
+    if string.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(string, 8).map_err(|_| invalid_data("Invalid number in tar header"))
+}
+
+/// Reads entries of a tar archive
+pub fn tar_entries<R: Read>(mut reader: R) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = vec![];
+    let mut header = [0u8; 512];
+    let mut long_name: Option<String> = None;
+    loop {
+        match reader.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            r => r?,
+        }
+        // Archive ends with zeroed blocks
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_number(&header[124..136])?;
+        let padded_size = size.div_ceil(512) * 512;
+
+        let mut name = tar_str(&header[0..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = tar_str(&header[345..500]);
+            if !prefix.is_empty() {
+                name = prefix + "/" + &name;
+            }
+        }
+        if let Some(long_name) = long_name.take() {
+            name = long_name;
+        }
+
+        match header[156] {
+            // GNU long name for the next entry
+            b'L' => {
+                let mut data = vec![];
+                (&mut reader).take(padded_size).read_to_end(&mut data)?;
+                if (data.len() as u64) < padded_size {
+                    return Err(invalid_data("Unexpected end of tar archive"));
+                }
+                long_name = Some(tar_str(&data[..size as usize]));
+                continue;
+            }
+            b'0' | b'7' | 0 => entries.push((PathBuf::from(name), size, false)),
+            b'5' => entries.push((PathBuf::from(name), 0, true)),
+            // Links and other special entries don't take space
+            _ => {}
+        }
+        let skipped = io::copy(&mut (&mut reader).take(padded_size), &mut io::sink())?;
+        if skipped < padded_size {
+            return Err(invalid_data("Unexpected end of tar archive"));
+        }
+    }
+    Ok(entries)
+}
+
+fn le_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn le_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// Reads entries of a zip archive from its central directory
+pub fn zip_entries<R: Read + Seek>(mut reader: R) -> io::Result<Vec<ArchiveEntry>> {
+    // End of central directory record is at the end, possibly followed by a comment
+    let len = reader.seek(SeekFrom::End(0))?;
+    let search = len.min(22 + u64::from(u16::MAX));
+    reader.seek(SeekFrom::Start(len - search))?;
+    let mut tail = vec![0u8; search as usize];
+    reader.read_exact(&mut tail)?;
+    let eocd = (0..=tail.len().saturating_sub(22))
+        .rev()
+        .find(|&i| tail[i..i + 4] == [0x50, 0x4b, 0x05, 0x06])
+        .ok_or_else(|| invalid_data("No end of central directory in zip archive"))?;
+
+    let count = le_u16(&tail, eocd + 10);
+    let cd_size = le_u32(&tail, eocd + 12);
+    let cd_offset = le_u32(&tail, eocd + 16);
+    if count == u16::MAX || cd_offset == u32::MAX {
+        return Err(invalid_data("Zip64 archives aren't supported"));
+    }
+
+    reader.seek(SeekFrom::Start(u64::from(cd_offset)))?;
+    let mut cd = vec![0u8; cd_size as usize];
+    reader.read_exact(&mut cd)?;
+
+    let mut entries = vec![];
+    let mut pos = 0;
+    for _ in 0..count {
+        if cd.len() < pos + 46 || cd[pos..pos + 4] != [0x50, 0x4b, 0x01, 0x02] {
+            return Err(invalid_data("Invalid central directory in zip archive"));
+        }
+        let size = le_u32(&cd, pos + 24);
+        let name_len = le_u16(&cd, pos + 28) as usize;
+        let extra_len = le_u16(&cd, pos + 30) as usize;
+        let comment_len = le_u16(&cd, pos + 32) as usize;
+        let name = cd
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| invalid_data("Invalid central directory in zip archive"))?;
+        let name = String::from_utf8_lossy(name);
+        let is_dir = name.ends_with('/');
+        entries.push((PathBuf::from(name.as_ref()), u64::from(size), is_dir));
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::*;
+
+    /// Creates a tar header block for given entry
+    fn tar_header(name: &str, size: u64, typeflag: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+        header
+    }
+
+    fn tar_file(name: &str, size: usize) -> Vec<u8> {
+        let mut data = tar_header(name, size as u64, b'0');
+        data.extend(vec![1u8; size]);
+        data.extend(vec![0u8; (512 - size % 512) % 512]);
+        data
+    }
+
+    #[test]
+    fn tar_to_node_test() {
+        let mut tar = vec![];
+        tar.extend(tar_header("foo/", 0, b'5'));
+        tar.extend(tar_file("foo/bar", 1_000));
+        tar.extend(tar_file("foo/baz/qiz", 333));
+        tar.extend(tar_file("top", 10));
+        tar.extend(vec![0u8; 1024]);
+
+        let entries = tar_entries(&tar[..]).unwrap();
+        assert_eq!(
+            vec![
+                (PathBuf::from("foo/"), 0, true),
+                (PathBuf::from("foo/bar"), 1_000, false),
+                (PathBuf::from("foo/baz/qiz"), 333, false),
+                (PathBuf::from("top"), 10, false),
+            ],
+            entries
+        );
+
+        let node = tree_from_entries(Path::new("a.tar"), entries, i32::MAX);
+        assert_eq!(1_343, node.size);
+        assert!(node.is_dir);
+        let foo = &node.children[0];
+        assert_eq!(PathBuf::from("a.tar/foo"), foo.path);
+        assert!(foo.is_dir);
+        assert_eq!(1_333, foo.size);
+        // Synthesized from the prefix of "foo/baz/qiz"
+        let baz = &foo.children[1];
+        assert_eq!(PathBuf::from("a.tar/foo/baz"), baz.path);
+        assert!(baz.is_dir);
+        assert_eq!(333, baz.size);
+        assert_eq!(PathBuf::from("a.tar/top"), node.children[1].path);
+        assert!(!node.children[1].is_dir);
+    }
+
+    #[test]
+    fn zip_to_node_test() {
+        let mut zip = vec![];
+        let names = [("foo/", 0u32), ("foo/bar", 1_000), ("baz", 42)];
+        for (name, size) in names {
+            let mut header = vec![0u8; 46];
+            header[..4].copy_from_slice(&[0x50, 0x4b, 0x01, 0x02]);
+            header[24..28].copy_from_slice(&size.to_le_bytes());
+            header[28..30].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend(header);
+            zip.extend(name.as_bytes());
+        }
+        let mut eocd = vec![0u8; 22];
+        eocd[..4].copy_from_slice(&[0x50, 0x4b, 0x05, 0x06]);
+        eocd[10..12].copy_from_slice(&3u16.to_le_bytes());
+        eocd[12..16].copy_from_slice(&(zip.len() as u32).to_le_bytes());
+        zip.extend(eocd);
+
+        let entries = zip_entries(io::Cursor::new(zip)).unwrap();
+        let node = tree_from_entries(Path::new("a.zip"), entries, i32::MAX);
+        assert_eq!(1_042, node.size);
+        assert_eq!(PathBuf::from("a.zip/baz"), node.children[0].path);
+        assert_eq!(
+            PathBuf::from("a.zip/foo/bar"),
+            node.children[1].children[0].path
+        );
+        assert!(node.children[1].is_dir);
+    }
+}
@@ -0,0 +1,176 @@
+//! Reusing subtrees of a previous walk for directories that weren't modified since (`--cache`)
+
+use crate::{save_json, unfollowed_root_link, walk, Node, WalkOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Tree of a previous walk, with modification times of its dirs at the time
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalkCache {
+    root: Option<Node>,
+    #[serde(with = "crate::path_serde::keys")]
+    mtimes: HashMap<PathBuf, SystemTime>,
+    /// Depth the tree was walked with, subtrees of other depths can't be reused
+    depth: i32,
+}
+
+impl WalkCache {
+    /// Gets the stored subtree of the dir at `path`, if its `mtime` is the same as when it was walked
+    pub fn get(&self, path: &Path, mtime: SystemTime) -> Option<&Node> {
+        if self.mtimes.get(path) != Some(&mtime) {
+            return None;
+        }
+        let mut node = self.root.as_ref()?;
+        while node.path != path {
+            node = node.children.iter().find(|c| path.starts_with(&c.path))?;
+        }
+        Some(node)
+    }
+
+    /// Saves the cache as JSON, to be used by the next walk
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_json(self, path)
+    }
+
+    /// Loads a cache saved with `save`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+
+    /// Copies mtimes of `node` and all dirs inside of it into `mtimes`
+    fn copy_mtimes(&self, node: &Node, mtimes: &mut HashMap<PathBuf, SystemTime>) {
+        if let Some(mtime) = self.mtimes.get(&node.path) {
+            mtimes.insert(node.path.clone(), *mtime);
+        }
+        for child in node.children.iter().filter(|c| c.is_dir) {
+            self.copy_mtimes(child, mtimes);
+        }
+    }
+}
+
+/// Cache being read from, and the next one being filled in, during a walk
+pub(crate) struct CacheState<'a> {
+    old: Option<&'a WalkCache>,
+    mtimes: Mutex<HashMap<PathBuf, SystemTime>>,
+}
+
+impl CacheState<'_> {
+    /// Gets a copy of the cached subtree of the dir, if it's unchanged
+    pub(crate) fn reuse(&self, path: &Path, metadata: &std::fs::Metadata) -> Option<Node> {
+        let old = self.old?;
+        let node = old.get(path, metadata.modified().ok()?)?.clone();
+        // Keep them for the next walk too
+        old.copy_mtimes(&node, &mut self.mtimes.lock().unwrap());
+        Some(node)
+    }
+
+    /// Stores mtime of a walked dir, for the next walk
+    pub(crate) fn record(&self, path: &Path, metadata: &std::fs::Metadata) {
+        if let Ok(mtime) = metadata.modified() {
+            self.mtimes
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), mtime);
+        }
+    }
+}
+
+/// Walks a directory like `walk_dir`, but reuses subtrees from `cache`
+/// for dirs whose modification time didn't change.
+/// A dir's mtime only changes when entries are added, removed or renamed directly inside of it,
+/// so e.g. a file growing, or a change in a dir deeper inside, isn't noticed.
+/// Other options than depth aren't checked, so the cache should be from a walk with the same ones.
+/// Returns the tree and the cache for the next walk.
+pub fn walk_dir_cached(path: &Path, opts: &WalkOptions, cache: &WalkCache) -> (Node, WalkCache) {
+    if let Some(node) = unfollowed_root_link(path, opts) {
+        return (node, WalkCache::default());
+    }
+    let usable = cache.depth == opts.depth && cache.root.as_ref().is_some_and(|r| r.path == path);
+    let state = CacheState {
+        old: usable.then_some(cache),
+        mtimes: Mutex::new(HashMap::new()),
+    };
+    let node = walk(path, opts.depth, 0, opts, &|_, _, _| {}, Some(&state));
+    let cache = WalkCache {
+        root: Some(node.clone()),
+        mtimes: state.mtimes.into_inner().unwrap(),
+        depth: opts.depth,
+    };
+    (node, cache)
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+
+    #[test]
+    fn walk_dir_cached_test() {
+        let dir = std::env::temp_dir().join(format!("sofidu_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("static")).unwrap();
+        std::fs::create_dir_all(dir.join("changing")).unwrap();
+        std::fs::write(dir.join("static").join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("changing").join("bar"), vec![1u8; 100]).unwrap();
+        let opts = WalkOptions::default();
+        let size_of = |node: &Node, path: &Path| {
+            node.flatten()
+                .into_iter()
+                .find(|n| n.path == path)
+                .map(|n| n.size)
+        };
+
+        let (node, cache) = walk_dir_cached(&dir, &opts, &WalkCache::default());
+        assert_eq!(crate::walk_dir(&dir, &opts), node);
+
+        // Overwriting a file doesn't touch the mtime of its dir,
+        // so the dir isn't walked again and keeps the old size
+        std::fs::write(dir.join("static").join("foo"), vec![1u8; 500]).unwrap();
+        // New entry does, so that one is walked again
+        std::fs::write(dir.join("changing").join("baz"), vec![1u8; 100]).unwrap();
+        // Whole tree would be reused if root's mtime stayed the same
+        std::fs::write(dir.join("qiz"), vec![1u8; 100]).unwrap();
+        // Just to be sure, with coarse timestamps mtimes could stay the same
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        for changed in [dir.clone(), dir.join("changing")] {
+            File::open(changed).unwrap().set_modified(later).unwrap();
+        }
+        let (node, cache) = walk_dir_cached(&dir, &opts, &cache);
+        assert_eq!(Some(100), size_of(&node, &dir.join("static").join("foo")));
+        assert_eq!(Some(100), size_of(&node, &dir.join("changing").join("baz")));
+        assert_eq!(Some(100), size_of(&node, &dir.join("qiz")));
+        // Reused dirs stay in the cache
+        assert!(cache.mtimes.contains_key(&dir.join("static")));
+
+        // Cache of another depth isn't used
+        let opts = WalkOptions {
+            depth: 5,
+            ..Default::default()
+        };
+        let (node, _) = walk_dir_cached(&dir, &opts, &cache);
+        assert_eq!(Some(500), size_of(&node, &dir.join("static").join("foo")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cache_invalid_utf8_test() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = crate::test_dir("cache_invalid_utf8");
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join(OsStr::from_bytes(b"bad\xffname"))).unwrap();
+        std::fs::write(tree.join("foo"), vec![1u8; 100]).unwrap();
+        let (_, cache) = walk_dir_cached(&tree, &WalkOptions::default(), &WalkCache::default());
+
+        let path = dir.join("cache.json");
+        cache.save(&path).unwrap();
+        assert_eq!(cache, WalkCache::load(&path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
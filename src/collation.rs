@@ -0,0 +1,43 @@
+//! Sorting names by Unicode rules instead of bytes (`--collation unicode`)
+use feruca::Collator;
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+
+/// Compares names by the Unicode Collation Algorithm, so e.g. `é` sorts next to `e`, not after `z`.
+/// Names that collate the same are still ordered, so sorting is stable between runs.
+pub fn compare_names(collator: &mut Collator, a: &OsStr, b: &OsStr) -> Ordering {
+    collator.collate(a.to_string_lossy().as_ref(), b.to_string_lossy().as_ref())
+}
+
+#[cfg(test)]
+mod collation_tests {
+    use crate::{Collation, Node};
+    use std::path::PathBuf;
+
+    #[test]
+    fn unicode_collation_test() {
+        let names = ["zebra", "éclair", "apple", "Eagle", "eel"];
+        let children = names
+            .iter()
+            .map(|n| Node::new(PathBuf::from("foo").join(n), 1, vec![]))
+            .collect();
+        let node = Node::new(PathBuf::from("foo"), 5, children);
+        let sorted_names = |collation| {
+            let mut node = node.clone();
+            node.sort_by_name_with(collation);
+            node.children
+                .iter()
+                .map(|c| c.get_display_name(false).into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec!["apple", "Eagle", "éclair", "eel", "zebra"],
+            sorted_names(Collation::Unicode)
+        );
+        // By code points, uppercase goes first and accented letters last
+        assert_eq!(
+            vec!["Eagle", "apple", "eel", "zebra", "éclair"],
+            sorted_names(Collation::Byte)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Checks whether entries of a scanned tree may be deleted, ahead of actually deleting them.
+//! Nothing is deleted yet, this is the guard any deleting has to go through (`--allow-delete`).
+
+use crate::{Node, SofiduError};
+use std::path::{Component, Path};
+
+/// Checks whether `node` could be deleted from the tree scanned at `root`.
+/// It has to be allowed at all, be inside the tree (but not the root itself),
+/// without `..` or symlinked dirs on the way there, and its directory has to be writable.
+pub fn can_delete(root: &Node, node: &Node, allow_delete: bool) -> Result<(), SofiduError> {
+    let cant =
+        |reason: &str| SofiduError::CantDelete(node.path.display().to_string(), reason.to_string());
+    if !allow_delete {
+        return Err(cant("deleting isn't allowed without --allow-delete"));
+    }
+    if node.path == root.path {
+        return Err(cant("it's the root of the scanned tree"));
+    }
+    if !node.path.starts_with(&root.path) {
+        return Err(cant("it's outside of the scanned tree"));
+    }
+    if node.path.components().any(|c| c == Component::ParentDir) {
+        return Err(cant("its path goes up with '..'"));
+    }
+    if node.path.symlink_metadata().is_err() {
+        return Err(cant("it doesn't exist"));
+    }
+    // A symlinked dir on the way could lead anywhere, the real path has to be the same one
+    let relative = node.path.strip_prefix(&root.path).unwrap_or(&node.path);
+    let real_parent = node.path.parent().and_then(|p| p.canonicalize().ok());
+    let expected_parent = root
+        .path
+        .canonicalize()
+        .ok()
+        .map(|real_root| real_root.join(relative.parent().unwrap_or(Path::new(""))));
+    if real_parent.is_none() || real_parent != expected_parent {
+        return Err(cant("it's behind a symlink"));
+    }
+    // Removing an entry changes its directory, not the entry itself
+    match node.path.parent() {
+        Some(parent) if is_writable(parent) => Ok(()),
+        _ => Err(cant("its directory isn't writable")),
+    }
+}
+
+/// Checks whether the current user can write to `path`
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    nix::unistd::access(path, nix::unistd::AccessFlags::W_OK).is_ok()
+}
+
+/// Checks whether `path` isn't read-only (the closest this gets outside of Unix)
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    path.metadata()
+        .map(|m| !m.permissions().readonly())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod delete_tests {
+    use super::*;
+    use crate::{test_dir, walk_dir, WalkOptions};
+
+    #[test]
+    fn can_delete_test() {
+        let dir = test_dir("delete");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("foo"), vec![1u8; 100]).unwrap();
+        let root = walk_dir(&dir, &WalkOptions::default());
+        let child = root.children.iter().find(|c| c.is_dir).unwrap();
+        let file = &child.children[0];
+
+        assert!(matches!(
+            can_delete(&root, &root, true),
+            Err(SofiduError::CantDelete(_, _))
+        ));
+        assert_eq!(Ok(()), can_delete(&root, child, true));
+        assert_eq!(Ok(()), can_delete(&root, file, true));
+        // Not without the flag
+        assert!(can_delete(&root, file, false).is_err());
+        // Nor from another tree
+        assert!(can_delete(child, &root, true).is_err());
+        // Nor by going up out of the tree
+        let outside = crate::Node::new(
+            dir.join("sub").join("..").join("..").join("elsewhere"),
+            0,
+            vec![],
+        );
+        assert!(can_delete(&root, &outside, true).is_err());
+        // Nor through a symlinked dir, even though the path looks like it's inside
+        #[cfg(unix)]
+        {
+            let elsewhere =
+                dir.with_file_name(format!("sofidu_delete_elsewhere_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&elsewhere);
+            std::fs::create_dir_all(&elsewhere).unwrap();
+            std::fs::write(elsewhere.join("bar"), vec![1u8; 100]).unwrap();
+            std::os::unix::fs::symlink(&elsewhere, dir.join("link")).unwrap();
+            let linked = crate::Node::new(dir.join("link").join("bar"), 100, vec![]);
+            assert!(can_delete(&root, &linked, true).is_err());
+            // The link itself is inside though
+            let link = crate::Node::new(dir.join("link"), 0, vec![]);
+            assert_eq!(Ok(()), can_delete(&root, &link, true));
+            std::fs::remove_dir_all(&elsewhere).unwrap();
+        }
+        // Nor if it's gone since the scan
+        std::fs::remove_file(&file.path).unwrap();
+        assert!(can_delete(&root, file, true).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
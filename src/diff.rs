@@ -0,0 +1,286 @@
+//! Comparing trees of nodes, e.g. a scan against a previously saved snapshot
+
+use crate::{format_size, save_json, DisplayOptions, Node};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// How an entry changed between two trees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Only in the new tree
+    Added(u64),
+    /// Only in the old tree
+    Removed(u64),
+    /// In both trees, with different sizes
+    Resized { old: u64, new: u64 },
+}
+
+/// Difference in a single entry, `path` is relative to the root of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+impl DiffEntry {
+    /// Gets a single line display for this entry, with path and the change
+    pub fn get_as_string_line(&self, opts: &DisplayOptions) -> String {
+        let format_options = opts.format_options();
+        let format_size = |size: u64| format_size(size, &format_options);
+        let change = match self.change {
+            Change::Added(_) => opts.theme.added.paint("new"),
+            Change::Removed(_) => opts.theme.removed.paint("deleted"),
+            Change::Resized { old, new } if new > old => opts
+                .theme
+                .increase
+                .paint(format!("+{}", format_size(new - old))),
+            Change::Resized { old, new } => opts
+                .theme
+                .decrease
+                .paint(format!("-{}", format_size(old - new))),
+        };
+        // Root itself has an empty relative path
+        let path = if self.path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &self.path
+        };
+        let path = opts.escape_name(&path.to_string_lossy());
+        format!("{}{}{}", path, opts.delimiter, change)
+    }
+}
+
+/// Gets path of `node` relative to `root`
+fn relative_path(node: &Node, root: &Path) -> PathBuf {
+    node.path
+        .strip_prefix(root)
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|_| node.path.clone())
+}
+
+/// Compares two trees, matching nodes by their path relative to the root of each tree.
+/// Returns changed entries in order of the new tree, followed by removed ones.
+pub fn diff_trees(old: &Node, new: &Node) -> Vec<DiffEntry> {
+    let old_nodes = old.flatten();
+    let mut old_sizes: HashMap<PathBuf, u64> = old_nodes
+        .iter()
+        .map(|n| (relative_path(n, &old.path), n.size))
+        .collect();
+
+    let mut entries = vec![];
+    for node in new.flatten() {
+        let path = relative_path(&node, &new.path);
+        match old_sizes.remove(&path) {
+            None => entries.push(DiffEntry {
+                path,
+                change: Change::Added(node.size),
+            }),
+            Some(old_size) if old_size != node.size => entries.push(DiffEntry {
+                path,
+                change: Change::Resized {
+                    old: old_size,
+                    new: node.size,
+                },
+            }),
+            Some(_) => {}
+        }
+    }
+    for node in old_nodes {
+        let path = relative_path(&node, &old.path);
+        if let Some(size) = old_sizes.remove(&path) {
+            entries.push(DiffEntry {
+                path,
+                change: Change::Removed(size),
+            });
+        }
+    }
+    entries
+}
+
+/// Gets how much entries in both trees changed in size, by their paths in the new tree.
+/// Entries that are only in one of them aren't included.
+pub fn size_deltas(old: &Node, new: &Node) -> HashMap<PathBuf, i64> {
+    diff_trees(old, new)
+        .into_iter()
+        .filter_map(|entry| match entry.change {
+            Change::Resized {
+                old: old_size,
+                new: new_size,
+            } => Some((new.path.join(entry.path), new_size as i64 - old_size as i64)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Saves the tree as JSON, to be compared against later
+pub fn save_snapshot(node: &Node, path: &Path) -> io::Result<()> {
+    save_json(node, path)
+}
+
+/// Loads a tree saved with `save_snapshot`
+pub fn load_snapshot(path: &Path) -> io::Result<Node> {
+    let reader = BufReader::new(File::open(path)?);
+    serde_json::from_reader(reader).map_err(io::Error::from)
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::test_dir;
+
+    fn dir(path: &str, size: u64, children: Vec<Node>) -> Node {
+        let mut node = Node::new(PathBuf::from(path), size, children);
+        node.is_dir = true;
+        node
+    }
+
+    #[test]
+    fn size_deltas_test() {
+        let old = dir(
+            "foo",
+            3_000,
+            vec![
+                Node::new(PathBuf::from("foo/bar"), 1_000, vec![]),
+                Node::new(PathBuf::from("foo/baz"), 2_000, vec![]),
+                Node::new(PathBuf::from("foo/gone"), 0, vec![]),
+            ],
+        );
+        let new = dir(
+            "foo",
+            6_200,
+            vec![
+                Node::new(PathBuf::from("foo/bar"), 1_000, vec![]),
+                Node::new(PathBuf::from("foo/baz"), 200, vec![]),
+                Node::new(PathBuf::from("foo/qiz"), 5_000, vec![]),
+            ],
+        );
+        let deltas = size_deltas(&old, &new);
+        assert_eq!(2, deltas.len());
+        assert_eq!(Some(&3_200), deltas.get(Path::new("foo")));
+        assert_eq!(Some(&-1_800), deltas.get(Path::new("foo/baz")));
+        // Unchanged and added entries show nothing
+        assert_eq!(None, deltas.get(Path::new("foo/bar")));
+        assert_eq!(None, deltas.get(Path::new("foo/qiz")));
+    }
+
+    #[test]
+    fn diff_trees_test() {
+        let old = dir(
+            "old",
+            3_000,
+            vec![
+                Node::new(PathBuf::from("old/foo"), 1_000, vec![]),
+                Node::new(PathBuf::from("old/bar"), 1_000, vec![]),
+                Node::new(PathBuf::from("old/baz"), 1_000, vec![]),
+            ],
+        );
+        let new = dir(
+            "new",
+            5_800,
+            vec![
+                Node::new(PathBuf::from("new/foo"), 1_000, vec![]),
+                Node::new(PathBuf::from("new/bar"), 3_300, vec![]),
+                Node::new(PathBuf::from("new/qiz"), 1_500, vec![]),
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                DiffEntry {
+                    path: PathBuf::new(),
+                    change: Change::Resized {
+                        old: 3_000,
+                        new: 5_800
+                    }
+                },
+                DiffEntry {
+                    path: PathBuf::from("bar"),
+                    change: Change::Resized {
+                        old: 1_000,
+                        new: 3_300
+                    }
+                },
+                DiffEntry {
+                    path: PathBuf::from("qiz"),
+                    change: Change::Added(1_500)
+                },
+                DiffEntry {
+                    path: PathBuf::from("baz"),
+                    change: Change::Removed(1_000)
+                },
+            ],
+            diff_trees(&old, &new)
+        );
+    }
+
+    #[test]
+    fn diff_entry_as_string_line_test() {
+        colored::control::set_override(false);
+        let opts = DisplayOptions::default();
+        let entry = |change| DiffEntry {
+            path: PathBuf::from("foo/bar"),
+            change,
+        };
+        assert_eq!(
+            "foo/bar +2.3MB",
+            entry(Change::Resized {
+                old: 1_000_000,
+                new: 3_300_000
+            })
+            .get_as_string_line(&opts)
+        );
+        assert_eq!(
+            "foo/bar -500B",
+            entry(Change::Resized {
+                old: 1_000,
+                new: 500
+            })
+            .get_as_string_line(&opts)
+        );
+        assert_eq!(
+            "foo/bar new",
+            entry(Change::Added(1)).get_as_string_line(&opts)
+        );
+        assert_eq!(
+            "foo/bar deleted",
+            entry(Change::Removed(1)).get_as_string_line(&opts)
+        );
+        let root = DiffEntry {
+            path: PathBuf::new(),
+            change: Change::Resized { old: 1, new: 2 },
+        };
+        assert_eq!(". +1B", root.get_as_string_line(&opts));
+    }
+
+    #[test]
+    fn snapshot_roundtrip_test() {
+        let node = dir(
+            "foo",
+            1_333,
+            vec![Node::new(PathBuf::from("foo/bar"), 1_000, vec![])],
+        );
+        let path =
+            std::env::temp_dir().join(format!("sofidu_snapshot_{}.json", std::process::id()));
+        save_snapshot(&node, &path).unwrap();
+        assert_eq!(node, load_snapshot(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_invalid_utf8_test() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let name = Path::new("foo").join(OsStr::from_bytes(b"bad\xffname"));
+        let node = dir("foo", 1_333, vec![Node::new(name, 1_000, vec![])]);
+        let path = test_dir("snapshot_invalid_utf8").join("snapshot.json");
+        save_snapshot(&node, &path).unwrap();
+        assert_eq!(node, load_snapshot(&path).unwrap());
+        // Valid names are still plain strings
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with(r#"{"path":"foo","#));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Finding files with the same contents, which waste space (`--find-dupes`)
+
+use crate::{format_size, DisplayOptions, Node};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Files with identical contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSet {
+    /// Size of each of the files
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateSet {
+    /// Space taken up by all but one of the copies
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Finds sets of files in the tree with identical contents, most wasted space first.
+/// Files are grouped by size first, so only ones of the same size are hashed.
+/// Hard links share their data, so only the first of the paths to it counts.
+/// Empty files don't waste anything and are left out, as are files that can't be read.
+pub fn find_duplicates(node: &Node) -> Vec<DuplicateSet> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for file in node
+        .flatten()
+        .into_iter()
+        .filter(|n| !n.is_dir && n.size > 0)
+    {
+        by_size.entry(file.size).or_default().push(file.path);
+    }
+    let mut sets: Vec<_> = by_size
+        .into_par_iter()
+        .map(|(size, paths)| (size, without_hard_links(paths)))
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map_iter(|(size, paths)| {
+            let mut by_hash: HashMap<blake3::Hash, Vec<PathBuf>> = HashMap::new();
+            for path in paths {
+                if let Ok(hash) = hash_file(&path) {
+                    by_hash.entry(hash).or_default().push(path);
+                }
+            }
+            by_hash
+                .into_values()
+                .filter(|paths| paths.len() > 1)
+                .map(move |mut paths| {
+                    paths.sort();
+                    DuplicateSet { size, paths }
+                })
+        })
+        .collect();
+    // Then by paths, so the order is always the same
+    sets.sort_by(|a, b| {
+        b.wasted()
+            .cmp(&a.wasted())
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    sets
+}
+
+/// Leaves only the first path (by name) to each file's data
+fn without_hard_links(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.sort();
+    let mut seen = HashSet::new();
+    paths.retain(|path| file_id(path).is_none_or(|id| seen.insert(id)));
+    paths
+}
+
+/// Gets device and inode of the file, the same for all hard links to it
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = path.metadata().ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Hard links can't be told apart from copies outside of unix
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Returns a string with a line for each set of duplicates, followed by their paths
+pub fn get_as_string_duplicates(node: &Node, opts: &DisplayOptions) -> String {
+    let format_size = |size| {
+        opts.theme
+            .size
+            .paint(format_size(size, &opts.format_options()))
+    };
+    let mut output = "".to_owned();
+    for set in find_duplicates(node) {
+        output += &format!(
+            "{} copies of{d}{}{d}({} wasted)\n",
+            set.paths.len(),
+            format_size(set.size),
+            format_size(set.wasted()),
+            d = opts.delimiter
+        );
+        for path in &set.paths {
+            let path = opts.escape_name(&path.to_string_lossy());
+            output += &format!("  {}\n", opts.theme.file.paint(path));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod dupes_tests {
+    use super::*;
+    use crate::{test_dir, walk_dir, WalkOptions};
+
+    #[test]
+    fn find_duplicates_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("dupes");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("foo"), vec![1u8; 1_000]).unwrap();
+        std::fs::write(dir.join("sub").join("copy"), vec![1u8; 1_000]).unwrap();
+        // Same size, but not the same contents
+        std::fs::write(dir.join("bar"), vec![2u8; 1_000]).unwrap();
+        std::fs::write(dir.join("empty"), "").unwrap();
+        std::fs::write(dir.join("empty2"), "").unwrap();
+        // Hard links don't take any more space
+        #[cfg(unix)]
+        {
+            std::fs::hard_link(dir.join("foo"), dir.join("foo_link")).unwrap();
+            std::fs::hard_link(dir.join("bar"), dir.join("bar_link")).unwrap();
+        }
+        let node = walk_dir(&dir, &WalkOptions::default());
+
+        let expected = DuplicateSet {
+            size: 1_000,
+            paths: vec![dir.join("foo"), dir.join("sub").join("copy")],
+        };
+        assert_eq!(vec![expected], find_duplicates(&node));
+        assert_eq!(
+            format!(
+                "2 copies of 1.0KB (1.0KB wasted)\n  {}\n  {}\n",
+                dir.join("foo").display(),
+                dir.join("sub").join("copy").display()
+            ),
+            get_as_string_duplicates(&node, &DisplayOptions::default())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Walking relative to an open directory, with `openat` and `fstatat`,
+//! so paths aren't resolved again along the way (and can't be swapped out mid-scan)
+
+use crate::{entry_size, note, sparse_sizes, Node, TimeKind, WalkOptions};
+use nix::dir::Dir;
+use nix::fcntl::{openat, AtFlags, OFlag};
+use nix::sys::stat::{fstatat, FileStat, Mode, SFlag};
+use rayon::prelude::*;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Walks an already opened directory recursively, like `walk_dir`.
+/// Paths of nodes are relative to the directory, which itself is `.`.
+/// Symlinks are only followed with `follow_symlinks`, targets aren't checked for `follow_under`,
+/// and `skip_recent` and `abort` aren't supported.
+pub fn walk_fd(dir: &File, opts: &WalkOptions) -> Node {
+    walk(dir, Path::new("."), opts.depth, 0, opts)
+}
+
+/// `symlinks` is how many symlinks were followed to get to `dir`
+fn walk(dir: &File, path: &Path, depth: i32, symlinks: usize, opts: &WalkOptions) -> Node {
+    let metadata = dir.metadata();
+    let mut total_size = metadata.as_ref().map(|m| entry_size(m, opts)).unwrap_or(0);
+
+    // Fresh handle, so reading entries doesn't move the offset of `dir`
+    let names: Vec<CString> = Dir::openat(
+        dir,
+        ".",
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .map(|mut entries| {
+        entries
+            .iter()
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_owned())
+            .filter(|name| name.as_bytes() != b"." && name.as_bytes() != b"..")
+            .collect()
+    })
+    .unwrap_or_default();
+
+    let (children, sizes): (Vec<_>, Vec<_>) = names
+        .par_iter()
+        .filter_map(|name| {
+            let entry_path = path.join(OsStr::from_bytes(name.as_bytes()));
+            if opts.is_excluded(&entry_path) {
+                return None;
+            }
+            let mut stat = fstatat(dir, name.as_c_str(), AtFlags::AT_SYMLINK_NOFOLLOW).ok()?;
+            let mut symlinks = symlinks;
+            let mut followed = false;
+            if file_type(&stat) == SFlag::S_IFLNK && opts.follow_symlinks {
+                if symlinks >= opts.max_symlink_depth {
+                    note(
+                        opts.quiet,
+                        format_args!(
+                            "Warning: not following {}, already followed {} symlinks",
+                            entry_path.display(),
+                            symlinks
+                        ),
+                    );
+                }
+                // Look at whatever the link points to instead
+                else if let Ok(s) = fstatat(dir, name.as_c_str(), AtFlags::empty()) {
+                    stat = s;
+                    symlinks += 1;
+                    followed = true;
+                }
+            }
+            let mut oflag = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+            if !followed {
+                // So it can't be swapped for a link after being looked at
+                oflag |= OFlag::O_NOFOLLOW;
+            }
+            match file_type(&stat) {
+                SFlag::S_IFDIR => {
+                    let child =
+                        File::from(openat(dir, name.as_c_str(), oflag, Mode::empty()).ok()?);
+                    let node = walk(&child, &entry_path, depth - 1, symlinks, opts);
+                    let size = (
+                        node.size,
+                        node.file_count,
+                        node.dirs_with_self(),
+                        node.sparse_sizes,
+                    );
+                    Some(((depth > 0).then_some(node), size))
+                }
+                SFlag::S_IFREG => {
+                    let node = file_node(entry_path, &stat, opts);
+                    let size = (node.size, 1, 0, node.sparse_sizes);
+                    Some(((depth > 0).then_some(node), size))
+                }
+                _ => None,
+            }
+        })
+        .unzip();
+
+    total_size += sizes.iter().map(|s| s.0).sum::<u64>();
+    let mut total_sparse = metadata
+        .as_ref()
+        .ok()
+        .filter(|_| opts.sparse)
+        .and_then(sparse_sizes);
+    if let Some((apparent, allocated)) = &mut total_sparse {
+        for (child_apparent, child_allocated) in sizes.iter().filter_map(|s| s.3) {
+            *apparent += child_apparent;
+            *allocated += child_allocated;
+        }
+    }
+    let children = children.into_iter().flatten().collect();
+    let mut node = Node::new(path.to_path_buf(), total_size, children);
+    node.is_dir = true;
+    node.file_count = sizes.iter().map(|s| s.1).sum();
+    node.dir_count = sizes.iter().map(|s| s.2).sum();
+    node.sparse_sizes = total_sparse;
+    if let Ok(m) = &metadata {
+        node.set_ownership(m);
+        node.set_time(m, opts.time);
+    }
+    node
+}
+
+/// Gets the file type bits of the mode
+fn file_type(stat: &FileStat) -> SFlag {
+    SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT
+}
+
+/// Creates node of a file, with the same details `walk_dir` stores
+fn file_node(path: PathBuf, stat: &FileStat, opts: &WalkOptions) -> Node {
+    let (len, blocks) = (stat.st_size as u64, stat.st_blocks as u64);
+    let size = if opts.blocks { blocks } else { len };
+    let mut node = Node::new(path, size, vec![]);
+    // Not `Node::new`'s guess, paths here aren't relative to the working directory
+    node.is_dir = false;
+    node.file_count = 1;
+    node.uid = Some(stat.st_uid);
+    node.gid = Some(stat.st_gid);
+    // `mode_t` is smaller than u32 on some platforms
+    #[allow(clippy::unnecessary_cast)]
+    let mode = stat.st_mode as u32;
+    node.mode = Some(mode);
+    node.time = opts.time.and_then(|kind| match kind {
+        TimeKind::Modified => to_system_time(stat.st_mtime, stat.st_mtime_nsec),
+        TimeKind::Accessed => to_system_time(stat.st_atime, stat.st_atime_nsec),
+        TimeKind::Changed => to_system_time(stat.st_ctime, stat.st_ctime_nsec),
+    });
+    if opts.sparse {
+        node.sparse_sizes = Some((len, blocks * 512));
+    }
+    node
+}
+
+/// Converts seconds and nanoseconds since the epoch, as in `stat`
+pub(crate) fn to_system_time(secs: i64, nanos: i64) -> Option<SystemTime> {
+    let secs = u64::try_from(secs).ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, u32::try_from(nanos).ok()?))
+}
+
+#[cfg(test)]
+mod fd_tests {
+    use super::*;
+    use crate::{test_dir, walk_dir};
+
+    /// Relative paths, sizes and kinds of all nodes in the tree, in a fixed order
+    fn entries(node: &Node) -> Vec<(PathBuf, u64, bool, u64, u64)> {
+        let mut entries: Vec<_> = node
+            .flatten()
+            .into_iter()
+            .map(|n| {
+                let path = n.path.strip_prefix(&node.path).unwrap().to_path_buf();
+                (path, n.size, n.is_dir, n.file_count, n.dir_count)
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn walk_fd_test() {
+        let dir = test_dir("walk_fd");
+        std::fs::create_dir_all(dir.join("sub").join("deeper")).unwrap();
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("sub").join("bar"), vec![1u8; 2_000]).unwrap();
+        std::fs::write(dir.join("sub").join("deeper").join("baz"), vec![1u8; 30]).unwrap();
+        std::os::unix::fs::symlink("foo", dir.join("link")).unwrap();
+
+        for opts in [
+            WalkOptions::default(),
+            WalkOptions {
+                depth: 1,
+                blocks: true,
+                ..Default::default()
+            },
+        ] {
+            let expected = walk_dir(&dir, &opts);
+            let node = walk_fd(&File::open(&dir).unwrap(), &opts);
+            assert_eq!(Path::new("."), node.path);
+            assert_eq!(entries(&expected), entries(&node));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Splitting a scan inside a git repository into `.git`, tracked and untracked files (`--git-summary`)
+use crate::{walk_dir, Node, WalkOptions};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Total sizes of files of a git repository that are in the scanned tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GitSummary {
+    /// Everything in `.git`, i.e. history and other metadata (even if it isn't in the scanned tree)
+    pub git_dir: u64,
+    /// Files in the index
+    pub tracked: u64,
+    /// Files that aren't, e.g. build output or ignored ones
+    pub untracked: u64,
+}
+
+/// Finds the repository `path` is in, by looking for `.git` in it and all its parents
+pub fn find_repo_root(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    path.ancestors()
+        .find(|p| p.join(".git").is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Reads paths of files in the index of the repository, relative to its root
+pub fn read_index(git_dir: &Path) -> io::Result<HashSet<PathBuf>> {
+    let data = std::fs::read(git_dir.join("index"))?;
+    parse_index(&data)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported git index"))
+}
+
+/// Parses paths out of the index file (versions 2 to 4)
+fn parse_index(data: &[u8]) -> Option<HashSet<PathBuf>> {
+    let be32 = |pos: usize| Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?));
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let mut paths = HashSet::new();
+    let mut previous: Vec<u8> = vec![];
+    let mut pos = 12;
+    for _ in 0..be32(8)? {
+        let start = pos;
+        // Stat data, object id and flags take 62 bytes, extended flags 2 more
+        let flags = u16::from_be_bytes(data.get(start + 60..start + 62)?.try_into().ok()?);
+        pos = start + 62;
+        if version >= 3 && flags & 0x4000 != 0 {
+            pos += 2;
+        }
+        let mut name = vec![];
+        if version == 4 {
+            // Name is the previous one without some bytes at the end, plus the rest
+            let (strip, len) = read_varint(data.get(pos..)?)?;
+            pos += len;
+            name.extend_from_slice(previous.get(..previous.len().checked_sub(strip)?)?);
+        }
+        let end = pos + data.get(pos..)?.iter().position(|&b| b == 0)?;
+        name.extend_from_slice(&data[pos..end]);
+        pos = if version == 4 {
+            end + 1
+        } else {
+            // Padded with 1 to 8 NULs to a multiple of 8 bytes
+            start + (end - start + 8) / 8 * 8
+        };
+        paths.insert(PathBuf::from(String::from_utf8_lossy(&name).into_owned()));
+        previous = name;
+    }
+    Some(paths)
+}
+
+/// Reads a variable length number of the index (not quite LEB128, each continuation adds one).
+/// Returns the number and how many bytes it took.
+fn read_varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut bytes = data.iter();
+    let mut byte = *bytes.next()?;
+    let mut value = usize::from(byte & 0x7f);
+    let mut len = 1;
+    while byte & 0x80 != 0 {
+        byte = *bytes.next()?;
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+        len += 1;
+    }
+    Some((value, len))
+}
+
+/// Sums up sizes of `.git`, and tracked and untracked files in `node`, the tree scanned at `path`.
+/// Dirs whose contents aren't in the tree (e.g. past its depth) count as tracked
+/// if anything in them is. `.git` is only walked if it isn't in the tree.
+/// Returns `None` if `path` isn't in a git repository.
+pub fn git_summary(path: &Path, node: &Node, opts: &WalkOptions) -> io::Result<Option<GitSummary>> {
+    let Some(repo_root) = find_repo_root(path) else {
+        return Ok(None);
+    };
+    let git_dir = repo_root.join(".git");
+    let tracked_paths = read_index(&git_dir)?;
+    // Paths of nodes start with `path`, the index has them relative to the root
+    let prefix = path
+        .canonicalize()?
+        .strip_prefix(&repo_root)
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut summary = GitSummary::default();
+    let mut git_dir_in_tree = false;
+    let mut nodes = vec![node];
+    while let Some(node) = nodes.pop() {
+        let relative = prefix.join(node.path.strip_prefix(path).unwrap_or(&node.path));
+        if relative == Path::new(".git") {
+            git_dir_in_tree = true;
+            summary.git_dir = files_size(node);
+            continue;
+        }
+        if relative.starts_with(".git") {
+            continue;
+        }
+        if !node.children.is_empty() {
+            nodes.extend(&node.children);
+            continue;
+        }
+        let tracked = if node.is_dir {
+            tracked_paths.iter().any(|p| p.starts_with(&relative))
+        } else {
+            tracked_paths.contains(&relative)
+        };
+        if tracked {
+            summary.tracked += files_size(node);
+        } else {
+            summary.untracked += files_size(node);
+        }
+    }
+    if !git_dir_in_tree {
+        let opts = WalkOptions {
+            depth: i32::MAX,
+            ..opts.clone()
+        };
+        summary.git_dir = files_size(&walk_dir(&git_dir, &opts));
+    }
+    Ok(Some(summary))
+}
+
+/// Sums sizes of files in the tree, without sizes of dirs themselves
+/// unless their contents aren't in the tree
+fn files_size(node: &Node) -> u64 {
+    if !node.children.is_empty() {
+        node.children.iter().map(files_size).sum()
+    } else if node.is_dir && node.file_count == 0 {
+        // Nothing but dirs in there
+        0
+    } else {
+        node.size
+    }
+}
+
+#[cfg(test)]
+mod git_tests {
+    use super::*;
+
+    /// Builds an index file of version 2 with empty stat data
+    fn index_v2(paths: &[&str]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend(2u32.to_be_bytes());
+        data.extend((paths.len() as u32).to_be_bytes());
+        for path in paths {
+            let start = data.len();
+            data.extend([0u8; 60]);
+            data.extend((path.len() as u16).to_be_bytes());
+            data.extend(path.as_bytes());
+            let len = data.len() - start;
+            data.extend(vec![0u8; (len + 8) / 8 * 8 - len]);
+        }
+        // Checksum isn't checked
+        data.extend([0u8; 20]);
+        data
+    }
+
+    #[test]
+    fn parse_index_test() {
+        let expected: HashSet<_> = ["a.txt", "sub/b.txt"].iter().map(PathBuf::from).collect();
+        assert_eq!(
+            Some(expected),
+            parse_index(&index_v2(&["a.txt", "sub/b.txt"]))
+        );
+        // Version 4 strips the previous name: "sub/b.txt" minus "b.txt" plus "c.txt"
+        let mut data = b"DIRC".to_vec();
+        data.extend(4u32.to_be_bytes());
+        data.extend(2u32.to_be_bytes());
+        for (strip, rest) in [(0u8, "sub/b.txt"), (5, "c.txt")] {
+            data.extend([0u8; 60]);
+            data.extend(0u16.to_be_bytes());
+            data.push(strip);
+            data.extend(rest.as_bytes());
+            data.push(0);
+        }
+        let expected: HashSet<_> = ["sub/b.txt", "sub/c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(Some(expected), parse_index(&data));
+        assert_eq!(None, parse_index(b"nope"));
+        assert_eq!(Some((0x80, 2)), read_varint(&[0x80, 0x00]));
+    }
+
+    #[test]
+    fn git_summary_test() {
+        let dir = std::env::temp_dir().join(format!("sofidu_git_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(".git").join("objects")).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let index = index_v2(&["a.txt", "sub/b.txt"]);
+        std::fs::write(dir.join(".git").join("index"), &index).unwrap();
+        std::fs::write(dir.join(".git").join("objects").join("x"), vec![1u8; 1_000]).unwrap();
+        std::fs::write(dir.join("a.txt"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("sub").join("b.txt"), vec![1u8; 200]).unwrap();
+        std::fs::write(dir.join("sub").join("c.txt"), vec![1u8; 50]).unwrap();
+        let opts = WalkOptions::default();
+        let summary_of = |path: &Path, opts: &WalkOptions| {
+            git_summary(path, &walk_dir(path, opts), opts)
+                .unwrap()
+                .unwrap()
+        };
+
+        let summary = summary_of(&dir, &opts);
+        assert_eq!(1_000 + index.len() as u64, summary.git_dir);
+        assert_eq!(300, summary.tracked);
+        assert_eq!(50, summary.untracked);
+        // Only what's in the scanned tree, but `.git` is still found
+        let summary = summary_of(&dir.join("sub"), &opts);
+        assert_eq!(1_000 + index.len() as u64, summary.git_dir);
+        assert_eq!(200, summary.tracked);
+        assert_eq!(50, summary.untracked);
+        // Dirs past the depth count whole, as tracked since something in them is
+        let opts = WalkOptions {
+            depth: 1,
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+        let size_of = |name: &str| {
+            let child = node.children.iter().find(|c| c.path.ends_with(name));
+            child.unwrap().size
+        };
+        let summary = git_summary(&dir, &node, &opts).unwrap().unwrap();
+        assert_eq!(size_of(".git"), summary.git_dir);
+        assert_eq!(100 + size_of("sub"), summary.tracked);
+        assert_eq!(0, summary.untracked);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,81 +1,345 @@
 use colored::*;
 use rayon::prelude::*;
-use std::path::{Path, PathBuf};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use clap::Arg;
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version};
 
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod cache;
+#[cfg(feature = "collation")]
+pub mod collation;
+pub mod delete;
+pub mod diff;
+#[cfg(feature = "dupes")]
+pub mod dupes;
+#[cfg(unix)]
+pub mod fd;
+pub mod git;
+pub mod manifest;
+pub mod owners;
+mod path_serde;
+pub mod theme;
+#[cfg(feature = "time")]
+pub mod time;
+
 /// Represents a file or a directory
 /// `size` for directories is computed at creation
 /// `children` is a vec of nodes which are inside this directory (empty for non-dirs)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Node {
+    #[serde(with = "path_serde")]
     pub path: PathBuf,
     pub size: u64,
     pub children: Vec<Node>,
     pub is_dir: bool,
+    /// Number of files in this subtree (1 for a file), computed at creation like `size`
+    #[serde(default)]
+    pub file_count: u64,
+    /// Number of dirs in this subtree, not counting this one, computed at creation like `size`
+    #[serde(default)]
+    pub dir_count: u64,
+    /// Size of this node that isn't in any of its children (e.g. a dir's own metadata),
+    /// as it was at creation, so `recompute_size` can add children up again.
+    /// Unknown for nodes from snapshots saved before it was stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub own_size: Option<u64>,
+    /// Owner, group and mode, only known for nodes walked on Unix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Time chosen with `WalkOptions::time`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<std::time::SystemTime>,
+    /// Apparent and allocated size in bytes (of the whole subtree for dirs),
+    /// only known for nodes walked with `WalkOptions::sparse`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_sizes: Option<(u64, u64)>,
+    /// Whether this dir is on another device than its parent,
+    /// only known for nodes walked with `WalkOptions::mark_mounts`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mount_point: bool,
 }
 
 impl Node {
     pub fn new(path: PathBuf, size: u64, children: Vec<Node>) -> Self {
+        let is_dir = path.is_dir();
+        // Nodes created from outside of walk only know about the files they hold
+        let file_count = if children.is_empty() {
+            u64::from(!is_dir)
+        } else {
+            children.iter().map(|c| c.file_count).sum()
+        };
+        let dir_count = children.iter().map(Node::dirs_with_self).sum();
+        let own_size = size.saturating_sub(children.iter().map(|c| c.size).sum());
         Self {
             size,
             children,
-            is_dir: path.is_dir(),
+            is_dir,
+            file_count,
+            dir_count,
+            own_size: Some(own_size),
             path,
+            uid: None,
+            gid: None,
+            mode: None,
+            time: None,
+            sparse_sizes: None,
+            mount_point: false,
+        }
+    }
+
+    /// Gets number of dirs in this subtree, counting this one
+    pub(crate) fn dirs_with_self(&self) -> u64 {
+        u64::from(self.is_dir) + self.dir_count
+    }
+
+    /// Gets number of files and dirs in this subtree, what `--count-only` shows
+    /// (1 for a file, a dir doesn't count itself)
+    pub fn entry_count(&self) -> u64 {
+        self.file_count + self.dir_count
+    }
+
+    /// Stores owner, group and mode from metadata (does nothing on non-Unix)
+    pub(crate) fn set_ownership(&mut self, metadata: &std::fs::Metadata) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            self.uid = Some(metadata.uid());
+            self.gid = Some(metadata.gid());
+            self.mode = Some(metadata.mode());
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+    }
+
+    /// Stores the chosen time from metadata, if the platform has it
+    pub(crate) fn set_time(&mut self, metadata: &std::fs::Metadata, kind: Option<TimeKind>) {
+        self.time = kind.and_then(|kind| match kind {
+            TimeKind::Modified => metadata.modified().ok(),
+            TimeKind::Accessed => metadata.accessed().ok(),
+            TimeKind::Changed => changed_time(metadata),
+        });
+    }
+
+    /// Gets the time field for list view, preceded by the delimiter,
+    /// as a date or as how long ago it was with `opts.age`.
+    /// Empty if time isn't known (or the time feature is off).
+    fn get_time_field(&self, opts: &DisplayOptions) -> String {
+        #[cfg(feature = "time")]
+        if let Some(time) = self.time {
+            let time = if opts.age {
+                time::humanize_age(time, std::time::SystemTime::now())
+            } else {
+                time::format_time(time)
+            };
+            return format!("{}{}", opts.delimiter, time);
+        }
+        let _ = opts;
+        "".to_string()
+    }
+
+    /// Gets owner, group and mode fields for the long list view, each preceded by the delimiter.
+    /// Empty if they aren't known.
+    fn get_ownership_fields(&self, names: &owners::Names, delimiter: &str) -> String {
+        match (self.uid, self.gid, self.mode) {
+            (Some(uid), Some(gid), Some(mode)) => format!(
+                "{d}{}{d}{}{d}{}",
+                names.user(uid),
+                names.group(gid),
+                owners::format_mode(mode),
+                d = delimiter
+            ),
+            _ => "".to_string(),
+        }
+    }
+
+    /// Gets filename (or full path) of this node for display.
+    /// Invalid UTF-8 is replaced, so the name is shown as best as it can be.
+    pub fn get_display_name(&self, full_path: bool) -> std::borrow::Cow<'_, str> {
+        let name = if full_path {
+            self.path.as_os_str()
+        } else {
+            self.path
+                .file_name()
+                .unwrap_or_else(|| self.path.as_os_str())
+        };
+        name.to_string_lossy()
+    }
+
+    /// Gets the name of this node, colored by whether it's a dir or a file
+    /// Escaped as set in `opts`, see `DisplayOptions::escape_name`
+    fn get_styled_name(&self, full_path: bool, opts: &DisplayOptions) -> ColoredString {
+        self.style_name(opts.escape_name(&self.get_display_name(full_path)), opts)
+    }
+
+    /// Colors an already escaped name of this node, like `get_styled_name`
+    fn style_name(&self, mut string: String, opts: &DisplayOptions) -> ColoredString {
+        if opts.icons {
+            string = format!("{} {}", icon_for(self), string);
+        }
+        if self.is_dir {
+            string += std::path::MAIN_SEPARATOR_STR;
+            opts.theme.dir.paint(string)
+        } else {
+            opts.theme.file.paint(string)
+        }
+    }
+
+    /// Gets the value percentages are computed from
+    pub fn get_metric(&self, metric: PctMetric) -> u64 {
+        match metric {
+            PctMetric::Size => self.size,
+            PctMetric::Count => self.file_count,
+            PctMetric::Entries => self.entry_count(),
+        }
+    }
+
+    /// Gets percentage of `parent_size` (the parent's value of `metric`) this node takes
+    fn get_percentage(&self, parent_size: u64, metric: PctMetric) -> f32 {
+        match parent_size {
+            0 => 100.0, // If parent size is zero, just display 💯
+            v => (self.get_metric(metric) as f32 / v as f32) * 100.0,
         }
     }
 
     /// Gets a single line display for this node.
-    /// Includes filename or full path, and size
+    /// Includes filename or full path, and size.
+    /// `parent_size` is really the parent's value of `opts.pct_metric`, for the percentage.
     pub fn get_as_string_line(
         &self,
         full_path: bool,
-        machine_readable: bool,
         parent_size: Option<u64>,
+        opts: &DisplayOptions,
     ) -> String {
-        let string = if full_path {
-            self.path.to_str().unwrap_or("??")
-        } else {
-            self.path
-                .file_name()
-                .map(|f| f.to_str())
-                .flatten()
-                .unwrap_or_else(|| self.path.to_str().unwrap_or("??"))
-        };
-        let mut string = string.to_owned();
-        let string = if self.is_dir {
-            string += &std::path::MAIN_SEPARATOR.to_string();
-            string.bright_blue()
-        } else {
-            string.cyan()
+        let name = self.get_styled_name(full_path, opts);
+        self.get_as_string_line_named(name, parent_size, opts)
+    }
+
+    /// Gets the size field of this node's line, colored by how it compares to the baseline
+    fn get_styled_size(&self, opts: &DisplayOptions) -> ColoredString {
+        let format_options = opts.format_options();
+        let format_size = |size: u64| format_size(size, &format_options);
+        let string = match self.sparse_sizes.filter(|_| opts.show_both) {
+            _ if opts.count_only => match (opts.machine_readable, self.entry_count()) {
+                (true, count) => count.to_string(),
+                (false, 1) => "1 entry".to_string(),
+                (false, count) => format!("{} entries", count),
+            },
+            Some((apparent, allocated)) => format!(
+                "{} disk / {} apparent",
+                format_size(allocated),
+                format_size(apparent)
+            ),
+            None => format_size(self.size),
         };
-        let file_size_str = if machine_readable {
-            self.size.to_string()
-        } else {
-            file_size_to_str(self.size)
+        match opts.baseline.get(&self.path) {
+            Some(&delta) if delta > 0 => opts.theme.grew.paint(string),
+            Some(_) => opts.theme.shrunk.paint(string),
+            None => opts.theme.size.paint(string),
         }
-        .green();
+    }
+
+    /// Same as `get_as_string_line`, but with `string` in place of the name
+    fn get_as_string_line_named(
+        &self,
+        string: ColoredString,
+        parent_size: Option<u64>,
+        opts: &DisplayOptions,
+    ) -> String {
+        let format_options = opts.format_options();
+        let format_size = |size: u64| format_size(size, &format_options);
+        let file_size_str = self.get_styled_size(opts);
 
         let percentage_string = match parent_size {
             None => "".to_string(),
             Some(parent_size) => {
-                let percentage = match parent_size {
-                    0 => 100.0, // If parent size is zero, just display 💯
-                    v => (self.size as f32 / v as f32) * 100.0,
-                };
-                let string = format!(" {:.1}%", percentage);
-                if percentage > 30.0 {
-                    string.red().bold()
-                } else if percentage > 16.0 {
-                    string.bright_red()
+                let percentage = self.get_percentage(parent_size, opts.pct_metric);
+                let color = opts.theme.percent(percentage);
+                let string = color.paint(format_percentage(percentage));
+                if opts.bars {
+                    let bar = color.paint(fraction_to_bar(percentage as f64 / 100.0, BAR_WIDTH));
+                    format!("{}{}{}{}", opts.delimiter, string, opts.delimiter, bar)
                 } else {
-                    string.white()
+                    format!("{}{}", opts.delimiter, string)
                 }
-                .to_string()
             }
         };
-        format!("{} {}{}", string, file_size_str, percentage_string)
+        let sparse_string = match self.sparse_sizes.filter(|_| opts.sparse) {
+            None => "".to_string(),
+            Some((apparent, allocated)) => {
+                let string = format!(
+                    "{} (apparent) / {} (allocated)",
+                    format_size(apparent),
+                    format_size(allocated)
+                );
+                // Highlight files that take less space than they seem to
+                let string = if allocated < apparent {
+                    opts.theme.highlight.paint(string)
+                } else {
+                    string.normal()
+                };
+                format!("{}{}", opts.delimiter, string)
+            }
+        };
+        let delta_string = match opts.deltas.get(&self.path) {
+            Some(&delta) if delta > 0 => format!(
+                "{}{}",
+                opts.delimiter,
+                opts.theme
+                    .increase
+                    .paint(format!("↑ +{}", format_size(delta.unsigned_abs())))
+            ),
+            Some(&delta) => format!(
+                "{}{}",
+                opts.delimiter,
+                opts.theme
+                    .decrease
+                    .paint(format!("↓ -{}", format_size(delta.unsigned_abs())))
+            ),
+            None => "".to_string(),
+        };
+        let balance_string = if opts.balance && !self.children.is_empty() {
+            let balance = format!("balance {:.2}", size_balance(&self.children));
+            format!("{}{}", opts.delimiter, opts.theme.detail.paint(balance))
+        } else {
+            "".to_string()
+        };
+        let rank_string = match opts.ranks.get(&self.path) {
+            Some((rank, siblings)) => {
+                let rank = format!("[#{}/{}]", rank, siblings);
+                format!("{}{}", opts.delimiter, opts.theme.detail.paint(rank))
+            }
+            None => "".to_string(),
+        };
+        let mount_string = if self.mount_point {
+            format!("{}{}", opts.delimiter, opts.theme.mount.paint("[mount]"))
+        } else {
+            "".to_string()
+        };
+        format!(
+            "{}{}{}{}{}{}{}{}{}",
+            string,
+            mount_string,
+            opts.delimiter,
+            file_size_str,
+            percentage_string,
+            sparse_string,
+            balance_string,
+            rank_string,
+            delta_string
+        )
     }
 
     /// Gets a recursive tree display for this node
@@ -85,61 +349,303 @@ impl Node {
         &self,
         depth: usize,
         size_threshold: Option<u64>,
-        machine_readable: bool,
         parent_size: Option<u64>,
+        opts: &DisplayOptions,
     ) -> (String, bool) {
-        let mut passed_threshold = if let Some(size_threshold) = size_threshold {
-            self.size >= size_threshold
+        let mut output = vec![];
+        self.write_tree(&mut output, depth, size_threshold, parent_size, opts)
+            .expect("Writing to a Vec can't fail");
+        (
+            String::from_utf8(output).expect("Tree output should be valid UTF-8"),
+            self.shown_in_tree(size_threshold, opts),
+        )
+    }
+
+    /// Writes a recursive tree display for this node line by line into `w`,
+    /// so the whole output doesn't have to be held in memory.
+    /// Children are only written if they (or something deeper) pass the threshold.
+    pub fn write_tree<W: Write>(
+        &self,
+        w: &mut W,
+        depth: usize,
+        size_threshold: Option<u64>,
+        parent_size: Option<u64>,
+        opts: &DisplayOptions,
+    ) -> io::Result<()> {
+        let passing = size_threshold.map(|t| self.passing_paths(t, opts.strict_threshold));
+        self.write_tree_filtered(w, depth, passing.as_ref(), parent_size, opts)
+    }
+
+    /// Writes the tree like `write_tree`, with what passes the threshold already known
+    fn write_tree_filtered<W: Write>(
+        &self,
+        w: &mut W,
+        depth: usize,
+        passing: Option<&HashSet<&Path>>,
+        parent_size: Option<u64>,
+        opts: &DisplayOptions,
+    ) -> io::Result<()> {
+        let root;
+        let node = if depth == 0 && opts.root_own_size {
+            let mut own = self.clone_childless();
+            own.size = self.get_own_size();
+            root = own;
+            &root
         } else {
-            true
+            self
         };
+        // Chain of dirs with just one dir inside each is one line, of the deepest one
+        let mut last = self;
+        let mut chain = vec![];
+        if opts.compact && depth > 0 {
+            while let [only] = last.children.as_slice() {
+                if !only.is_dir {
+                    break;
+                }
+                chain.push(opts.escape_name(&last.get_display_name(false)));
+                last = only;
+            }
+        }
+        let line = match opts.root_label.as_ref().filter(|_| depth == 0) {
+            Some(label) => {
+                let label = opts.theme.dir.paint(label.as_str());
+                node.get_as_string_line_named(label, parent_size, opts)
+            }
+            None if !chain.is_empty() => {
+                chain.push(opts.escape_name(&last.get_display_name(false)));
+                let name = last.style_name(chain.join(std::path::MAIN_SEPARATOR_STR), opts);
+                last.get_as_string_line_named(name, parent_size, opts)
+            }
+            None => node.get_as_string_line(depth == 0, parent_size, opts),
+        };
+        writeln!(w, "{}{}", get_connectors(depth, opts), line)?;
+        if depth > 0 && !opts.expands(last) {
+            // Collapsed into just its line, which still has the whole size
+            return Ok(());
+        }
+        let shown: Vec<_> = last
+            .children
+            .iter()
+            .filter(|c| passing.is_none_or(|passing| passing.contains(c.path.as_path())))
+            .collect();
+        let max_children = opts.max_children.unwrap_or(usize::MAX);
+        for child in shown.iter().take(max_children) {
+            let parent_size = (!opts.no_percent).then(|| last.get_metric(opts.pct_metric));
+            child.write_tree_filtered(w, depth + 1, passing, parent_size, opts)?;
+        }
+        if shown.len() > max_children {
+            // Summarize the rest, so it's clear something was left out
+            let rest = &shown[max_children..];
+            let rest_size = rest.iter().map(|c| c.size).sum::<u64>();
+            let rest_size = format_size(rest_size, &opts.format_options());
+            writeln!(
+                w,
+                "{}... and {} more (total {})",
+                get_connectors(depth + 1, opts),
+                rest.len(),
+                rest_size
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Gets size of this node that isn't in any of its children,
+    /// i.e. its own metadata size (and sizes of children that aren't stored)
+    pub fn get_own_size(&self) -> u64 {
+        self.size
+            .saturating_sub(self.children.iter().map(|c| c.size).sum())
+    }
+
+    /// Writes the tree as a Graphviz DOT graph, with a node labeled with name and size
+    /// for each entry and edges from dirs to what's inside of them.
+    /// Fonts are bigger for bigger entries, by their share of this node's size.
+    pub fn write_dot<W: Write>(&self, w: &mut W, opts: &DisplayOptions) -> io::Result<()> {
+        writeln!(w, "digraph sofidu {{")?;
+        writeln!(w, "    node [shape=box];")?;
+        self.write_dot_nodes(w, self.size, None, &mut 0, opts)?;
+        writeln!(w, "}}")
+    }
 
-        // This is display indentation, could be replaced with something prettier
-        let mut result = format!(
-            "{}{}\n",
-            "| ".repeat(depth),
-            &self.get_as_string_line(depth == 0, machine_readable, parent_size)
+    /// Writes DOT lines of this node, its edge from `parent` and everything below it.
+    /// Nodes are named by the order they're written in, `next_id` is the next free one.
+    fn write_dot_nodes<W: Write>(
+        &self,
+        w: &mut W,
+        total: u64,
+        parent: Option<usize>,
+        next_id: &mut usize,
+        opts: &DisplayOptions,
+    ) -> io::Result<()> {
+        let id = *next_id;
+        *next_id += 1;
+        let name = opts.escape_name(&self.get_display_name(parent.is_none()));
+        let label = format!(
+            "{}\\n{}",
+            name.replace('\\', "\\\\").replace('"', "\\\""),
+            format_size(self.size, &opts.format_options())
         );
+        let fraction = match total {
+            0 => 1.0,
+            total => self.size as f64 / total as f64,
+        };
+        let style = if self.is_dir {
+            ", style=filled, fillcolor=lightblue"
+        } else {
+            ""
+        };
+        writeln!(
+            w,
+            "    n{} [label=\"{}\", fontsize={:.1}{}];",
+            id,
+            label,
+            DOT_MIN_FONT_SIZE + (DOT_MAX_FONT_SIZE - DOT_MIN_FONT_SIZE) * fraction,
+            style
+        )?;
+        if let Some(parent) = parent {
+            writeln!(w, "    n{} -> n{};", parent, id)?;
+        }
+        for child in &self.children {
+            child.write_dot_nodes(w, total, Some(id), next_id, opts)?;
+        }
+        Ok(())
+    }
 
-        // This part is kinda wacky, but it had to be for parallelism
-        let (results, passed_thresholds): (Vec<_>, Vec<_>) = self
-            .children
-            .par_iter()
-            .map(|child| {
-                let child_res = child.get_as_string_tree(
-                    depth + 1,
-                    size_threshold,
-                    machine_readable,
-                    Some(self.size),
-                );
-                let mut child_out = "".to_owned();
-                let mut passed_threshold = false;
-                if let Some(size_threshold) = size_threshold {
-                    // Something deeper passed threshold so this node does too
-                    if child_res.1 {
-                        child_out += &child_res.0;
-                        passed_threshold = true;
-                    }
-                    // This node passes the threshold by itself
-                    else if child.size >= size_threshold {
-                        child_out += &format!(
-                            "{} {}\n",
-                            "| ".repeat(depth + 1),
-                            child.get_as_string_line(false, machine_readable, Some(self.size))
-                        );
-                        passed_threshold = true;
-                    }
-                } else {
-                    child_out += &child_res.0;
-                }
-                (child_out, passed_threshold)
-            })
-            .unzip(); // Vec of tuples to tuple of vecs
+    /// Writes this node and all nodes below it as newline-delimited JSON,
+    /// one object with `path`, `size` and `is_dir` per line
+    pub fn write_ndjson<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let entry = NdjsonEntry {
+            path: self.path.to_string_lossy(),
+            size: self.size,
+            is_dir: self.is_dir,
+        };
+        serde_json::to_writer(&mut *w, &entry)?;
+        writeln!(w)?;
+        for child in &self.children {
+            child.write_ndjson(w)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the biggest file in this subtree (this node itself, if it's a file)
+    pub fn largest_file(&self) -> Option<&Node> {
+        let own = (!self.is_dir).then_some(self);
+        self.children
+            .iter()
+            .filter_map(|c| c.largest_file())
+            .chain(own)
+            .max_by_key(|n| n.size)
+    }
+
+    /// Gets a line pointing out the biggest file in this subtree, if there's any
+    pub fn get_largest_file_line(&self, opts: &DisplayOptions) -> Option<String> {
+        let file = self.largest_file()?;
+        Some(format!(
+            "Largest file: {} ({})",
+            opts.theme
+                .file
+                .paint(opts.escape_name(&file.path.to_string_lossy())),
+            opts.theme
+                .size
+                .paint(format_size(file.size, &opts.format_options()))
+        ))
+    }
+
+    /// Gets a line with total size of this node, easy to tell apart from the entries
+    pub fn get_total_line(&self) -> String {
+        format!("TOTAL\t{}", self.size)
+    }
+
+    /// Writes a separate tree for each child of this node, with the child as its root,
+    /// so there's no combined root line. Biggest children go first.
+    pub fn write_per_child<W: Write>(
+        &self,
+        w: &mut W,
+        size_threshold: Option<u64>,
+        opts: &DisplayOptions,
+    ) -> io::Result<()> {
+        // Roots of these trees are children, not the root
+        let opts = &DisplayOptions {
+            root_label: None,
+            ..opts.clone()
+        };
+        let mut children: Vec<_> = self.children.iter().collect();
+        // Stable, so children just as big keep their order
+        children.sort_by_key(|child| std::cmp::Reverse(child.size));
+        for child in children {
+            if child.shown_in_tree(size_threshold, opts) {
+                child.write_tree(w, 0, size_threshold, None, opts)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether this node or anything below it is at least `size_threshold` big
+    pub fn passes_threshold(&self, size_threshold: Option<u64>) -> bool {
+        match size_threshold {
+            None => true,
+            Some(size_threshold) => {
+                self.size >= size_threshold
+                    || self
+                        .children
+                        .par_iter()
+                        .any(|c| c.passes_threshold(Some(size_threshold)))
+            }
+        }
+    }
+
+    /// Collects paths of nodes in this subtree that pass `passes_threshold`
+    /// (or `passes_threshold_strict` if `strict`),
+    /// checking each node once from the bottom up instead of everything below it again
+    fn passing_paths(&self, size_threshold: u64, strict: bool) -> HashSet<&Path> {
+        let mut passing = HashSet::new();
+        self.collect_passing(size_threshold, strict, &mut passing);
+        passing
+    }
+
+    /// Adds this node and nodes below it that pass to `passing`, returning if this one does
+    fn collect_passing<'a>(
+        &'a self,
+        size_threshold: u64,
+        strict: bool,
+        passing: &mut HashSet<&'a Path>,
+    ) -> bool {
+        // Every child has to be visited, so no short-circuiting
+        let mut below = false;
+        for child in &self.children {
+            below |= child.collect_passing(size_threshold, strict, passing);
+        }
+        let passes = if strict && !self.children.is_empty() {
+            below
+        } else {
+            self.size >= size_threshold || below
+        };
+        if passes {
+            passing.insert(&self.path);
+        }
+        passes
+    }
+
+    /// Checks whether any node without children (a file, or a dir cut off by depth)
+    /// in this subtree is at least `size_threshold` big, i.e. this is on the way to it.
+    /// Unlike `passes_threshold`, big dirs with nothing big inside don't pass.
+    pub fn passes_threshold_strict(&self, size_threshold: Option<u64>) -> bool {
+        match size_threshold {
+            None => true,
+            Some(size_threshold) if self.children.is_empty() => self.size >= size_threshold,
+            Some(_) => self
+                .children
+                .par_iter()
+                .any(|c| c.passes_threshold_strict(size_threshold)),
+        }
+    }
 
-        // Concat all results
-        result = results.iter().fold(result, |fold, r| fold + r);
-        passed_threshold |= passed_thresholds.par_iter().any(|&p| p);
-        (result, passed_threshold)
+    /// Checks the threshold the way `opts` say to
+    fn shown_in_tree(&self, size_threshold: Option<u64>, opts: &DisplayOptions) -> bool {
+        if opts.strict_threshold {
+            self.passes_threshold_strict(size_threshold)
+        } else {
+            self.passes_threshold(size_threshold)
+        }
     }
 
     /// Returns a string that lists all of the nodes,
@@ -148,484 +654,7040 @@ impl Node {
         &self,
         only_files: bool,
         size_threshold: Option<u64>,
-        machine_readable: bool,
+        path_filter: &PathFilter,
+        opts: &DisplayOptions,
     ) -> String {
         let mut output = "".to_owned();
-        let nodes = self.flatten();
-        for node in nodes {
-            if only_files && node.is_dir {
+        let names = opts.long.then(owners::Names::load);
+        let filter = EntryFilter {
+            only_files,
+            paths: path_filter.clone(),
+            size_range: opts.size_range,
+        };
+        let nodes = self.filtered_entries(&filter, size_threshold, opts.pct_metric, opts.order);
+        for (node, parent_size) in nodes {
+            let resolved;
+            let node = match opts.resolve_links.then(|| resolve_link(&node.path)) {
+                Some(Some(target)) => {
+                    resolved = Node {
+                        path: target,
+                        ..node.clone_childless()
+                    };
+                    &resolved
+                }
+                _ => node,
+            };
+            if let Some(format) = &opts.format {
+                // Template is checked when parsing arguments, but show what's wrong anyway
+                output += &expand_template(format, node, parent_size, opts)
+                    .unwrap_or_else(|e| e.to_string());
+                output += "\n";
                 continue;
             }
-            if let Some(size_threshold) = size_threshold {
-                if node.size < size_threshold {
-                    continue;
-                }
+            let parent_size = parent_size.filter(|_| opts.list_percent);
+            let mut fields = "".to_owned();
+            if let Some(names) = &names {
+                fields += &node.get_ownership_fields(names, &opts.delimiter);
+            }
+            fields += &node.get_time_field(opts);
+            let mut line = node.get_as_string_line(true, parent_size, opts);
+            let width = visible_width(&line) + visible_width(&fields);
+            if let Some(max_width) = opts.max_width.filter(|max| width > *max) {
+                // Shorten just the path, the rest of the line has to fit too
+                let name = opts.escape_name(&node.get_display_name(true));
+                let separator = usize::from(node.is_dir);
+                // Measured like the line, escape sequences in raw names don't take up space
+                let rest = width.saturating_sub(visible_width(&name) + separator);
+                let name = truncate_middle(&name, max_width.saturating_sub(rest + separator));
+                line =
+                    node.get_as_string_line_named(node.style_name(name, opts), parent_size, opts);
             }
-            output += &node.get_as_string_line(true, machine_readable, None);
+            output += &line;
+            output += &fields;
             output += "\n";
         }
         output
     }
 
-    /// Turns a tree of nodes into a flat vec of nodes
-    pub fn flatten(&self) -> Vec<Node> {
-        let mut nodes = vec![self.clone_childless()];
-        for child in &self.children {
-            nodes.append(&mut child.flatten());
+    /// Returns the entries of the list view as a GitHub-flavored Markdown table,
+    /// with paths, sizes and percentages of parents, without colors
+    pub fn get_as_string_markdown(
+        &self,
+        only_files: bool,
+        size_threshold: Option<u64>,
+        path_filter: &PathFilter,
+        opts: &DisplayOptions,
+    ) -> String {
+        let mut output = "| Path | Size | % |\n| --- | ---: | ---: |\n".to_owned();
+        let filter = EntryFilter {
+            only_files,
+            paths: path_filter.clone(),
+            size_range: opts.size_range,
+        };
+        let format_options = opts.format_options();
+        let nodes = self.filtered_entries(&filter, size_threshold, opts.pct_metric, opts.order);
+        for (node, parent_size) in nodes {
+            let mut name = opts.escape_name(&node.get_display_name(true));
+            if node.is_dir {
+                name += std::path::MAIN_SEPARATOR_STR;
+            }
+            let percentage = parent_size
+                .map(|p| format_percentage(node.get_percentage(p, opts.pct_metric)))
+                .unwrap_or_default();
+            output += &format!(
+                "| {} | {} | {} |\n",
+                name.replace('|', "\\|"),
+                format_size(node.size, &format_options),
+                percentage
+            );
+        }
+        output
+    }
+
+    /// Gets the nodes the list view shows (this one and everything below it),
+    /// for formatting them some other way
+    pub fn list_entries(&self, filter: EntryFilter, size_threshold: Option<u64>) -> Vec<&Node> {
+        self.filtered_entries(
+            &filter,
+            size_threshold,
+            PctMetric::Size,
+            ListOrder::DepthFirst,
+        )
+        .into_iter()
+        .map(|(node, _)| node)
+        .collect()
+    }
+
+    /// Gets nodes passing the list view's filters, with their parents' values of `metric`
+    fn filtered_entries(
+        &self,
+        filter: &EntryFilter,
+        size_threshold: Option<u64>,
+        metric: PctMetric,
+        order: ListOrder,
+    ) -> Vec<(&Node, Option<u64>)> {
+        let mut nodes = vec![];
+        match order {
+            ListOrder::DepthFirst => self.flatten_with_parent_size(None, metric, &mut nodes),
+            ListOrder::BreadthFirst => nodes = self.flatten_bfs_with_parent_size(metric),
         }
+        nodes.retain(|(node, _)| {
+            !(filter.only_files && node.is_dir)
+                && filter.paths.matches(&node.path)
+                && size_threshold.is_none_or(|t| node.size >= t)
+                && filter
+                    .size_range
+                    .is_none_or(|(min, max)| (min..=max).contains(&node.size))
+        });
         nodes
     }
-    /// Returns a clone of this node but without children
-    pub fn clone_childless(&self) -> Self {
-        Self {
-            path: self.path.clone(),
-            is_dir: self.is_dir,
-            size: self.size,
-            children: vec![],
+
+    /// Gets the lines of the tree view, in the order they're displayed,
+    /// for formatting them some other way.
+    /// Children are only included if they (or something deeper) pass the threshold.
+    pub fn tree_lines(&self, size_threshold: Option<u64>) -> TreeLines<'_> {
+        TreeLines {
+            stack: vec![TreeLine {
+                depth: 0,
+                node: self,
+                percent: None,
+            }],
+            passing: size_threshold.map(|t| self.passing_paths(t, false)),
         }
     }
 
-    /// Sort all nodes in the tree by size descending
-    pub fn sort(&mut self) {
-        self.children.sort_unstable_by_key(|c| c.size);
-        self.children.reverse();
-        for child in self.children.iter_mut() {
-            child.sort();
+    /// Returns the largest entries without children, that together make up
+    /// at least `percent`% of their total size, largest first.
+    /// Sizes of dirs themselves aren't in that total, since there are no entries for them.
+    pub fn cumulative_entries(&self, percent: f64) -> Vec<Node> {
+        let mut entries = vec![];
+        self.collect_leaves(&mut entries);
+        entries.sort_by_key(|node| std::cmp::Reverse(node.size));
+
+        let total: u64 = entries.iter().map(|node| node.size).sum();
+        let target = total as f64 * percent / 100.0;
+        let mut sum = 0;
+        let mut count = 0;
+        for node in &entries {
+            if sum as f64 >= target {
+                break;
+            }
+            sum += node.size;
+            count += 1;
         }
+        entries.truncate(count);
+        entries
     }
-}
 
-/// Walks a directory recursively, creating nodes along the way
-pub fn walk_dir(path: &Path, depth: i32, follow_symlinks: bool) -> Node {
-    let mut nodes: Vec<Node> = vec![];
+    /// Pushes all nodes without children (files, or dirs cut off by depth) into `nodes`
+    fn collect_leaves(&self, nodes: &mut Vec<Node>) {
+        if self.children.is_empty() {
+            nodes.push(self.clone());
+        }
+        for child in &self.children {
+            child.collect_leaves(nodes);
+        }
+    }
 
-    let mut total_size = path.metadata().map(|m| m.len()).unwrap_or(0);
+    /// Returns a string that lists the largest entries,
+    /// that together make up at least `percent`% of total size
+    pub fn get_as_string_cumulative(&self, percent: f64, opts: &DisplayOptions) -> String {
+        let mut output = "".to_owned();
+        let names = opts.long.then(owners::Names::load);
+        for node in self.cumulative_entries(percent) {
+            // Percentage is of the total here, that's what the entries add up to
+            let parent_size = Some(self.get_metric(opts.pct_metric)).filter(|_| opts.list_percent);
+            output += &node.get_as_string_line(true, parent_size, opts);
+            if let Some(names) = &names {
+                output += &node.get_ownership_fields(names, &opts.delimiter);
+            }
+            output += &node.get_time_field(opts);
+            output += "\n";
+        }
+        output
+    }
 
-    if let Ok(entries) = path.read_dir() {
-        // Walk over children
-        let (children, sizes): (Vec<_>, Vec<_>) = entries
-            .into_iter()
-            .par_bridge()
-            .filter_map(|entry| {
-                let mut node = None;
-                let mut size = None;
-                if let Ok(ref entry) = entry {
-                    if let Ok(file_type) = entry.file_type() {
-                        if file_type.is_dir() {
-                            // Walk this dir recursively
-                            let node_temp = walk_dir(&entry.path(), depth - 1, follow_symlinks);
-                            size = Some(node_temp.size);
-                            if depth > 0 {
-                                // If not too deep, store it
-                                node = Some(node_temp);
-                            }
-                        } else if file_type.is_file() {
-                            // Get size for this file
-                            let size_temp = entry.metadata().map(|m| m.len()).unwrap_or(0);
-                            size = Some(size_temp);
-                            if depth > 0 {
-                                // If not too deep, store it
-                                node = Some(Node::new(entry.path(), size_temp, vec![]));
-                            }
-                        }
-                    }
-                };
-                if node.is_some() || size.is_some() {
-                    // Store the results
-                    Some((node, size))
-                } else {
-                    // Filter out if both are none
-                    None
-                }
-            })
-            .unzip(); // Vec of tuples to tuple of vecs
+    /// Returns the `n` deepest entries with how deep they are (this node is 0),
+    /// deepest first and otherwise in the order of the tree
+    pub fn deepest_entries(&self, n: usize) -> Vec<(&Node, usize)> {
+        let mut entries = vec![];
+        self.flatten_with_depth(0, &mut entries);
+        // Stable, so entries just as deep keep their order
+        entries.sort_by_key(|(_, depth)| std::cmp::Reverse(*depth));
+        entries.truncate(n);
+        entries
+    }
 
-        // Append all new children
-        children.into_iter().flatten().for_each(|child| {
-            nodes.push(child);
-        });
-        // Add up all sizes of children
-        total_size += sizes.into_par_iter().flatten().sum::<u64>();
-    };
-    Node::new(path.to_path_buf(), total_size, nodes)
-}
+    /// Same as `flatten`, but pairs each node with its depth below this one
+    fn flatten_with_depth<'a>(&'a self, depth: usize, nodes: &mut Vec<(&'a Node, usize)>) {
+        nodes.push((self, depth));
+        for child in &self.children {
+            child.flatten_with_depth(depth + 1, nodes);
+        }
+    }
 
-pub struct AppSettings {
-    pub path: PathBuf,
-    pub depth: i32,
-    pub sort: bool,
-    pub reverse: bool,
-    pub list: bool,
-    pub machine: bool,
-    pub only_files: bool,
-    pub threshold: Option<u64>,
-}
+    /// Ranks every entry by size among the other children of its parent, biggest is 1,
+    /// as (rank, number of siblings including itself) by path.
+    /// Entries of the same size share the rank. The root isn't ranked.
+    pub fn sibling_ranks(&self) -> HashMap<PathBuf, (usize, usize)> {
+        let mut ranks = HashMap::new();
+        self.collect_ranks(&mut ranks);
+        ranks
+    }
 
-impl AppSettings {
-    /// Parses arguments using clap to AppSettings
-    pub fn from_args(args: Vec<String>) -> Self {
-        let clap_color_setting = if std::env::var_os("NO_COLOR").is_none() {
-            clap::AppSettings::ColoredHelp
-        } else {
-            clap::AppSettings::ColorNever
+    fn collect_ranks(&self, ranks: &mut HashMap<PathBuf, (usize, usize)>) {
+        let mut sizes: Vec<_> = self.children.iter().map(|c| c.size).collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        for child in &self.children {
+            // Position of the first one this big, same as 1 + number of bigger siblings
+            let rank = sizes.partition_point(|&size| size > child.size) + 1;
+            ranks.insert(child.path.clone(), (rank, sizes.len()));
+            child.collect_ranks(ranks);
+        }
+    }
+
+    /// Counts entries at each depth of the tree and sums up their sizes,
+    /// as (entries, size) with this node at index 0
+    pub fn sizes_by_level(&self) -> Vec<(u64, u64)> {
+        let mut entries = vec![];
+        self.flatten_with_depth(0, &mut entries);
+        let mut levels: Vec<(u64, u64)> = vec![];
+        for (node, depth) in entries {
+            if levels.len() <= depth {
+                levels.resize(depth + 1, (0, 0));
+            }
+            levels[depth].0 += 1;
+            levels[depth].1 += node.size;
+        }
+        levels
+    }
+
+    /// Returns a string with a line for each depth of the tree,
+    /// with the number of entries there and their total size
+    pub fn get_as_string_by_level(&self, opts: &DisplayOptions) -> String {
+        let mut output = "".to_owned();
+        for (level, (entries, size)) in self.sizes_by_level().into_iter().enumerate() {
+            let entries = match entries {
+                1 => "1 entry".to_string(),
+                entries => format!("{} entries", entries),
+            };
+            output += &format!(
+                "Level {}:{d}{},{d}{}\n",
+                level,
+                entries,
+                opts.theme
+                    .size
+                    .paint(format_size(size, &opts.format_options())),
+                d = opts.delimiter
+            );
+        }
+        output
+    }
+
+    /// Returns a string that lists the `n` deepest entries, with their depths
+    pub fn get_as_string_deepest(&self, n: usize, opts: &DisplayOptions) -> String {
+        let mut output = "".to_owned();
+        for (node, depth) in self.deepest_entries(n) {
+            let depth = format!("depth {}", depth);
+            output += &format!(
+                "{}{}{}\n",
+                node.get_as_string_line(true, None, opts),
+                opts.delimiter,
+                opts.theme.detail.paint(depth)
+            );
+        }
+        output
+    }
+
+    /// Returns a string with a line for each size class of `HISTOGRAM_BUCKETS`,
+    /// with the number of files in it, their total size and a bar of the number
+    pub fn get_as_string_histogram(&self, opts: &DisplayOptions) -> String {
+        let format_options = FormatOptions {
+            precision: 0,
+            ..opts.format_options()
         };
-        let app = app_from_crate!()
-            .setting(clap_color_setting)
-            .arg(
-                Arg::with_name("path")
-                    .help("Path to directory to walk. Current directory by default.")
-                    .default_value("."),
-            )
-            .arg(
-                Arg::with_name("depth")
-                    .help("Depth of displayed tree/list")
-                    .long("depth")
-                    .default_value("-1")
-                    .takes_value(true)
-                    .short("d"),
-            )
-            .arg(
-                Arg::with_name("sort")
-                    .help("Sort entries by size")
-                    .long("sort")
-                    .short("s"),
-            )
-            .arg(
-                Arg::with_name("reverse")
-                    .help("Reverse the output")
-                    .long("reverse")
-                    .short("r"),
-            )
-            .arg(
-                Arg::with_name("list")
-                    .help("Display entries as a list instead of a tree")
-                    .long("list")
-                    .short("l"),
-            )
-            .arg(
-                Arg::with_name("machine")
-                    .help("Display sizes in bytes (\"machine readable\")")
-                    .long("machine-readable")
-                    .short("m"),
-            )
-            .arg(
-                Arg::with_name("only files")
-                    .help("Display only files")
-                    .long("only_files")
-                    .requires("list")
-                    .short("f"),
-            )
-            .arg(
-                Arg::with_name("threshold")
-                    .value_name("thresh")
-                    .help("Only show files with size bigger than this (only for list view)")
-                    .long("threshold")
-                    .takes_value(true)
-                    .short("t"),
+        let format_size = |size: u64| format_size(size, &format_options);
+        let files: Vec<_> = self.flatten().into_iter().filter(|n| !n.is_dir).collect();
+        let mut output = "".to_owned();
+        let mut lower = 0;
+        for (upper, count, total) in size_histogram(&files, &HISTOGRAM_BUCKETS) {
+            let label = if upper == u64::MAX {
+                format!(">={}", format_size(lower))
+            } else {
+                format!("<{}", format_size(upper))
+            };
+            let fraction = match files.len() {
+                0 => 0.0,
+                len => count as f64 / len as f64,
+            };
+            output += &format!(
+                "{}{d}{} files{d}{}{d}{}\n",
+                label,
+                count,
+                opts.theme.size.paint(format_size(total)),
+                fraction_to_bar(fraction, BAR_WIDTH),
+                d = opts.delimiter
+            );
+            lower = upper;
+        }
+        output
+    }
+
+    /// Pairs every node in the tree with its share of this node's size, in percent
+    pub fn percentages_of_total(&self) -> Vec<(Node, f64)> {
+        self.flatten()
+            .into_iter()
+            .map(|node| {
+                let percentage = match self.size {
+                    0 => 100.0,
+                    total => node.size as f64 / total as f64 * 100.0,
+                };
+                (node, percentage)
+            })
+            .collect()
+    }
+
+    /// Returns a string that lists all of the nodes as `path\tpercentage`,
+    /// percentage being of the total size, with `opts.precision` decimal places
+    pub fn get_as_string_percent_only(&self, opts: &DisplayOptions) -> String {
+        let mut output = "".to_owned();
+        for (node, percentage) in self.percentages_of_total() {
+            output += &format!(
+                "{}\t{:.*}\n",
+                node.get_styled_name(true, opts),
+                opts.precision,
+                percentage
             );
+        }
+        output
+    }
 
-        // Get argument matches
-        let matches = app.get_matches_from(args);
-        let depth_input = matches.value_of("depth").unwrap();
-        let depth = match parse_depth(depth_input) {
-            Ok(v) => v,
-            Err(m) => {
-                println!("{}", m);
-                std::process::exit(1)
+    /// Same as `flatten`, but pairs each node with size (or other `metric`) of its parent
+    fn flatten_with_parent_size<'a>(
+        &'a self,
+        parent_size: Option<u64>,
+        metric: PctMetric,
+        nodes: &mut Vec<(&'a Node, Option<u64>)>,
+    ) {
+        nodes.push((self, parent_size));
+        for child in &self.children {
+            child.flatten_with_parent_size(Some(self.get_metric(metric)), metric, nodes);
+        }
+    }
+
+    /// Returns a string with a table of all of the nodes passing the threshold,
+    /// padded so it's `width` characters wide, with paths on the left and sizes aligned at the right edge.
+    /// Paths that don't fit are shortened in the middle.
+    pub fn get_as_string_table(
+        &self,
+        size_threshold: Option<u64>,
+        width: usize,
+        opts: &DisplayOptions,
+    ) -> String {
+        let rows: Vec<_> = self
+            .list_entries(EntryFilter::default(), size_threshold)
+            .into_iter()
+            .map(|node| (node, node.get_styled_size(opts)))
+            .collect();
+        let size_width = rows
+            .iter()
+            .map(|(_, size)| size.chars().count())
+            .max()
+            .unwrap_or(0);
+        let path_width = table_path_width(width, size_width);
+        let mut output = "".to_owned();
+        for (node, size) in rows {
+            let separator = usize::from(node.is_dir);
+            let name = opts.escape_name(&node.get_display_name(true));
+            let name = truncate_middle(&name, path_width.saturating_sub(separator));
+            let padding = path_width.saturating_sub(name.chars().count() + separator);
+            // By hand, colored strings aren't padded by the formatter
+            let size_padding = size_width - size.chars().count();
+            output += &format!(
+                "{}{} {}{}\n",
+                node.style_name(name, opts),
+                " ".repeat(padding),
+                " ".repeat(size_padding),
+                size
+            );
+        }
+        output
+    }
+
+    /// Same as `flatten_with_parent_size`, but level by level (breadth-first)
+    fn flatten_bfs_with_parent_size(&self, metric: PctMetric) -> Vec<(&Node, Option<u64>)> {
+        let mut nodes = vec![];
+        let mut queue = std::collections::VecDeque::from([(self, None)]);
+        while let Some((node, parent_size)) = queue.pop_front() {
+            let size = Some(node.get_metric(metric));
+            queue.extend(node.children.iter().map(|c| (c, size)));
+            nodes.push((node, parent_size));
+        }
+        nodes
+    }
+
+    /// Same as `flatten`, but level by level: this node, then all its children,
+    /// then all their children and so on
+    pub fn flatten_bfs(&self) -> Vec<Node> {
+        self.flatten_bfs_with_parent_size(PctMetric::Size)
+            .into_iter()
+            .map(|(node, _)| node.clone_childless())
+            .collect()
+    }
+
+    /// Turns a tree of nodes into a flat vec of nodes
+    pub fn flatten(&self) -> Vec<Node> {
+        let mut nodes = vec![self.clone_childless()];
+        for child in &self.children {
+            nodes.append(&mut child.flatten());
+        }
+        nodes
+    }
+    /// Returns a clone of this node but without children
+    pub fn clone_childless(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            is_dir: self.is_dir,
+            size: self.size,
+            file_count: self.file_count,
+            dir_count: self.dir_count,
+            own_size: self.own_size,
+            children: vec![],
+            uid: self.uid,
+            gid: self.gid,
+            mode: self.mode,
+            time: self.time,
+            sparse_sizes: self.sparse_sizes,
+            mount_point: self.mount_point,
+        }
+    }
+
+    /// Sort all nodes in the tree by size descending.
+    /// Nodes of the same size are sorted by path, so the order is always the same.
+    pub fn sort(&mut self) {
+        self.sort_by(SortKey::Size);
+    }
+
+    /// Same as `sort`, but only for the children of this node, deeper levels keep their order
+    pub fn sort_top_level(&mut self) {
+        self.sort_top_level_by(SortKey::Size);
+    }
+
+    /// Same as `sort`, but by `key` descending
+    pub fn sort_by(&mut self, key: SortKey) {
+        self.sort_top_level_by(key);
+        self.children
+            .par_iter_mut()
+            .for_each(|child| child.sort_by(key));
+    }
+
+    /// Same as `sort_top_level`, but by `key` descending
+    pub fn sort_top_level_by(&mut self, key: SortKey) {
+        match key {
+            SortKey::Size => {
+                let by_size =
+                    |a: &Node, b: &Node| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path));
+                if self.children.len() >= PAR_SORT_MIN_LEN {
+                    self.children.par_sort_unstable_by(by_size);
+                } else {
+                    self.children.sort_unstable_by(by_size);
+                }
+            }
+            SortKey::Count => self.children.sort_unstable_by(|a, b| {
+                b.entry_count()
+                    .cmp(&a.entry_count())
+                    .then_with(|| a.path.cmp(&b.path))
+            }),
+            // Goes through the whole subtree, so only once per child
+            SortKey::MaxChild => self
+                .children
+                .sort_by_cached_key(|c| (std::cmp::Reverse(c.max_leaf_size()), c.path.clone())),
+        }
+    }
+
+    /// Gets size of the biggest node without children (a file, or a dir cut off by depth)
+    /// in this subtree, which is this one itself if it has no children.
+    /// Dirs without any files inside are left out, their size isn't that of a file.
+    pub fn max_leaf_size(&self) -> u64 {
+        if self.children.is_empty() {
+            return if self.is_effectively_empty() {
+                0
+            } else {
+                self.size
+            };
+        }
+        self.children
+            .par_iter()
+            .map(|c| c.max_leaf_size())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Removes all nodes smaller than `size` from the tree.
+    /// Sizes of the remaining nodes still include what was removed.
+    pub fn prune_below(&mut self, size: u64) {
+        self.children.retain(|c| c.size >= size);
+        for child in self.children.iter_mut() {
+            child.prune_below(size);
+        }
+    }
+
+    /// Finds directories that look like copies of each other (e.g. the same dir bind-mounted twice),
+    /// i.e. those with the same names and sizes of children.
+    /// Returns pairs of (first found, copy of it), in tree order.
+    /// Copies inside of other copies aren't reported, as they are implied.
+    pub fn find_duplicate_subtrees(&self) -> Vec<(PathBuf, PathBuf)> {
+        let mut seen = std::collections::HashMap::new();
+        let mut duplicates = vec![];
+        self.collect_duplicates(&mut seen, &mut duplicates);
+        duplicates
+    }
+
+    fn collect_duplicates(
+        &self,
+        seen: &mut std::collections::HashMap<Vec<(OsString, u64)>, PathBuf>,
+        duplicates: &mut Vec<(PathBuf, PathBuf)>,
+    ) {
+        // Dirs without children (or cut off by depth) have nothing to compare
+        if self.size > 0 && !self.children.is_empty() {
+            let mut key: Vec<_> = self
+                .children
+                .iter()
+                .map(|c| (c.path.file_name().unwrap_or_default().to_owned(), c.size))
+                .collect();
+            key.sort();
+            if let Some(original) = seen.get(&key) {
+                duplicates.push((original.clone(), self.path.clone()));
+                return;
             }
+            seen.insert(key, self.path.clone());
+        }
+        for child in &self.children {
+            child.collect_duplicates(seen, duplicates);
+        }
+    }
+
+    /// Removes copies found by `find_duplicate_subtrees`, so they're counted once.
+    /// Sizes of their ancestors are updated. Returns what was removed.
+    pub fn dedupe_subtrees(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        let duplicates = self.find_duplicate_subtrees();
+        for (_, copy) in &duplicates {
+            self.remove_path(copy);
+        }
+        duplicates
+    }
+
+    /// Removes the node at `path` from the tree, subtracting its size, file and dir counts
+    /// from its ancestors. Returns those, if the node was found.
+    fn remove_path(&mut self, path: &Path) -> Option<(u64, u64, u64)> {
+        let removed = if let Some(i) = self.children.iter().position(|c| c.path == path) {
+            let child = self.children.remove(i);
+            (child.size, child.file_count, child.dirs_with_self())
+        } else {
+            self.children
+                .iter_mut()
+                .find(|c| path.starts_with(&c.path))?
+                .remove_path(path)?
         };
-        let path_str = matches.value_of("path").unwrap();
-        let sort = matches.is_present("sort");
-        let list = matches.is_present("list");
-        let only_files = matches.is_present("only files");
-        let machine = matches.is_present("machine");
-        let reverse = matches.is_present("reverse");
-        let threshold = matches.value_of("threshold").map(|a| {
-            let r = str_to_file_size(a);
-            match r {
-                Ok(v) => v,
-                Err(m) => {
-                    println!("{}", m);
-                    std::process::exit(1)
-                }
+        self.size = self.size.saturating_sub(removed.0);
+        self.file_count = self.file_count.saturating_sub(removed.1);
+        self.dir_count = self.dir_count.saturating_sub(removed.2);
+        Some(removed)
+    }
+
+    /// Recalculates sizes, file and dir counts of this node and all dirs below it
+    /// from their children, e.g. after removing some of them, and returns the new size.
+    /// Each node adds up its own size from creation (see `own_size`) and its children,
+    /// so nothing is read from the filesystem again.
+    /// Files and dirs cut off by depth keep their size, dirs with all children removed
+    /// are left with just their own size.
+    pub fn recompute_size(&mut self) -> u64 {
+        let own_size = self.own_size.unwrap_or_else(|| self.get_own_size());
+        if self.children.is_empty() {
+            if self.is_dir && own_size < self.size {
+                // Everything that was inside is gone
+                self.size = own_size;
+                self.file_count = 0;
+                self.dir_count = 0;
             }
-        });
+            return self.size;
+        }
+        let children_size: u64 = self
+            .children
+            .par_iter_mut()
+            .map(|c| c.recompute_size())
+            .sum();
+        self.size = own_size + children_size;
+        self.file_count =
+            u64::from(!self.is_dir) + self.children.iter().map(|c| c.file_count).sum::<u64>();
+        self.dir_count = self.children.iter().map(Node::dirs_with_self).sum();
+        self.size
+    }
 
-        // Check if path is valid
-        let path = PathBuf::from(path_str);
-        if !path.exists() || !path.is_dir() {
-            println!("Invalid path provided: {}", path_str);
-            std::process::exit(1);
+    /// Removes all nodes from the tree that don't match any of the globs (by name or whole path),
+    /// aren't inside of a matching one and don't have any matching ones inside.
+    /// Sizes of the remaining nodes still include what was removed.
+    /// Returns whether anything in this subtree matched.
+    pub fn keep_paths(&mut self, patterns: &[glob::Pattern]) -> bool {
+        if any_glob_matches(patterns, &self.path) {
+            // Whole branch is shown
+            return true;
         }
+        self.children.retain_mut(|c| c.keep_paths(patterns));
+        !self.children.is_empty()
+    }
 
-        Self {
-            path,
-            depth,
-            list,
-            sort,
-            only_files,
-            machine,
-            threshold,
-            reverse,
+    /// Whether this is a directory without any files in its subtree
+    /// (it can still have nonzero size, from its own metadata and that of empty dirs inside)
+    pub fn is_effectively_empty(&self) -> bool {
+        self.is_dir && self.file_count == 0
+    }
+
+    /// Removes all effectively empty directories from the tree.
+    /// Sizes of the remaining nodes still include what was removed.
+    pub fn prune_empty(&mut self) {
+        self.children.retain(|c| !c.is_effectively_empty());
+        for child in self.children.iter_mut() {
+            child.prune_empty();
+        }
+    }
+
+    /// Removes the bottom `levels` levels of the tree,
+    /// i.e. every node with less than `levels` levels of nodes below it,
+    /// so they're collapsed into their nearest remaining ancestor.
+    /// Sizes of the remaining nodes still include what was removed.
+    pub fn prune_depth(&mut self, levels: usize) {
+        self.prune_height(levels);
+    }
+
+    /// Prunes like `prune_depth`, returning height of this node from before pruning
+    fn prune_height(&mut self, levels: usize) -> usize {
+        let heights: Vec<usize> = self
+            .children
+            .iter_mut()
+            .map(|c| c.prune_height(levels))
+            .collect();
+        let height = heights.iter().max().map_or(0, |h| h + 1);
+        let mut heights = heights.into_iter();
+        self.children
+            .retain(|_| heights.next().is_some_and(|h| h >= levels));
+        height
+    }
+
+    /// Sort all nodes in the tree by name, so output doesn't depend on the OS order
+    pub fn sort_by_name(&mut self) {
+        self.sort_by_name_with(Collation::Byte);
+    }
+
+    /// Same as `sort_by_name`, but names are compared as `collation` says
+    pub fn sort_by_name_with(&mut self, collation: Collation) {
+        match collation {
+            Collation::Byte => self
+                .children
+                .sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
+            #[cfg(feature = "collation")]
+            Collation::Unicode => {
+                let mut collator = feruca::Collator::default();
+                let name = |node: &Node| node.path.file_name().unwrap_or_default().to_owned();
+                self.children
+                    .sort_by(|a, b| collation::compare_names(&mut collator, &name(a), &name(b)));
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.sort_by_name_with(collation);
         }
     }
 }
 
-/// Parses depth a from str
-fn parse_depth(input: &str) -> Result<i32, String> {
-    let mut depth = {
-        if let Ok(depth) = input.parse::<i32>() {
-            depth
+/// Single line of `--ndjson` output
+#[derive(Serialize)]
+struct NdjsonEntry<'a> {
+    path: std::borrow::Cow<'a, str>,
+    size: u64,
+    is_dir: bool,
+}
+
+/// Children of a node are sorted in parallel if there's at least this many of them
+const PAR_SORT_MIN_LEN: usize = 10_000;
+
+/// Options controlling how nodes are displayed
+#[derive(Debug, Clone)]
+pub struct DisplayOptions {
+    /// Display sizes in bytes
+    pub machine_readable: bool,
+    /// Put between fields of a line (name, size, percentage)
+    pub delimiter: String,
+    /// Decimal places of human readable sizes
+    pub precision: usize,
+    /// Show percentage of parent size in list view too
+    pub list_percent: bool,
+    /// Dim the tree indentation
+    pub connector_color: bool,
+    /// Show owner, group and mode in list view
+    pub long: bool,
+    /// Show times in list view as how long ago they were, instead of dates
+    pub age: bool,
+    /// Show names as they are, without escaping control characters
+    pub raw_names: bool,
+    /// Draw a bar after each percentage
+    pub bars: bool,
+    /// Leave percentages out of the tree, without computing them at all
+    pub no_percent: bool,
+    /// What percentages are computed from
+    pub pct_metric: PctMetric,
+    /// Show only root's own size on its tree line, instead of the total
+    pub root_own_size: bool,
+    /// Show at most this many children of each node in tree view
+    pub max_children: Option<usize>,
+    /// Template for lines of list view, see `expand_template`
+    pub format: Option<String>,
+    /// Show apparent and allocated sizes of files
+    pub sparse: bool,
+    /// Units of human readable sizes
+    pub units: UnitSystem,
+    /// Color the tree indentation by depth, cycling through `DEPTH_COLORS`
+    pub rainbow: bool,
+    /// Show only nodes leading to entries above the threshold in tree view,
+    /// see `Node::passes_threshold_strict`
+    pub strict_threshold: bool,
+    /// Separate thousands of machine readable sizes with this, e.g. `3,233,333`
+    pub digit_separator: Option<char>,
+    /// Show allocated and apparent sizes instead of just the size, where they're known
+    pub show_both: bool,
+    /// Shown in place of the path of the root in tree view
+    pub root_label: Option<String>,
+    /// Show children in tree view only for dirs at least this big, or with at least
+    /// `expand_min_children` children, if either is set (the root always has them)
+    pub expand_min_size: Option<u64>,
+    pub expand_min_children: Option<usize>,
+    /// Escape all non-ASCII characters in names, for terminals and logs that can't show them
+    pub ascii_escape: bool,
+    /// Size changes to show next to entries, by path (e.g. since the last refresh of `--watch`)
+    pub deltas: HashMap<PathBuf, i64>,
+    /// List only entries with sizes between these (inclusive)
+    pub size_range: Option<(u64, u64)>,
+    /// List symlinks by the real paths of what they point to
+    pub resolve_links: bool,
+    /// Shorten paths in list view, so lines are at most this many characters long
+    pub max_width: Option<usize>,
+    /// Order of entries in list view
+    pub order: ListOrder,
+    /// Put an icon based on type before each name (needs a Nerd Font)
+    pub icons: bool,
+    /// Show how evenly size is spread among children of each dir, see `size_balance`
+    pub balance: bool,
+    /// How much entries grew (or shrunk) since the baseline, by path, to color their sizes
+    pub baseline: HashMap<PathBuf, i64>,
+    /// Ranks of entries by size among their siblings, see `Node::sibling_ranks`
+    pub ranks: HashMap<PathBuf, (usize, usize)>,
+    /// Show number of files and dirs inside in place of the size
+    pub count_only: bool,
+    /// Merge chains of dirs with only one dir inside each into a single tree line
+    pub compact: bool,
+    pub theme: theme::Theme,
+}
+
+impl DisplayOptions {
+    /// Escapes control characters in a name, unless `raw_names` is set,
+    /// and non-ASCII characters too if `ascii_escape` is
+    pub fn escape_name(&self, name: &str) -> String {
+        let name = if self.raw_names {
+            name.to_string()
         } else {
-            return Err(format!(
-                "Invalid depth provided, expected integer value, got '{}'",
-                input
-            ));
+            escape_control_chars(name)
+        };
+        if self.ascii_escape {
+            escape_non_ascii(&name)
+        } else {
+            name
         }
-    };
-    if depth < -1 {
-        return Err("Depth must be 0 or greater or -1 for max depth".to_string());
     }
-    if depth == -1 {
-        depth = i32::MAX;
+
+    /// Checks if children of the dir are shown in tree view, see `expand_min_size`
+    pub fn expands(&self, node: &Node) -> bool {
+        if self.expand_min_size.is_none() && self.expand_min_children.is_none() {
+            return true;
+        }
+        self.expand_min_size.is_some_and(|min| node.size >= min)
+            || self
+                .expand_min_children
+                .is_some_and(|min| node.children.len() >= min)
+    }
+
+    /// Gets options for formatting sizes with `format_size`
+    pub fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            units: self.units,
+            precision: self.precision,
+            machine_readable: self.machine_readable,
+            digit_separator: self.digit_separator,
+        }
+    }
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            machine_readable: false,
+            delimiter: " ".to_string(),
+            precision: 1,
+            list_percent: false,
+            connector_color: true,
+            long: false,
+            age: false,
+            raw_names: false,
+            bars: false,
+            no_percent: false,
+            pct_metric: PctMetric::Size,
+            root_own_size: false,
+            max_children: None,
+            format: None,
+            sparse: false,
+            units: UnitSystem::Si,
+            rainbow: false,
+            strict_threshold: false,
+            digit_separator: None,
+            show_both: false,
+            root_label: None,
+            expand_min_size: None,
+            expand_min_children: None,
+            deltas: HashMap::new(),
+            ascii_escape: false,
+            size_range: None,
+            resolve_links: false,
+            max_width: None,
+            order: ListOrder::DepthFirst,
+            icons: false,
+            balance: false,
+            baseline: HashMap::new(),
+            ranks: HashMap::new(),
+            count_only: false,
+            compact: false,
+            theme: theme::Theme::DEFAULT,
+        }
+    }
+}
+
+/// Units of human readable sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitSystem {
+    /// Powers of 1000 (KB, MB, GB)
+    Si,
+    /// Powers of 1024 (KiB, MiB, GiB)
+    Iec,
+}
+
+/// How `format_size` formats sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub units: UnitSystem,
+    /// Decimal places (plain bytes are always whole)
+    pub precision: usize,
+    /// Plain number of bytes (or blocks), without units
+    pub machine_readable: bool,
+    /// Separate thousands of plain numbers with this
+    pub digit_separator: Option<char>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            units: UnitSystem::Si,
+            precision: 1,
+            machine_readable: false,
+            digit_separator: None,
+        }
+    }
+}
+
+/// What entries are sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Total size
+    Size,
+    /// Size of the biggest file inside, e.g. for finding which dir has the biggest file
+    MaxChild,
+    /// Number of files and dirs inside
+    Count,
+}
+
+/// How names are compared when sorting by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collation {
+    /// By bytes (code points), so e.g. `é` goes after `z`
+    Byte,
+    /// By Unicode rules, see `collation::compare_names`
+    #[cfg(feature = "collation")]
+    Unicode,
+}
+
+/// Order of entries in the list view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListOrder {
+    /// Each entry followed by everything inside of it
+    DepthFirst,
+    /// Level by level
+    BreadthFirst,
+}
+
+/// What percentage of parent is computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PctMetric {
+    Size,
+    /// Number of files, e.g. for finding what uses up inodes
+    Count,
+    /// Number of files and dirs, what `--count-only` shows
+    Entries,
+}
+
+/// Gets the time of the last status change of an entry
+#[cfg(unix)]
+fn changed_time(metadata: &std::fs::Metadata) -> Option<std::time::SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    fd::to_system_time(metadata.ctime(), metadata.ctime_nsec())
+}
+
+/// Gets the creation time of an entry, since there's no status change time
+#[cfg(not(unix))]
+fn changed_time(metadata: &std::fs::Metadata) -> Option<std::time::SystemTime> {
+    metadata.created().ok()
+}
+
+/// Gets the canonical path of what `path` points to, if it's a symlink that can be resolved
+pub fn resolve_link(path: &Path) -> Option<PathBuf> {
+    let metadata = path.symlink_metadata().ok()?;
+    if !metadata.file_type().is_symlink() {
+        return None;
+    }
+    path.canonicalize().ok()
+}
+
+/// Icons of files with these extensions, from Nerd Fonts
+const ICONS: [(&str, char); 24] = [
+    ("rs", '\u{e7a8}'),
+    ("py", '\u{e606}'),
+    ("js", '\u{e74e}'),
+    ("ts", '\u{e628}'),
+    ("go", '\u{e626}'),
+    ("c", '\u{e61e}'),
+    ("h", '\u{e61e}'),
+    ("cpp", '\u{e61d}'),
+    ("java", '\u{e738}'),
+    ("sh", '\u{f489}'),
+    ("html", '\u{f13b}'),
+    ("css", '\u{e749}'),
+    ("json", '\u{e60b}'),
+    ("toml", '\u{e615}'),
+    ("md", '\u{f48a}'),
+    ("txt", '\u{f15c}'),
+    ("pdf", '\u{f1c1}'),
+    ("png", '\u{f1c5}'),
+    ("jpg", '\u{f1c5}'),
+    ("mp3", '\u{f001}'),
+    ("mp4", '\u{f03d}'),
+    ("zip", '\u{f410}'),
+    ("gz", '\u{f410}'),
+    ("iso", '\u{e271}'),
+];
+const DIR_ICON: char = '\u{f115}';
+const FILE_ICON: char = '\u{f15b}';
+
+/// Gets the Nerd Font icon for a node, by its extension (case insensitive) or whether it's a dir
+pub fn icon_for(node: &Node) -> char {
+    if node.is_dir {
+        return DIR_ICON;
+    }
+    let extension = node
+        .path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    ICONS
+        .iter()
+        .find(|(ext, _)| extension.as_deref() == Some(*ext))
+        .map_or(FILE_ICON, |(_, icon)| *icon)
+}
+
+/// Replaces control characters (newlines, escape sequences etc.) with their escapes,
+/// so names can't mess up the terminal or pretend to be other lines
+pub fn escape_control_chars(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_control() {
+            // These are all below 0x100
+            escaped += &format!("\\x{:02x}", c as u32);
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Replaces non-ASCII characters with `\u{XXXX}` escapes, so names show the same everywhere
+pub fn escape_non_ascii(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            escaped += &format!("\\u{{{:04x}}}", c as u32);
+        }
+    }
+    escaped
+}
+
+/// Shortens `s` to at most `max` characters by replacing the middle with an ellipsis,
+/// keeping the file name at the end (or as much of its end as fits)
+pub fn truncate_middle(s: &str, max: usize) -> String {
+    let len = s.chars().count();
+    if len <= max {
+        return s.to_string();
+    }
+    if max == 0 {
+        return "".to_string();
+    }
+    // Includes the separator before the name, so it's clear something was left out before it
+    let name_len = s
+        .rfind(std::path::MAIN_SEPARATOR)
+        .map_or(len, |i| s[i..].chars().count());
+    let tail_len = name_len.min(max - 1);
+    let head: String = s.chars().take(max - 1 - tail_len).collect();
+    let tail: String = s.chars().skip(len - tail_len).collect();
+    format!("{}…{}", head, tail)
+}
+
+/// Counts characters of `s` that take up space, i.e. not ones of color escape sequences
+fn visible_width(s: &str) -> usize {
+    strip_escapes(s).chars().count()
+}
+
+/// Removes color escape sequences from `s`
+fn strip_escapes(s: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Sequences look like `\x1b[1;31m`
+            chars.find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Formats a percentage with one decimal place. Ones that aren't zero, but would show as 0.0%,
+/// are shown as <0.1% instead, so they can't be mistaken for empty entries
+fn format_percentage(percentage: f32) -> String {
+    let string = format!("{:.1}%", percentage);
+    if percentage > 0.0 && string == "0.0%" {
+        "<0.1%".to_string()
+    } else {
+        string
+    }
+}
+
+/// Gets how evenly size is spread among `children`, as entropy of their shares of the total,
+/// normalized to 0 (all of it in one of them) to 1 (all the same size).
+/// Less than two children, or all of them empty, count as balanced.
+pub fn size_balance(children: &[Node]) -> f32 {
+    let total: u64 = children.iter().map(|c| c.size).sum();
+    if children.len() < 2 || total == 0 {
+        return 1.0;
+    }
+    let entropy: f64 = children
+        .iter()
+        .filter(|c| c.size > 0)
+        .map(|c| {
+            let share = c.size as f64 / total as f64;
+            -share * share.ln()
+        })
+        .sum();
+    (entropy / (children.len() as f64).ln()) as f32
+}
+
+/// Width of the percentage bars, in characters
+const BAR_WIDTH: usize = 10;
+
+/// Width of tables (`--table`) when the output isn't a terminal
+pub const TABLE_FALLBACK_WIDTH: usize = 80;
+/// Paths in tables get at least this many characters, even if the terminal is narrower
+const TABLE_MIN_PATH_WIDTH: usize = 10;
+
+/// Gets width of the path column of a table `total` characters wide,
+/// with sizes `size_width` characters wide and a space between them
+pub fn table_path_width(total: usize, size_width: usize) -> usize {
+    total
+        .saturating_sub(size_width + 1)
+        .max(TABLE_MIN_PATH_WIDTH)
+}
+
+/// Gets width of the terminal the output goes to, or `TABLE_FALLBACK_WIDTH` if it isn't one
+pub fn table_width() -> usize {
+    terminal_size::terminal_size().map_or(TABLE_FALLBACK_WIDTH, |(width, _)| usize::from(width.0))
+}
+
+/// Font sizes of the smallest and the biggest entries in DOT graphs
+const DOT_MIN_FONT_SIZE: f64 = 10.0;
+const DOT_MAX_FONT_SIZE: f64 = 30.0;
+
+/// Draws a bar `width` characters long, filled in proportion to `fraction` (0 to 1).
+/// Partially filled characters use eighths of a block, so small differences are still visible.
+pub fn fraction_to_bar(fraction: f64, width: usize) -> String {
+    const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (fraction.clamp(0.0, 1.0) * (width * 8) as f64).round() as usize;
+    let (full, partial) = (eighths / 8, eighths % 8);
+    let mut bar = "█".repeat(full);
+    if partial != 0 {
+        bar.push(EIGHTHS[partial]);
+    }
+    // Pad, so whatever comes after is aligned
+    let len = bar.chars().count();
+    bar + &" ".repeat(width - len)
+}
+
+/// Upper bounds of the size classes in `--histogram`, from 1KB to 1GB
+pub const HISTOGRAM_BUCKETS: [u64; 7] = [
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+];
+
+/// Sorts nodes into size classes. `buckets` are ascending upper bounds (exclusive),
+/// nodes larger than all of them go into an extra class with `u64::MAX` as the bound.
+/// Returns (upper bound, count, total size) for each class.
+pub fn size_histogram(nodes: &[Node], buckets: &[u64]) -> Vec<(u64, usize, u64)> {
+    let mut histogram: Vec<_> = buckets
+        .iter()
+        .chain(std::iter::once(&u64::MAX))
+        .map(|&upper| (upper, 0, 0))
+        .collect();
+    for node in nodes {
+        let i = buckets
+            .iter()
+            .position(|&upper| node.size < upper)
+            .unwrap_or(buckets.len());
+        histogram[i].1 += 1;
+        histogram[i].2 += node.size;
+    }
+    histogram
+}
+
+/// Colors of tree indentation with `--rainbow`, by depth
+const DEPTH_COLORS: [Color; 6] = [
+    Color::Red,
+    Color::Yellow,
+    Color::Green,
+    Color::Cyan,
+    Color::Blue,
+    Color::Magenta,
+];
+
+/// Gets the indentation in front of a tree line, dimmed so names stand out
+fn get_connectors(depth: usize, opts: &DisplayOptions) -> ColoredString {
+    // This is display indentation, could be replaced with something prettier
+    let connectors = "| ".repeat(depth);
+    if opts.rainbow {
+        connectors.color(DEPTH_COLORS[depth % DEPTH_COLORS.len()])
+    } else if opts.connector_color {
+        opts.theme.connectors.paint(connectors)
+    } else {
+        connectors.normal()
+    }
+}
+
+/// A line of the tree view, see `Node::tree_lines`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreeLine<'a> {
+    /// How deep in the tree it is, the root is 0
+    pub depth: usize,
+    pub node: &'a Node,
+    /// Percentage of the parent's size, none for the root
+    pub percent: Option<f64>,
+}
+
+/// Iterator over lines of the tree view, see `Node::tree_lines`
+#[derive(Debug, Clone)]
+pub struct TreeLines<'a> {
+    /// Lines still to go, the next one is last
+    stack: Vec<TreeLine<'a>>,
+    /// Paths of nodes that pass the threshold, if there is one
+    passing: Option<HashSet<&'a Path>>,
+}
+
+impl<'a> Iterator for TreeLines<'a> {
+    type Item = TreeLine<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.stack.pop()?;
+        let parent = line.node;
+        let children = parent.children.iter().rev().filter(|c| {
+            self.passing
+                .as_ref()
+                .is_none_or(|passing| passing.contains(c.path.as_path()))
+        });
+        for child in children {
+            let percent = match parent.size {
+                0 => 100.0,
+                size => child.size as f64 / size as f64 * 100.0,
+            };
+            self.stack.push(TreeLine {
+                depth: line.depth + 1,
+                node: child,
+                percent: Some(percent),
+            });
+        }
+        Some(line)
+    }
+}
+
+/// Which entries `Node::list_entries` returns, like the options of the list view
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    /// Skip directories
+    pub only_files: bool,
+    /// Regex filters for paths
+    pub paths: PathFilter,
+    /// Only sizes between these (inclusive)
+    pub size_range: Option<(u64, u64)>,
+}
+
+/// Regex filters for paths shown in the list view
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    /// Only paths matching this are shown
+    pub include: Option<Regex>,
+    /// Paths matching this are hidden
+    pub exclude: Option<Regex>,
+}
+
+impl PathFilter {
+    /// Checks whether given path passes both filters
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        if let Some(include) = &self.include {
+            if !include.is_match(&path) {
+                return false;
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            if exclude.is_match(&path) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Checks whether any of the globs matches name or whole path of the entry
+fn any_glob_matches(patterns: &[glob::Pattern], path: &Path) -> bool {
+    patterns.iter().any(|pattern| {
+        path.file_name()
+            .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+            || pattern.matches_path(path)
+    })
+}
+
+/// Options controlling how `walk_dir` traverses the filesystem
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// How deep nodes are stored (sizes are always computed for the whole subtree)
+    pub depth: i32,
+    /// Follow symlinks encountered during the walk
+    pub follow_symlinks: bool,
+    /// Follow the walked path if it's a symlink itself
+    pub dereference_args: bool,
+    /// Measure entries in allocated 512-byte blocks instead of bytes
+    pub blocks: bool,
+    /// How many symlinks can be followed along a single path, so cycles can't go on forever
+    pub max_symlink_depth: usize,
+    /// Entries matching any of these globs are skipped, and not counted in sizes
+    pub exclude: Vec<glob::Pattern>,
+    /// Which time of entries to store, if any
+    pub time: Option<TimeKind>,
+    /// Store apparent and allocated sizes of entries
+    pub sparse: bool,
+    /// Don't print warnings to stderr
+    pub quiet: bool,
+    /// Mark dirs that are on another device than their parent (Unix)
+    pub mark_mounts: bool,
+    /// Follow only symlinks whose target is inside of this (canonicalized) dir,
+    /// unless `follow_symlinks` follows all of them anyway
+    pub follow_under: Option<PathBuf>,
+    /// Skip dirs modified less than this long ago, and don't count them in sizes
+    pub skip_recent: Option<std::time::Duration>,
+    /// Stop walking once this is set (e.g. by `timeout_flag`), leaving out entries not reached yet
+    pub abort: Option<Arc<AtomicBool>>,
+}
+
+/// Which of the times of an entry to show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeKind {
+    Modified,
+    Accessed,
+    /// Time of the last status change (ctime), e.g. of permissions or owner.
+    /// Creation time on platforms without it, like Windows.
+    Changed,
+}
+
+impl WalkOptions {
+    /// Whether the entry should be skipped, globs are matched against its name and whole path
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        any_glob_matches(&self.exclude, path)
+    }
+
+    /// Whether the walk should stop, see `abort`
+    pub fn aborted(&self) -> bool {
+        self.abort
+            .as_ref()
+            .is_some_and(|abort| abort.load(Ordering::Relaxed))
+    }
+
+    /// Whether the dir with this metadata was modified too recently to be walked (`skip_recent`)
+    pub fn is_recent(&self, metadata: &std::fs::Metadata) -> bool {
+        self.skip_recent.is_some_and(|age| {
+            metadata
+                .modified()
+                .is_ok_and(|mtime| mtime.elapsed().map_or(true, |elapsed| elapsed < age))
+        })
+    }
+
+    /// Whether the symlink at `path` should be followed
+    pub fn follows(&self, path: &Path) -> bool {
+        self.follow_symlinks
+            || self.follow_under.as_ref().is_some_and(|dir| {
+                path.canonicalize()
+                    .is_ok_and(|target| target.starts_with(dir))
+            })
+    }
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            depth: i32::MAX,
+            follow_symlinks: false,
+            dereference_args: false,
+            blocks: false,
+            max_symlink_depth: 40,
+            exclude: vec![],
+            time: None,
+            sparse: false,
+            quiet: false,
+            mark_mounts: false,
+            follow_under: None,
+            skip_recent: None,
+            abort: None,
+        }
+    }
+}
+
+/// Gets a flag that's set once `timeout` passes, for `WalkOptions::abort`
+pub fn timeout_flag(timeout: std::time::Duration) -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let timer_flag = Arc::clone(&flag);
+    // Just ends with the process if the walk finishes first
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        timer_flag.store(true, Ordering::Relaxed);
+    });
+    flag
+}
+
+/// Gets the size of a single entry from its metadata, in the unit chosen by `opts`
+pub fn entry_size(metadata: &std::fs::Metadata, opts: &WalkOptions) -> u64 {
+    if opts.blocks {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            metadata.blocks()
+        }
+        // No block info outside of unix, so approximate it from the length
+        #[cfg(not(unix))]
+        {
+            metadata.len().div_ceil(512)
+        }
+    } else {
+        metadata.len()
+    }
+}
+
+/// Gets apparent size (length) and allocated size (blocks on disk) of a file, both in bytes.
+/// Sparse files have less allocated than apparent.
+#[cfg(unix)]
+pub fn sparse_sizes(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.len(), metadata.blocks() * 512))
+}
+
+/// There's no block info outside of unix, so nothing to compare
+#[cfg(not(unix))]
+pub fn sparse_sizes(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Checks whether a dir is a mount point, i.e. on another device than its parent
+#[cfg(unix)]
+pub fn is_mount_point(child_meta: &std::fs::Metadata, parent_dev: u64) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    child_meta.dev() != parent_dev
+}
+
+/// Checks whether the dir at `path` is on another device than its parent
+#[cfg(unix)]
+fn on_other_device(path: &Path, parent_meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    path.metadata()
+        .is_ok_and(|m| is_mount_point(&m, parent_meta.dev()))
+}
+
+/// Devices aren't known outside of unix
+#[cfg(not(unix))]
+fn on_other_device(_path: &Path, _parent_meta: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Walks a directory recursively, creating nodes along the way
+/// A symlink given as `path` is only followed if `dereference_args` or `follow_symlinks` is set
+pub fn walk_dir(path: &Path, opts: &WalkOptions) -> Node {
+    if let Some(node) = unfollowed_root_link(path, opts) {
+        return node;
+    }
+    walk(path, opts.depth, 0, opts, &|_, _, _| {}, None)
+}
+
+/// Walks a directory like `walk_dir`, but without building the tree.
+/// Instead, `callback` is called with path, size and whether it's a dir, for every entry
+/// as soon as it's sized (dirs after everything inside of them). Returns the total size.
+/// Walking is parallel, so `callback` may be called from multiple threads at once.
+pub fn walk_dir_streaming(
+    path: &Path,
+    opts: &WalkOptions,
+    callback: impl Fn(&Path, u64, bool) + Sync,
+) -> u64 {
+    if let Some(node) = unfollowed_root_link(path, opts) {
+        callback(&node.path, node.size, false);
+        return node.size;
+    }
+    // Depth of 0 so no nodes are kept
+    walk(path, 0, 0, opts, &callback, None).size
+}
+
+/// Walks a directory and writes its tree at the same time, like `walk_dir` followed by
+/// `Node::write_tree`, but only entries along the current path (and their siblings) are kept.
+/// Each level is sized by walking everything below it again, so walking takes longer
+/// the deeper the tree is. Entries are in order of names as `collation` says,
+/// or unsorted if it's none, since sorting by size would need the whole tree.
+/// Returns the root, without children.
+pub fn write_tree_low_memory<W: Write>(
+    w: &mut W,
+    path: &Path,
+    size_threshold: Option<u64>,
+    walk_opts: &WalkOptions,
+    display_opts: &DisplayOptions,
+    collation: Option<Collation>,
+) -> io::Result<Node> {
+    let tree = LowMemoryTree {
+        size_threshold,
+        walk_opts,
+        display_opts,
+        collation,
+    };
+    let level_opts = WalkOptions {
+        depth: walk_opts.depth.min(1),
+        ..walk_opts.clone()
+    };
+    let mut root = walk_dir(path, &level_opts);
+    if let Some(collation) = collation {
+        root.sort_by_name_with(collation);
+    }
+    let own;
+    let line_node = if display_opts.root_own_size {
+        own = Node {
+            size: root.get_own_size(),
+            ..root.clone_childless()
+        };
+        &own
+    } else {
+        &root
+    };
+    let line = match &display_opts.root_label {
+        Some(label) => {
+            let label = display_opts.theme.dir.paint(label.as_str());
+            line_node.get_as_string_line_named(label, None, display_opts)
+        }
+        None => line_node.get_as_string_line(true, None, display_opts),
+    };
+    writeln!(w, "{}{}", get_connectors(0, display_opts), line)?;
+    tree.write_children(w, &root, 0, walk_opts.depth)?;
+    root.children.clear();
+    Ok(root)
+}
+
+/// Options of `write_tree_low_memory`, passed down the levels
+struct LowMemoryTree<'a> {
+    size_threshold: Option<u64>,
+    walk_opts: &'a WalkOptions,
+    display_opts: &'a DisplayOptions,
+    collation: Option<Collation>,
+}
+
+impl LowMemoryTree<'_> {
+    /// Writes lines of children of `level`, which only has its direct children,
+    /// walking each dir again for its own children before moving on to the next one.
+    /// `remaining` is how many levels below `level` are still shown.
+    fn write_children<W: Write>(
+        &self,
+        w: &mut W,
+        level: &Node,
+        depth: usize,
+        remaining: i32,
+    ) -> io::Result<()> {
+        let opts = self.display_opts;
+        let shown: Vec<_> = level
+            .children
+            .iter()
+            .filter(|c| c.passes_threshold(self.size_threshold))
+            .collect();
+        let max_children = opts.max_children.unwrap_or(usize::MAX);
+        let parent_size = Some(level.get_metric(opts.pct_metric));
+        for child in shown.iter().take(max_children) {
+            // Walked before its line is written, so e.g. its balance can be shown
+            let expanded = (child.is_dir && remaining > 1).then(|| {
+                let mut next = walk(&child.path, 1, 0, self.walk_opts, &|_, _, _| {}, None);
+                next.mount_point = child.mount_point;
+                if let Some(collation) = self.collation {
+                    next.sort_by_name_with(collation);
+                }
+                next
+            });
+            let node = expanded.as_ref().unwrap_or(child);
+            let line = node.get_as_string_line(false, parent_size, opts);
+            writeln!(w, "{}{}", get_connectors(depth + 1, opts), line)?;
+            if let Some(next) = expanded.filter(|n| opts.expands(n)) {
+                self.write_children(w, &next, depth + 1, remaining - 1)?;
+            }
+        }
+        if shown.len() > max_children {
+            // Summarize the rest, same as `Node::write_tree`
+            let rest = &shown[max_children..];
+            let rest_size = rest.iter().map(|c| c.size).sum::<u64>();
+            let rest_size = format_size(rest_size, &opts.format_options());
+            writeln!(
+                w,
+                "{}... and {} more (total {})",
+                get_connectors(depth + 1, opts),
+                rest.len(),
+                rest_size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Gets node for the link itself, if `path` is a symlink that shouldn't be followed
+pub(crate) fn unfollowed_root_link(path: &Path, opts: &WalkOptions) -> Option<Node> {
+    let metadata = path.symlink_metadata().ok()?;
+    if metadata.file_type().is_symlink() && !(opts.dereference_args || opts.follows(path)) {
+        // Report the link itself
+        let mut node = Node::new(path.to_path_buf(), entry_size(&metadata, opts), vec![]);
+        node.is_dir = false;
+        node.file_count = 1;
+        Some(node)
+    } else {
+        None
+    }
+}
+
+/// Called with path, size and whether it's a dir, for each walked entry
+type EntryCallback<'a> = &'a (dyn Fn(&Path, u64, bool) + Sync);
+
+/// `symlinks` is how many symlinks were followed to get to `path`.
+/// Unchanged dirs are taken from `cache` instead of walked, if there's one.
+pub(crate) fn walk(
+    path: &Path,
+    depth: i32,
+    symlinks: usize,
+    opts: &WalkOptions,
+    callback: EntryCallback,
+    cache: Option<&cache::CacheState>,
+) -> Node {
+    let mut nodes: Vec<Node> = vec![];
+
+    let metadata = path.metadata();
+    // Only dirs that are stored in the tree can be cached
+    if let (Some(cache), Ok(m), true) = (cache, &metadata, depth >= 0) {
+        if let Some(node) = cache.reuse(path, m) {
+            return node;
+        }
+        cache.record(path, m);
+    }
+    let mut total_size = metadata.as_ref().map(|m| entry_size(m, opts)).unwrap_or(0);
+    let mut total_count = 0;
+    let mut total_dirs = 0;
+    let mut total_sparse = metadata
+        .as_ref()
+        .ok()
+        .filter(|_| opts.sparse)
+        .and_then(sparse_sizes);
+
+    if let Ok(entries) = path.read_dir() {
+        let parent_meta = &metadata;
+        // Walk over children
+        let (children, sizes): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .par_bridge()
+            .filter_map(|entry| {
+                if opts.aborted() {
+                    return None;
+                }
+                let mut node = None;
+                let mut size = None;
+                if let Ok(ref entry) = entry {
+                    if opts.is_excluded(&entry.path()) {
+                        return None;
+                    }
+                    if let Ok(mut file_type) = entry.file_type() {
+                        let mut metadata = None;
+                        let mut symlinks = symlinks;
+                        if file_type.is_symlink() && opts.follows(&entry.path()) {
+                            if symlinks >= opts.max_symlink_depth {
+                                note(
+                                    opts.quiet,
+                                    format_args!(
+                                        "Warning: not following {}, already followed {} symlinks",
+                                        entry.path().display(),
+                                        symlinks
+                                    ),
+                                );
+                            }
+                            // Look at whatever the link points to instead
+                            else if let Ok(m) = entry.path().metadata() {
+                                file_type = m.file_type();
+                                metadata = Some(m);
+                                symlinks += 1;
+                            }
+                        }
+                        if file_type.is_dir() && opts.skip_recent.is_some() {
+                            let recent = match &metadata {
+                                Some(m) => opts.is_recent(m),
+                                None => entry.metadata().is_ok_and(|m| opts.is_recent(&m)),
+                            };
+                            if recent {
+                                return None;
+                            }
+                        }
+                        if file_type.is_dir() {
+                            // Walk this dir recursively
+                            let mut node_temp =
+                                walk(&entry.path(), depth - 1, symlinks, opts, callback, cache);
+                            if let (true, Ok(parent_meta)) = (opts.mark_mounts, &parent_meta) {
+                                node_temp.mount_point = on_other_device(&entry.path(), parent_meta);
+                            }
+                            size = Some((
+                                node_temp.size,
+                                node_temp.file_count,
+                                node_temp.dirs_with_self(),
+                                node_temp.sparse_sizes,
+                            ));
+                            if depth > 0 {
+                                // If not too deep, store it
+                                node = Some(node_temp);
+                            }
+                        } else if file_type.is_file() {
+                            // Get size for this file
+                            let metadata = metadata.map(Ok).unwrap_or_else(|| entry.metadata());
+                            let size_temp =
+                                metadata.as_ref().map(|m| entry_size(m, opts)).unwrap_or(0);
+                            let sparse = metadata
+                                .as_ref()
+                                .ok()
+                                .filter(|_| opts.sparse)
+                                .and_then(sparse_sizes);
+                            size = Some((size_temp, 1, 0, sparse));
+                            callback(&entry.path(), size_temp, false);
+                            if depth > 0 {
+                                // If not too deep, store it
+                                let mut node_temp = Node::new(entry.path(), size_temp, vec![]);
+                                if let Ok(m) = &metadata {
+                                    node_temp.set_ownership(m);
+                                    node_temp.set_time(m, opts.time);
+                                }
+                                node_temp.sparse_sizes = sparse;
+                                node = Some(node_temp);
+                            }
+                        }
+                    }
+                };
+                if node.is_some() || size.is_some() {
+                    // Store the results
+                    Some((node, size))
+                } else {
+                    // Filter out if both are none
+                    None
+                }
+            })
+            .unzip(); // Vec of tuples to tuple of vecs
+
+        // Append all new children
+        children.into_iter().flatten().for_each(|child| {
+            nodes.push(child);
+        });
+        // Add up all sizes, file and dir counts of children
+        let sizes: Vec<_> = sizes.into_iter().flatten().collect();
+        total_size += sizes.par_iter().map(|s| s.0).sum::<u64>();
+        total_count = sizes.par_iter().map(|s| s.1).sum::<u64>();
+        total_dirs = sizes.par_iter().map(|s| s.2).sum::<u64>();
+        if let Some((apparent, allocated)) = &mut total_sparse {
+            for (child_apparent, child_allocated) in sizes.iter().filter_map(|s| s.3) {
+                *apparent += child_apparent;
+                *allocated += child_allocated;
+            }
+        }
+    };
+    callback(path, total_size, true);
+    let mut node = Node::new(path.to_path_buf(), total_size, nodes);
+    node.file_count = total_count;
+    node.dir_count = total_dirs;
+    node.sparse_sizes = total_sparse;
+    if let Ok(m) = &metadata {
+        node.set_ownership(m);
+        node.set_time(m, opts.time);
+    }
+    node
+}
+
+/// Intermediate tree used when building nodes from a list of paths
+#[derive(Default)]
+struct EntryTree {
+    size: u64,
+    is_dir: bool,
+    children: BTreeMap<OsString, EntryTree>,
+}
+
+impl EntryTree {
+    fn insert(&mut self, path: &Path, size: u64, is_dir: bool) {
+        let mut current = self;
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                current.is_dir = true;
+                current = current.children.entry(name.to_owned()).or_default();
+            }
+        }
+        current.size = size;
+        current.is_dir |= is_dir;
+    }
+
+    fn into_node(self, path: PathBuf, depth: i32) -> Node {
+        let children: Vec<Node> = self
+            .children
+            .into_iter()
+            .map(|(name, child)| child.into_node(path.join(name), depth - 1))
+            .collect();
+        let size = self.size + children.iter().map(|c| c.size).sum::<u64>();
+        let is_dir = self.is_dir;
+        let file_count = u64::from(!is_dir) + children.iter().map(|c| c.file_count).sum::<u64>();
+        let dir_count = children.iter().map(Node::dirs_with_self).sum();
+        // Only store children if not too deep, same as `walk_dir`
+        let children = if depth > 0 { children } else { vec![] };
+        let mut node = Node::new(path, size, children);
+        node.is_dir = is_dir;
+        node.file_count = file_count;
+        node.dir_count = dir_count;
+        node
+    }
+}
+
+/// Builds a tree of nodes rooted at `root` from a list of (relative path, size, is_dir),
+/// without touching the filesystem.
+/// Directories missing from the list are synthesized from path prefixes.
+pub fn tree_from_entries(root: &Path, entries: Vec<(PathBuf, u64, bool)>, depth: i32) -> Node {
+    let mut tree = EntryTree {
+        is_dir: true,
+        ..Default::default()
+    };
+    for (path, size, is_dir) in entries {
+        tree.insert(&path, size, is_dir);
+    }
+    tree.into_node(root.to_path_buf(), depth)
+}
+
+pub struct AppSettings {
+    pub path: PathBuf,
+    pub depth: i32,
+    pub sort: bool,
+    pub reverse: bool,
+    pub list: bool,
+    pub machine: bool,
+    pub only_files: bool,
+    pub threshold: Option<u64>,
+    pub blocks: bool,
+    pub path_filter: PathFilter,
+    pub follow_symlinks: bool,
+    pub follow_under: Option<PathBuf>,
+    pub skip_recent: Option<std::time::Duration>,
+    /// Stop walking after this long and show what was walked until then
+    pub timeout: Option<std::time::Duration>,
+    pub dereference_args: bool,
+    pub delimiter: String,
+    #[cfg(feature = "archive")]
+    pub archive: bool,
+    pub precision: usize,
+    pub per_child: bool,
+    pub check: bool,
+    pub unsorted: bool,
+    pub list_percent: bool,
+    pub iec_input: bool,
+    /// Where to save the scan as a snapshot
+    pub save: Option<PathBuf>,
+    /// Snapshot to compare the scan against
+    pub diff: Option<PathBuf>,
+    pub prune_below: Option<u64>,
+    pub prune_depth: Option<usize>,
+    pub no_summary: bool,
+    pub cumulative: Option<f64>,
+    pub deepest: Option<usize>,
+    pub no_connector_color: bool,
+    pub max_symlink_depth: usize,
+    pub exclude: Vec<glob::Pattern>,
+    /// Show only branches of the tree matching any of these globs, and their ancestors
+    pub only_paths: Vec<glob::Pattern>,
+    pub long: bool,
+    pub raw_names: bool,
+    pub watch: Option<std::time::Duration>,
+    pub bars: bool,
+    pub no_percent: bool,
+    pub pct_metric: PctMetric,
+    pub exclude_empty: bool,
+    pub no_total_in_root: bool,
+    pub max_children: Option<usize>,
+    pub format: Option<String>,
+    pub show_time: bool,
+    pub age: bool,
+    pub time: TimeKind,
+    pub dedupe_dirs: bool,
+    pub sparse: bool,
+    pub ndjson: bool,
+    /// Directory to compare the scanned path against, as if it were a snapshot of it
+    pub compare: Option<PathBuf>,
+    pub percent_only: bool,
+    pub table: bool,
+    pub markdown: bool,
+    /// Sort only children of the root by size, leaving deeper levels in the OS order
+    pub sort_top_level_only: bool,
+    pub histogram: bool,
+    pub by_owner: bool,
+    pub by_level: bool,
+    #[cfg(feature = "dupes")]
+    pub find_dupes: bool,
+    /// Don't print notes and warnings to stderr, only errors
+    pub quiet: bool,
+    pub rainbow: bool,
+    pub strict_threshold: bool,
+    pub group_digits: bool,
+    pub show_both: bool,
+    /// Where walks are cached, to reuse unchanged dirs from the last one
+    pub cache: Option<PathBuf>,
+    /// Shown in place of the path of the root in tree view
+    pub root_label: Option<String>,
+    pub mark_mounts: bool,
+    pub expand_min_size: Option<u64>,
+    pub expand_min_children: Option<usize>,
+    pub ascii_escape: bool,
+    pub sort_key: SortKey,
+    pub size_range: Option<(u64, u64)>,
+    pub json_errors: bool,
+    pub resolve_links: bool,
+    pub max_width: Option<usize>,
+    pub highlight_largest: bool,
+    pub order: ListOrder,
+    pub icons: bool,
+    pub allow_delete: bool,
+    pub balance: bool,
+    pub baseline: Option<PathBuf>,
+    pub count_only: bool,
+    pub collation: Collation,
+    pub git_summary: bool,
+    pub low_memory: bool,
+    pub from_manifest: Option<PathBuf>,
+    pub compact: bool,
+    pub dot: Option<PathBuf>,
+    pub theme: theme::Theme,
+    pub show_rank: bool,
+}
+
+impl AppSettings {
+    /// Gets options for `walk_dir` from these settings
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            depth: self.depth,
+            blocks: self.blocks,
+            follow_symlinks: self.follow_symlinks,
+            follow_under: self.follow_under.clone(),
+            skip_recent: self.skip_recent,
+            dereference_args: self.dereference_args,
+            max_symlink_depth: self.max_symlink_depth,
+            exclude: self.exclude.clone(),
+            time: self.show_time.then_some(self.time),
+            sparse: self.sparse || self.show_both,
+            quiet: self.quiet,
+            mark_mounts: self.mark_mounts,
+            // Timer of `timeout` starts with each walk, see `scan_path`
+            abort: None,
+        }
+    }
+
+    /// Checks whether the total line should be printed after the output
+    pub fn show_total_line(&self) -> bool {
+        // Percentages alone are all there is, no sizes
+        self.display_options().machine_readable && !self.no_summary && !self.percent_only
+    }
+
+    /// Converts size in bytes to the unit sizes are measured in (bytes or blocks)
+    pub fn size_in_unit(&self, bytes: u64) -> u64 {
+        if self.blocks {
+            bytes / 512
+        } else {
+            bytes
+        }
+    }
+
+    /// Sorts the tree by size if asked to, otherwise by name unless it should stay unsorted
+    pub fn apply_sort(&self, node: &mut Node) {
+        if self.sort {
+            node.sort_by(self.sort_key);
+        } else if self.sort_top_level_only {
+            node.sort_top_level_by(self.sort_key);
+        } else if !self.unsorted {
+            node.sort_by_name_with(self.collation);
+        }
+    }
+
+    /// Gets options for displaying nodes from these settings
+    pub fn display_options(&self) -> DisplayOptions {
+        DisplayOptions {
+            // Block counts are plain numbers, so show them as such
+            machine_readable: self.machine || self.blocks,
+            delimiter: self.delimiter.clone(),
+            precision: self.precision,
+            list_percent: self.list_percent,
+            connector_color: !self.no_connector_color,
+            long: self.long,
+            age: self.age,
+            raw_names: self.raw_names,
+            bars: self.bars,
+            no_percent: self.no_percent,
+            pct_metric: self.pct_metric,
+            root_own_size: self.no_total_in_root,
+            max_children: self.max_children,
+            format: self.format.clone(),
+            sparse: self.sparse,
+            units: UnitSystem::Si,
+            rainbow: self.rainbow,
+            strict_threshold: self.strict_threshold,
+            digit_separator: self.group_digits.then_some(','),
+            show_both: self.show_both,
+            root_label: self.root_label.clone(),
+            expand_min_size: self.expand_min_size.map(|size| self.size_in_unit(size)),
+            expand_min_children: self.expand_min_children,
+            deltas: HashMap::new(),
+            ascii_escape: self.ascii_escape,
+            size_range: self
+                .size_range
+                .map(|(min, max)| (self.size_in_unit(min), self.size_in_unit(max))),
+            resolve_links: self.resolve_links,
+            max_width: self.max_width,
+            order: self.order,
+            icons: self.icons,
+            balance: self.balance,
+            baseline: HashMap::new(),
+            ranks: HashMap::new(),
+            count_only: self.count_only,
+            compact: self.compact,
+            theme: self.theme,
+        }
+    }
+
+    /// Creates clap app with all of the arguments
+    fn app() -> clap::App<'static, 'static> {
+        let clap_color_setting = if std::env::var_os("NO_COLOR").is_none() {
+            clap::AppSettings::ColoredHelp
+        } else {
+            clap::AppSettings::ColorNever
+        };
+        let app = app_from_crate!()
+            .setting(clap_color_setting)
+            // Config file values are put before the CLI ones, so those override them
+            .setting(clap::AppSettings::AllArgsOverrideSelf)
+            .arg(
+                Arg::with_name("path")
+                    .help("Path to directory to walk. Current directory by default.")
+                    .default_value("."),
+            )
+            .arg(
+                Arg::with_name("depth")
+                    .help("Depth of displayed tree/list")
+                    .long("depth")
+                    .default_value("-1")
+                    .takes_value(true)
+                    .short("d"),
+            )
+            .arg(
+                Arg::with_name("max depth")
+                    .value_name("depth")
+                    .help("Same as --depth, max depth of displayed tree/list (-1 for no limit)")
+                    .long("max-depth")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no recurse")
+                    .help("Show only direct children of the directory (same as --depth 1, sizes still include everything inside)")
+                    .long("no-recurse")
+                    .short("R")
+                    .conflicts_with_all(&["depth", "max depth"]),
+            )
+            .arg(
+                Arg::with_name("sort")
+                    .help("Sort entries by size")
+                    .long("sort")
+                    .short("s"),
+            )
+            .arg(
+                Arg::with_name("sort by")
+                    .value_name("key")
+                    .help("Sort entries by total size, size of the biggest file inside, or number of files and dirs inside (implies --sort)")
+                    .long("sort-by")
+                    .possible_values(&["size", "max-child", "count"])
+                    .conflicts_with("unsorted")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("reverse")
+                    .help("Reverse the output")
+                    .long("reverse")
+                    .short("r"),
+            )
+            .arg(
+                Arg::with_name("list")
+                    .help("Display entries as a list instead of a tree (given twice, same as --long)")
+                    .long("list")
+                    .short("l")
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("long")
+                    .help("Show owner, group and permissions of listed entries")
+                    .long("long")
+                    .requires("list"),
+            )
+            .arg(
+                Arg::with_name("machine")
+                    .help("Display sizes in bytes (\"machine readable\")")
+                    .long("machine-readable")
+                    .short("m"),
+            )
+            .arg(
+                Arg::with_name("group digits")
+                    .help("Separate thousands of sizes in bytes with commas, e.g. 3,233,333")
+                    .long("group-digits"),
+            )
+            .arg(
+                Arg::with_name("only files")
+                    .help("Display only files")
+                    .long("only_files")
+                    .requires("list")
+                    .short("f"),
+            )
+            .arg(
+                Arg::with_name("threshold")
+                    .value_name("thresh")
+                    .help("Only show entries at least this big, or this percentage of the filesystem with a trailing % (in tree view, also dirs with such entries inside)")
+                    .long("threshold")
+                    .takes_value(true)
+                    .short("t"),
+            )
+            .arg(
+                Arg::with_name("size between")
+                    .value_name("min..max")
+                    .help("Only show entries with sizes in this range, e.g. 100MB..1GB (only for list view)")
+                    .long("size-between")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("strict threshold")
+                    .help("In tree view, show only entries leading to files above the threshold, not every big directory")
+                    .long("strict-threshold")
+                    .requires("threshold"),
+            )
+            .arg(
+                Arg::with_name("blocks")
+                    .help("Display allocated 512-byte blocks instead of bytes")
+                    .long("blocks")
+                    .short("b"),
+            )
+            .arg(
+                Arg::with_name("match")
+                    .value_name("regex")
+                    .help("Only show paths matching this regex (only for list view)")
+                    .long("match")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no match")
+                    .value_name("regex")
+                    .help("Hide paths matching this regex (only for list view)")
+                    .long("no-match")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("order")
+                    .value_name("order")
+                    .help("List entries depth-first (each directory followed by its contents, the default), or breadth-first (level by level)")
+                    .long("order")
+                    .possible_values(&["dfs", "bfs"])
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max width")
+                    .value_name("n")
+                    .help("Shorten listed paths in the middle, so lines are at most this many characters long")
+                    .long("max-width")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("resolve links")
+                    .help("List symlinks by the real paths of their targets, to see where data actually is")
+                    .long("resolve-links")
+                    .requires("list"),
+            )
+            .arg(
+                Arg::with_name("follow symlinks")
+                    .help("Follow all symlinks")
+                    .long("follow-symlinks")
+                    .short("L"),
+            )
+            .arg(
+                Arg::with_name("follow under")
+                    .value_name("dir")
+                    .help("Follow only symlinks that point to somewhere inside of the directory")
+                    .long("follow-under")
+                    .conflicts_with("follow symlinks")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("skip recent")
+                    .value_name("seconds")
+                    .help("Skip directories modified less than this many seconds ago (e.g. caches that will change anyway), not counting them in sizes")
+                    .long("skip-recent")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("timeout")
+                    .value_name("seconds")
+                    .help("Stop walking after this many seconds and show the partial tree, e.g. for slow drives")
+                    .long("timeout")
+                    .conflicts_with_all(&["cache", "low memory", "archive", "from manifest"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("dereference args")
+                    .help("Follow the given path if it's a symlink, but no symlinks inside it")
+                    .long("dereference-args")
+                    .short("H"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .value_name("glob")
+                    .help("Skip entries matching this glob (can be given multiple times)")
+                    .long("exclude")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("only path")
+                    .value_name("glob")
+                    .help("Show only entries matching this glob, what's inside of them and their parents, after walking everything (can be given multiple times)")
+                    .long("only-path")
+                    .conflicts_with("low memory")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("exclude from")
+                    .value_name("file")
+                    .help("Skip entries matching any of the globs in this file (one per line)")
+                    .long("exclude-from")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ndjson")
+                    .help("Output every entry as a JSON object on its own line")
+                    .long("ndjson")
+                    .conflicts_with_all(&["list", "per child", "diff"]),
+            )
+            .arg(
+                Arg::with_name("from manifest")
+                    .value_name("file")
+                    .help("Build the tree from lines of <size>\\t<path> in the file instead of walking the path, which is then only the root they are relative to")
+                    .long("from-manifest")
+                    .conflicts_with_all(&["cache", "compare", "watch", "low memory", "git summary", "archive"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("low memory")
+                    .help("Write the tree while walking, keeping only the current path in memory instead of the whole tree (slower, and entries can't be sorted by size)")
+                    .long("low-memory")
+                    .conflicts_with_all(&[
+                        "list", "sort", "sort top level only", "reverse", "per child", "ndjson",
+                        "histogram", "percent only", "cumulative", "strict threshold",
+                        "dedupe dirs", "exclude empty", "prune below", "prune depth",
+                        "highlight largest", "git summary", "count only", "save", "cache",
+                        "compare", "diff", "baseline", "watch", "archive", "only path",
+                    ]),
+            )
+            .arg(
+                Arg::with_name("git summary")
+                    .help("At the end, show sizes of .git, and of files tracked and not tracked by git")
+                    .long("git-summary")
+                    .conflicts_with_all(&["ndjson", "archive"]),
+            )
+            .arg(
+                Arg::with_name("highlight largest")
+                    .help("Point out the largest file found at the end")
+                    .long("highlight-largest")
+                    .conflicts_with("ndjson"),
+            )
+            .arg(
+                Arg::with_name("by level")
+                    .help("Show number of entries and their total size at each depth, instead of the tree")
+                    .long("by-level")
+                    .conflicts_with_all(&[
+                        "list", "per child", "ndjson", "histogram", "percent only", "diff", "compare",
+                        "low memory",
+                    ]),
+            )
+            .arg(
+                Arg::with_name("histogram")
+                    .help("Show how many files there are of each size class (<1KB, <10KB, ..., >=1GB) and how much they take")
+                    .long("histogram")
+                    .conflicts_with_all(&["list", "per child", "diff", "compare", "ndjson", "percent only"]),
+            )
+            .arg(
+                Arg::with_name("percent only")
+                    .help("Output only paths and their percentages of the total size, separated by a tab")
+                    .long("percent-only")
+                    .conflicts_with_all(&["list", "per child", "diff", "compare", "ndjson"]),
+            )
+            .arg(
+                Arg::with_name("table")
+                    .help("Output paths and sizes as a table as wide as the terminal (or 80 characters if output isn't one), with sizes aligned at the right edge")
+                    .long("table")
+                    .conflicts_with_all(&[
+                        "list", "per child", "diff", "compare", "ndjson", "percent only", "histogram",
+                        "by level", "low memory",
+                    ]),
+            )
+            .arg(
+                Arg::with_name("markdown")
+                    .help("Output the list view as a Markdown table, e.g. for pasting into docs or issues")
+                    .long("markdown")
+                    .conflicts_with_all(&[
+                        "per child", "diff", "compare", "ndjson", "percent only", "histogram", "by level",
+                        "low memory", "table", "format",
+                    ]),
+            )
+            .arg(
+                Arg::with_name("show both")
+                    .help("Show both disk (allocated) and apparent size on every line")
+                    .long("show-both"),
+            )
+            .arg(
+                Arg::with_name("sparse")
+                    .help("Show apparent and allocated sizes of files, highlighting sparse ones")
+                    .long("sparse"),
+            )
+            .arg(
+                Arg::with_name("dedupe dirs")
+                    .help("Count directories that look the same (same names and sizes inside) only once")
+                    .long("dedupe-dirs"),
+            )
+            .arg(
+                Arg::with_name("exclude empty")
+                    .help("Hide directories without any files inside")
+                    .long("exclude-empty"),
+            )
+            .arg(
+                Arg::with_name("max symlink depth")
+                    .value_name("n")
+                    .help("Maximum number of symlinks followed along a single path")
+                    .long("max-symlink-depth")
+                    .default_value("40")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("delimiter")
+                    .value_name("delim")
+                    .help("Separate fields of each line with this, e.g. '\\t' (only for list view)")
+                    .long("delimiter")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .value_name("template")
+                    .help("Format each line of the list with a template, e.g. '{size}\\t{path}'. Placeholders: {path}, {name}, {size}, {bytes}, {pct}, {is_dir}")
+                    .long("format")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("precision")
+                    .help("Number of decimal places of displayed sizes (at most 6)")
+                    .long("precision")
+                    .default_value("1")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("per child")
+                    .help(
+                        "Display a separate tree for each child of the directory, without the root",
+                    )
+                    .long("per-child")
+                    .conflicts_with("list"),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .help("Don't print notes and warnings to stderr, only errors")
+                    .long("quiet")
+                    .short("q"),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .help("Only check if the path exists and is readable, without walking it")
+                    .long("check"),
+            )
+            .arg(
+                Arg::with_name("unsorted")
+                    .help(
+                        "Keep entries in the order the OS returns them, instead of sorting by name",
+                    )
+                    .long("unsorted")
+                    .conflicts_with("sort"),
+            )
+            .arg(
+                Arg::with_name("sort top level only")
+                    .help("Sort only the top level entries by size, keeping deeper ones in the order the OS returns them")
+                    .long("sort-top-level-only")
+                    .conflicts_with_all(&["sort", "unsorted"]),
+            )
+            .arg(
+                Arg::with_name("list percent")
+                    .help("Display percentage of parent size (only for list view)")
+                    .long("list-percent")
+                    .requires("list"),
+            )
+            .arg(
+                Arg::with_name("iec input")
+                    .help("Treat units of given sizes as powers of 1024 (1KB = 1024B)")
+                    .long("iec-input"),
+            )
+            .arg(
+                Arg::with_name("save")
+                    .value_name("file")
+                    .help("Save the scan as a JSON snapshot, to compare against later with --diff")
+                    .long("save")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("dot")
+                    .value_name("file")
+                    .help("Also write the tree as a Graphviz DOT graph to the file")
+                    .long("dot")
+                    .conflicts_with("low memory")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("cache")
+                    .value_name("file")
+                    .help("Reuse dirs that weren't modified since the last scan with this cache, and update it (only added, removed or renamed entries change a dir, so files that grew or shrank anywhere and changes deeper inside aren't noticed)")
+                    .long("cache")
+                    .conflicts_with("archive")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("compare")
+                    .value_name("dir")
+                    .help("Compare the given directory against the path, showing entries only in one of them and size differences")
+                    .long("compare")
+                    .conflicts_with_all(&["list", "per child", "diff", "ndjson", "archive"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("diff")
+                    .value_name("file")
+                    .help("Display changes since the snapshot saved with --save")
+                    .long("diff")
+                    .conflicts_with_all(&["list", "per child"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("baseline")
+                    .value_name("file")
+                    .help("Color sizes of entries that grew since the snapshot saved with --save red, and shrunk ones green")
+                    .long("baseline")
+                    .conflicts_with_all(&["diff", "compare"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("prune below")
+                    .value_name("size")
+                    .help("Remove entries smaller than this from the scan result (e.g. before --save)")
+                    .long("prune-below")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("prune depth")
+                    .value_name("levels")
+                    .help("Collapse this many levels from the bottom of the tree into their parents")
+                    .long("prune-depth")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("deepest")
+                    .value_name("n")
+                    .help("Only list the n most deeply nested entries, e.g. to find nested node_modules")
+                    .long("deepest")
+                    .requires("list")
+                    .conflicts_with("cumulative")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("cumulative")
+                    .value_name("percent")
+                    .help("Only list the largest entries, that together make up this percentage of total size")
+                    .long("cumulative")
+                    .requires("list")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no summary")
+                    .help("Don't print the total line at the end of machine readable output")
+                    .long("no-summary"),
+            )
+            .arg(
+                Arg::with_name("watch")
+                    .value_name("seconds")
+                    .help("Scan again every this many seconds and redraw the output")
+                    .long("watch")
+                    .conflicts_with_all(&["save", "check", "dot"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("bars")
+                    .help("Draw a bar showing the percentage of parent size")
+                    .long("bars"),
+            )
+            .arg(
+                Arg::with_name("no percent")
+                    .help("Don't show percentages of parent size in the tree, which is a bit faster for huge trees")
+                    .long("no-percent")
+                    .conflicts_with_all(&["bars", "pct metric", "list percent"]),
+            )
+            .arg(
+                Arg::with_name("pct metric")
+                    .value_name("metric")
+                    .help("Compute percentages from size or number of files")
+                    .long("pct-metric")
+                    .possible_values(&["size", "count"])
+                    .default_value("size")
+                    .takes_value(true),
+            )
+            .arg(
+                // Nothing deletes entries yet, see `delete::can_delete`
+                Arg::with_name("allow delete")
+                    .help("Allow deleting entries")
+                    .long("allow-delete")
+                    .hidden(true),
+            )
+            .arg(
+                Arg::with_name("count only")
+                    .help("Show number of files and dirs inside of each entry instead of its size (percentages and --sort use it too)")
+                    .long("count-only")
+                    .conflicts_with_all(&["show both", "ndjson"]),
+            )
+            .arg(
+                Arg::with_name("show rank")
+                    .help("Show each entry's rank by size among its siblings, e.g. [#2/15] for the 2nd biggest of 15")
+                    .long("show-rank")
+                    .conflicts_with("low memory"),
+            )
+            .arg(
+                Arg::with_name("balance")
+                    .help("Show how evenly size is spread among children of each directory, from 0 (all in one) to 1 (even)")
+                    .long("balance"),
+            )
+            .arg(
+                Arg::with_name("icons")
+                    .help("Show an icon for each entry based on its type (needs a Nerd Font)")
+                    .long("icons")
+                    .conflicts_with("ascii escape"),
+            )
+            .arg(
+                Arg::with_name("ascii escape")
+                    .help("Escape non-ASCII characters in names as \\u{XXXX}, e.g. for legacy terminals or logs")
+                    .long("ascii-escape"),
+            )
+            .arg(
+                Arg::with_name("json errors")
+                    .help("Print errors on stderr as JSON objects with \"error\" and \"kind\", for tools wrapping sofidu")
+                    .long("json-errors"),
+            )
+            .arg(
+                Arg::with_name("raw names")
+                    .help("Don't escape control characters in names (they can mess up the terminal)")
+                    .long("raw-names"),
+            )
+            .arg(
+                Arg::with_name("compact")
+                    .help("Show chains of directories with only one directory inside each as a single line, e.g. a/b/c/")
+                    .long("compact")
+                    .conflicts_with("low memory"),
+            )
+            .arg(
+                Arg::with_name("max children")
+                    .value_name("n")
+                    .help("Show only first n children (largest ones, when sorted) of each directory in tree")
+                    .long("max-children")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("expand min size")
+                    .value_name("size")
+                    .help("Show contents only of directories at least this big in tree (or with enough children, with --expand-min-children), others are collapsed into one line")
+                    .long("expand-min-size")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("expand min children")
+                    .value_name("n")
+                    .help("Show contents only of directories with at least n children in tree (or big enough, with --expand-min-size), others are collapsed into one line")
+                    .long("expand-min-children")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no total in root")
+                    .help("Show only the root's own size on its tree line, instead of the total")
+                    .long("no-total-in-root"),
+            )
+            .arg(
+                Arg::with_name("mark mounts")
+                    .help("Mark directories that are mount points with [mount] (Unix)")
+                    .long("mark-mounts"),
+            )
+            .arg(
+                Arg::with_name("root label")
+                    .value_name("label")
+                    .help("Show this instead of the path on the first line of the tree, e.g. \"Backup Drive\"")
+                    .long("root-label")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("theme")
+                    .value_name("theme")
+                    .help("Colors of the output: default, mono (none at all) or solarized")
+                    .long("theme")
+                    .possible_values(&theme::Theme::NAMES)
+                    .default_value("default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rainbow")
+                    .help("Color the tree indentation by depth, so deep levels are easier to follow")
+                    .long("rainbow")
+                    .alias("depth-color"),
+            )
+            .arg(
+                Arg::with_name("no connector color")
+                    .help("Don't dim the tree indentation")
+                    .long("no-connector-color"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .value_name("file")
+                    .help("Read default options from this file instead of sofidu.toml or ~/.config/sofidu/config.toml")
+                    .long("config")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("no config")
+                    .help("Don't read default options from a config file")
+                    .long("no-config")
+                    .conflicts_with("config"),
+            );
+
+        #[cfg(feature = "archive")]
+        let app = app.arg(
+            Arg::with_name("archive")
+                .help("Read given path as an archive (tar, tar.gz or zip) and show its contents")
+                .long("archive"),
+        );
+
+        #[cfg(feature = "time")]
+        let app = app
+            .arg(
+                Arg::with_name("show time")
+                    .help("Show time of each listed entry (modification time, unless --time)")
+                    .long("show-time")
+                    .requires("list"),
+            )
+            .arg(
+                Arg::with_name("time")
+                    .value_name("kind")
+                    .help("Which time to show: modification, access or status change (creation on Windows)")
+                    .long("time")
+                    .possible_values(&["mtime", "atime", "ctime"])
+                    .requires("show time")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("age")
+                    .help("Show how long ago each listed entry was modified, e.g. '3d ago', instead of dates")
+                    .long("age")
+                    .requires("list"),
+            );
+
+        #[cfg(unix)]
+        let app = app.arg(
+            Arg::with_name("by owner")
+                .help("Show total size and number of files of each owner, instead of the tree")
+                .long("by-owner")
+                .conflicts_with_all(&[
+                    "list",
+                    "per child",
+                    "ndjson",
+                    "histogram",
+                    "percent only",
+                    "diff",
+                    "compare",
+                    "low memory",
+                    "by level",
+                ]),
+        );
+
+        #[cfg(feature = "dupes")]
+        let app = app.arg(
+            Arg::with_name("find dupes")
+                .help("Show sets of files with identical contents and how much space they waste, instead of the tree")
+                .long("find-dupes")
+                .conflicts_with_all(&[
+                    "list", "per child", "ndjson", "histogram", "percent only", "diff", "compare",
+                    "low memory", "by level", "archive", "from manifest",
+                ]),
+        );
+
+        #[cfg(feature = "collation")]
+        let app = app.arg(
+            Arg::with_name("collation")
+                .value_name("collation")
+                .help("Sort names by bytes, or by Unicode rules (e.g. accented letters next to plain ones)")
+                .long("collation")
+                .possible_values(&["byte", "unicode"])
+                .takes_value(true),
+        );
+
+        app
+    }
+
+    /// Parses arguments using clap to AppSettings,
+    /// with defaults from `SOFIDU_*` environment variables and the config file
+    pub fn from_args(args: Vec<String>) -> Self {
+        let env = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+        Self::from_args_with_env(args, env_to_args(env), true)
+    }
+
+    /// Gets matches of the arguments, exiting on clap's errors like `get_matches_from`,
+    /// but with them as JSON (see `exit_with_error`) if `--json-errors` is among them
+    fn get_matches(args: Vec<String>) -> clap::ArgMatches<'static> {
+        let json_errors = args.iter().any(|a| a == "--json-errors");
+        match Self::app().get_matches_from_safe(args) {
+            Ok(matches) => matches,
+            // Help and version aren't errors, they're still printed as usual
+            Err(e) if json_errors && e.use_stderr() => exit_with_error(
+                true,
+                "invalid_arguments",
+                format_args!("{}", clap_error_message(&e)),
+            ),
+            Err(e) => e.exit(),
+        }
+    }
+
+    /// Same as `from_args`, but with arguments from environment variables already given,
+    /// and config files only looked for if `discover_config` (otherwise just `--config`).
+    /// CLI arguments win over the environment, and those over the config file,
+    /// even if they conflict.
+    fn from_args_with_env(
+        args: Vec<String>,
+        env_args: Vec<Vec<String>>,
+        discover_config: bool,
+    ) -> Self {
+        let program: Vec<String> = args.iter().take(1).cloned().collect();
+        let cli: Vec<String> = args.into_iter().skip(1).collect();
+        let mut given = without_conflicts(&program, env_args, &cli);
+        given.extend(cli);
+        let with_env: Vec<String> = program.iter().chain(&given).cloned().collect();
+        // Get argument matches
+        let mut matches = Self::get_matches(with_env);
+        let json_errors = matches.is_present("json errors");
+        if let Some(config_path) = config_path(&matches, discover_config) {
+            let config_args = match read_config(&config_path) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            };
+            // Parse again with config values before the others,
+            // they're only defaults, so the given arguments win even over conflicting ones
+            let mut all_args = program.clone();
+            all_args.extend(without_conflicts(&program, config_args, &given));
+            all_args.extend(given);
+            matches = Self::get_matches(all_args);
+        }
+        // Could be set in the config too
+        let json_errors = matches.is_present("json errors");
+        let parse_depth_arg = |input| match parse_depth(input) {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        };
+        let quiet = matches.is_present("quiet");
+        let depth = parse_depth_arg(matches.value_of("depth").unwrap());
+        let explicit_depth = Some(depth).filter(|_| matches.occurrences_of("depth") > 0);
+        let max_depth = matches.value_of("max depth").map(parse_depth_arg);
+        if depths_conflict(explicit_depth, max_depth) {
+            note(quiet, format_args!("Note: --depth and --max-depth differ, using --max-depth (--depth is the old name for it)"));
+        }
+        let depth = if matches.is_present("no recurse") {
+            1
+        } else {
+            max_depth.unwrap_or(depth)
+        };
+        let precision = match parse_precision(matches.value_of("precision").unwrap()) {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        };
+        let path_str = matches.value_of("path").unwrap();
+        // With `--sort-top-level-only` the key applies just to the top level
+        let sort = matches.is_present("sort")
+            || matches.is_present("sort by") && !matches.is_present("sort top level only");
+        let count_only = matches.is_present("count only");
+        let sort_key = match matches.value_of("sort by") {
+            Some("max-child") => SortKey::MaxChild,
+            Some("count") => SortKey::Count,
+            Some(_) => SortKey::Size,
+            None if count_only => SortKey::Count,
+            None => SortKey::Size,
+        };
+        let list = matches.is_present("list");
+        let long = matches.is_present("long") || matches.occurrences_of("list") > 1;
+        let raw_names = matches.is_present("raw names");
+        let ascii_escape = matches.is_present("ascii escape");
+        let icons = matches.is_present("icons");
+        let collation = match matches.value_of("collation") {
+            #[cfg(feature = "collation")]
+            Some("unicode") => Collation::Unicode,
+            _ => Collation::Byte,
+        };
+        let balance = matches.is_present("balance");
+        let allow_delete = matches.is_present("allow delete");
+        let bars = matches.is_present("bars");
+        let no_percent = matches.is_present("no percent");
+        let exclude_empty = matches.is_present("exclude empty");
+        let dedupe_dirs = matches.is_present("dedupe dirs");
+        let sparse = matches.is_present("sparse");
+        let ndjson = matches.is_present("ndjson");
+        let highlight_largest = matches.is_present("highlight largest");
+        let git_summary = matches.is_present("git summary");
+        let low_memory = matches.is_present("low memory");
+        let from_manifest = matches.value_of("from manifest").map(PathBuf::from);
+        let percent_only = matches.is_present("percent only");
+        let table = matches.is_present("table");
+        let markdown = matches.is_present("markdown");
+        let histogram = matches.is_present("histogram");
+        let by_owner = matches.is_present("by owner");
+        let by_level = matches.is_present("by level");
+        #[cfg(feature = "dupes")]
+        let find_dupes = matches.is_present("find dupes");
+        let no_total_in_root = matches.is_present("no total in root");
+        let pct_metric = match matches.value_of("pct metric").unwrap() {
+            "count" => PctMetric::Count,
+            // Percentages of what's shown
+            _ if count_only && matches.occurrences_of("pct metric") == 0 => PctMetric::Entries,
+            _ => PctMetric::Size,
+        };
+        let watch = matches.value_of("watch").map(|a| match parse_interval(a) {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        });
+        let only_files = matches.is_present("only files");
+        let machine = matches.is_present("machine");
+        let reverse = matches.is_present("reverse");
+        let blocks = matches.is_present("blocks");
+        let follow_symlinks = matches.is_present("follow symlinks");
+        // Canonicalized, so targets can be compared against it
+        let follow_under =
+            matches
+                .value_of("follow under")
+                .map(|dir| match Path::new(dir).canonicalize() {
+                    Ok(dir) => dir,
+                    Err(_) => fail(SofiduError::InvalidPath(dir.to_string()), json_errors),
+                });
+        let skip_recent = matches
+            .value_of("skip recent")
+            .map(|a| match parse_interval(a) {
+                Ok(age) => age,
+                Err(m) => fail(m, json_errors),
+            });
+        let timeout = matches
+            .value_of("timeout")
+            .map(|a| match parse_interval(a) {
+                Ok(timeout) => timeout,
+                Err(m) => fail(m, json_errors),
+            });
+        let resolve_links = matches.is_present("resolve links");
+        let order = match matches.value_of("order") {
+            Some("bfs") => ListOrder::BreadthFirst,
+            _ => ListOrder::DepthFirst,
+        };
+        let max_width = matches
+            .value_of("max width")
+            .map(|a| match parse_levels(a) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            });
+        let dereference_args = matches.is_present("dereference args");
+        let archive = matches.is_present("archive");
+        let age = matches.is_present("age");
+        let show_time = matches.is_present("show time") || age;
+        let time = match matches.value_of("time") {
+            Some("atime") => TimeKind::Accessed,
+            Some("ctime") => TimeKind::Changed,
+            _ => TimeKind::Modified,
+        };
+        let per_child = matches.is_present("per child");
+        let unsorted = matches.is_present("unsorted");
+        let sort_top_level_only = matches.is_present("sort top level only");
+        let list_percent = matches.is_present("list percent");
+        let no_summary = matches.is_present("no summary");
+        let no_connector_color = matches.is_present("no connector color");
+        let rainbow = matches.is_present("rainbow");
+        let compact = matches.is_present("compact");
+        let show_rank = matches.is_present("show rank");
+        let theme = matches
+            .value_of("theme")
+            .and_then(theme::Theme::by_name)
+            .unwrap_or_default();
+        let strict_threshold = matches.is_present("strict threshold");
+        let group_digits = matches.is_present("group digits");
+        let show_both = matches.is_present("show both");
+        let save = matches.value_of("save").map(PathBuf::from);
+        let dot = matches.value_of("dot").map(PathBuf::from);
+        let cache = matches.value_of("cache").map(PathBuf::from);
+        let root_label = matches.value_of("root label").map(String::from);
+        let mark_mounts = matches.is_present("mark mounts");
+        let diff = matches.value_of("diff").map(PathBuf::from);
+        let baseline = matches.value_of("baseline").map(PathBuf::from);
+        let compare = matches.value_of("compare").map(PathBuf::from);
+        let delimiter = parse_delimiter(matches.value_of("delimiter").unwrap_or(" "));
+        let iec_input = matches.is_present("iec input");
+        let size_base = if iec_input { 1024 } else { 1000 };
+        let parse_size_arg = |name: &str| {
+            matches
+                .value_of(name)
+                .map(|a| match str_to_file_size_base(a, size_base) {
+                    Ok(v) => v,
+                    Err(m) => fail(m, json_errors),
+                })
+        };
+        let prune_below = parse_size_arg("prune below");
+        let expand_min_size = parse_size_arg("expand min size");
+        let size_range =
+            matches
+                .value_of("size between")
+                .map(|a| match parse_size_range(a, size_base) {
+                    Ok(v) => v,
+                    Err(m) => fail(m, json_errors),
+                });
+        let max_symlink_depth = match parse_levels(matches.value_of("max symlink depth").unwrap()) {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        };
+        let prune_depth = matches
+            .value_of("prune depth")
+            .map(|a| match parse_levels(a) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            });
+        let mut exclude_patterns: Vec<String> = matches
+            .values_of("exclude")
+            .map(|values| values.map(str::to_string).collect())
+            .unwrap_or_default();
+        if let Some(path) = matches.value_of("exclude from") {
+            match read_exclude_from(Path::new(path)) {
+                Ok(patterns) => exclude_patterns.extend(patterns),
+                Err(m) => fail(m, json_errors),
+            }
+        }
+        let exclude = match exclude_patterns
+            .iter()
+            .map(|p| parse_glob(p))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        };
+        let only_paths = match matches
+            .values_of("only path")
+            .into_iter()
+            .flatten()
+            .map(parse_glob)
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        };
+        let format = matches.value_of("format").map(|a| {
+            let format = parse_delimiter(a);
+            if let Err(m) = check_template(&format) {
+                fail(m, json_errors)
+            }
+            format
+        });
+        let max_children = matches
+            .value_of("max children")
+            .map(|a| match parse_levels(a) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            });
+        let expand_min_children =
+            matches
+                .value_of("expand min children")
+                .map(|a| match parse_levels(a) {
+                    Ok(v) => v,
+                    Err(m) => fail(m, json_errors),
+                });
+        let deepest = matches.value_of("deepest").map(|a| match parse_levels(a) {
+            Ok(v) => v,
+            Err(m) => fail(m, json_errors),
+        });
+        let cumulative = matches
+            .value_of("cumulative")
+            .map(|a| match parse_percent(a) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            });
+
+        let parse_regex_arg = |name: &str| {
+            matches.value_of(name).map(|a| match parse_regex(a) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            })
+        };
+        let path_filter = PathFilter {
+            include: parse_regex_arg("match"),
+            exclude: parse_regex_arg("no match"),
+        };
+
+        // Check if path is valid
+        let path = PathBuf::from(path_str);
+        let check = matches.is_present("check");
+        // In check mode validation result is the output, so leave it for later
+        // Path is just the root of the manifest's tree, it doesn't have to exist
+        if !check && from_manifest.is_none() {
+            for path in std::iter::once(&path).chain(&compare) {
+                if let Err(m) = validate_path(path) {
+                    fail(m, json_errors)
+                }
+            }
+        }
+        // Percentages need the filesystem, so only after the path is known to be valid
+        let threshold = matches.value_of("threshold").map(|a| {
+            let fs_size = || {
+                filesystem_size(Path::new(path_str)).map_err(|e| {
+                    SofiduError::UnreadablePath(
+                        path_str.to_string(),
+                        format!("can't get size of its filesystem ({})", e),
+                    )
+                })
+            };
+            match str_to_threshold(a, size_base, fs_size) {
+                Ok(v) => v,
+                Err(m) => fail(m, json_errors),
+            }
+        });
+        if !check && !path.is_dir() && !archive && from_manifest.is_none() {
+            note(
+                quiet,
+                format_args!(
+                    "Warning: {} is not a directory, only its own size will be shown",
+                    path_str
+                ),
+            );
+        }
+
+        Self {
+            path,
+            depth,
+            list,
+            sort,
+            only_files,
+            machine,
+            threshold,
+            reverse,
+            blocks,
+            path_filter,
+            follow_symlinks,
+            follow_under,
+            skip_recent,
+            timeout,
+            dereference_args,
+            delimiter,
+            #[cfg(feature = "archive")]
+            archive,
+            precision,
+            per_child,
+            check,
+            unsorted,
+            list_percent,
+            iec_input,
+            save,
+            diff,
+            prune_below,
+            prune_depth,
+            no_summary,
+            cumulative,
+            deepest,
+            no_connector_color,
+            max_symlink_depth,
+            exclude,
+            only_paths,
+            long,
+            raw_names,
+            watch,
+            bars,
+            no_percent,
+            pct_metric,
+            exclude_empty,
+            no_total_in_root,
+            max_children,
+            format,
+            show_time,
+            age,
+            time,
+            dedupe_dirs,
+            sparse,
+            ndjson,
+            compare,
+            percent_only,
+            table,
+            markdown,
+            sort_top_level_only,
+            histogram,
+            by_owner,
+            by_level,
+            #[cfg(feature = "dupes")]
+            find_dupes,
+            quiet,
+            rainbow,
+            strict_threshold,
+            group_digits,
+            show_both,
+            cache,
+            root_label,
+            mark_mounts,
+            expand_min_size,
+            expand_min_children,
+            ascii_escape,
+            sort_key,
+            size_range,
+            json_errors,
+            resolve_links,
+            max_width,
+            highlight_largest,
+            order,
+            icons,
+            allow_delete,
+            balance,
+            baseline,
+            count_only,
+            collation,
+            git_summary,
+            low_memory,
+            from_manifest,
+            compact,
+            dot,
+            theme,
+            show_rank,
+        }
+    }
+}
+
+/// Errors that can happen when parsing user input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SofiduError {
+    /// Depth isn't an integer
+    InvalidDepth(String),
+    /// Depth is an integer, but smaller than -1
+    DepthOutOfRange,
+    /// File size is negative
+    InvalidSize(String),
+    UnknownUnit(String),
+    /// Numeric part of input couldn't be parsed
+    ParseValue(String),
+    InvalidPrecision(String),
+    InvalidLevels(String),
+    InvalidPercent(String),
+    InvalidInterval(String),
+    /// Size range isn't `min..max` with min not bigger than max (input, reason)
+    InvalidSizeRange(String, String),
+    InvalidRegex(String),
+    InvalidGlob(String),
+    InvalidTemplate(String),
+    /// Path doesn't exist
+    InvalidPath(String),
+    /// Path exists, but can't be read (path, reason)
+    UnreadablePath(String, String),
+    /// Config file can't be read or has invalid contents (path, reason)
+    InvalidConfig(String, String),
+    /// Exclude patterns file can't be read (path, reason)
+    InvalidExcludeFile(String, String),
+    /// Entry isn't allowed to be deleted (path, reason)
+    CantDelete(String, String),
+}
+
+impl std::fmt::Display for SofiduError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidDepth(input) => write!(
+                f,
+                "Invalid depth provided, expected integer value, got '{}'",
+                input
+            ),
+            Self::DepthOutOfRange => write!(f, "Depth must be 0 or greater or -1 for max depth"),
+            Self::InvalidSize(input) => write!(
+                f,
+                "Invalid file size provided, expected non-negative value, got '{}'",
+                input
+            ),
+            Self::UnknownUnit(unit) => write!(
+                f,
+                "Invalid file size unit: {}.\n Supported file size units: B, MB, KB, GB.",
+                unit
+            ),
+            Self::ParseValue(input) => write!(f, "Failed to parse value: {}", input),
+            Self::InvalidPrecision(input) => write!(
+                f,
+                "Invalid precision provided, expected integer value from 0 to {}, got '{}'",
+                MAX_PRECISION, input
+            ),
+            Self::InvalidLevels(input) => write!(
+                f,
+                "Invalid number of levels provided, expected non-negative integer value, got '{}'",
+                input
+            ),
+            Self::InvalidPercent(input) => write!(
+                f,
+                "Invalid percentage provided, expected value between 0 and 100, got '{}'",
+                input
+            ),
+            Self::InvalidInterval(input) => write!(
+                f,
+                "Invalid interval provided, expected positive number of seconds, got '{}'",
+                input
+            ),
+            Self::InvalidSizeRange(input, reason) => write!(
+                f,
+                "Invalid size range provided, expected <min>..<max>, got '{}': {}",
+                input, reason
+            ),
+            Self::InvalidRegex(e) => write!(f, "Invalid regex provided: {}", e),
+            Self::InvalidGlob(e) => write!(f, "Invalid glob provided: {}", e),
+            Self::InvalidTemplate(e) => write!(f, "Invalid format template provided: {}", e),
+            Self::InvalidPath(path) => write!(f, "Invalid path provided: {}", path),
+            Self::UnreadablePath(path, reason) => {
+                write!(f, "Can't read directory {}: {}", path, reason)
+            }
+            Self::InvalidConfig(path, reason) => {
+                write!(f, "Invalid config file {}: {}", path, reason)
+            }
+            Self::InvalidExcludeFile(path, reason) => {
+                write!(f, "Can't read exclude file {}: {}", path, reason)
+            }
+            Self::CantDelete(path, reason) => write!(f, "Can't delete {}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for SofiduError {}
+
+impl SofiduError {
+    /// Gets a short name of what went wrong, for tools reading `--json-errors`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidDepth(_) => "invalid_depth",
+            Self::DepthOutOfRange => "depth_out_of_range",
+            Self::InvalidSize(_) => "invalid_size",
+            Self::UnknownUnit(_) => "unknown_unit",
+            Self::ParseValue(_) => "parse_value",
+            Self::InvalidPrecision(_) => "invalid_precision",
+            Self::InvalidLevels(_) => "invalid_levels",
+            Self::InvalidPercent(_) => "invalid_percent",
+            Self::InvalidInterval(_) => "invalid_interval",
+            Self::InvalidSizeRange(_, _) => "invalid_size_range",
+            Self::InvalidRegex(_) => "invalid_regex",
+            Self::InvalidGlob(_) => "invalid_glob",
+            Self::InvalidTemplate(_) => "invalid_template",
+            Self::InvalidPath(_) => "invalid_path",
+            Self::UnreadablePath(_, _) => "unreadable_path",
+            Self::InvalidConfig(_, _) => "invalid_config",
+            Self::InvalidExcludeFile(_, _) => "invalid_exclude_file",
+            Self::CantDelete(_, _) => "cant_delete",
+        }
+    }
+}
+
+/// Formats an error as a JSON object with `error` (the message) and `kind`
+pub fn error_json(kind: &str, message: std::fmt::Arguments) -> String {
+    serde_json::json!({ "error": message.to_string(), "kind": kind }).to_string()
+}
+
+/// Prints an error and exits. It's printed on stdout as is,
+/// or on stderr as JSON (see `error_json`) if `json_errors` is set.
+pub fn exit_with_error(json_errors: bool, kind: &str, message: std::fmt::Arguments) -> ! {
+    if json_errors {
+        eprintln!("{}", error_json(kind, message));
+    } else {
+        println!("{}", message);
+    }
+    std::process::exit(1)
+}
+
+/// Gets the message of clap's error on one line, without usage and help that follow it
+/// and without the `error: ` and colors in front of it
+fn clap_error_message(error: &clap::Error) -> String {
+    let lines: Vec<_> = strip_escapes(&error.message)
+        .lines()
+        .take_while(|line| !line.starts_with("USAGE:"))
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let message = lines.join(" ");
+    match message.strip_prefix("error: ") {
+        Some(rest) => rest.to_string(),
+        None => message,
+    }
+}
+
+/// Same as `exit_with_error`, for errors of parsing arguments
+fn fail(error: SofiduError, json_errors: bool) -> ! {
+    exit_with_error(json_errors, error.kind(), format_args!("{}", error))
+}
+
+/// Finds config file to read, if any.
+/// Explicit `--config` is used as is, otherwise (if `discover`) first existing of
+/// `sofidu.toml` in current directory and `sofidu/config.toml` in user's config directory.
+fn config_path(matches: &clap::ArgMatches, discover: bool) -> Option<PathBuf> {
+    if matches.is_present("no config") {
+        return None;
+    }
+    if let Some(path) = matches.value_of("config") {
+        return Some(PathBuf::from(path));
+    }
+    if !discover {
+        return None;
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+    std::iter::once(PathBuf::from("sofidu.toml"))
+        .chain(config_dir.map(|d| d.join("sofidu").join("config.toml")))
+        .find(|p| p.is_file())
+}
+
+/// Reads config file into a list of arguments, grouped by option
+fn read_config(path: &Path) -> Result<Vec<Vec<String>>, SofiduError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| SofiduError::InvalidConfig(path.display().to_string(), e.to_string()))?;
+    config_to_args(&contents).map_err(|e| SofiduError::InvalidConfig(path.display().to_string(), e))
+}
+
+/// Long names of options that can be set with `SOFIDU_<OPTION>` environment variables
+const ENV_OPTIONS: [&str; 14] = [
+    "depth",
+    "max-depth",
+    "sort",
+    "reverse",
+    "unsorted",
+    "threshold",
+    "blocks",
+    "iec-input",
+    "precision",
+    "theme",
+    "quiet",
+    "json-errors",
+    "config",
+    "no-config",
+];
+
+/// Turns `SOFIDU_<OPTION>` environment variables into arguments grouped by option,
+/// like `config_to_args`, e.g. `SOFIDU_MAX_DEPTH=3` into `--max-depth 3`.
+/// Only options in `ENV_OPTIONS` are taken, other variables are ignored.
+/// `true` sets flags and `false` leaves them out, other values are passed on.
+fn env_to_args(vars: impl IntoIterator<Item = (String, String)>) -> Vec<Vec<String>> {
+    let mut vars: Vec<_> = vars
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let option = key
+                .strip_prefix("SOFIDU_")?
+                .to_lowercase()
+                .replace('_', "-");
+            ENV_OPTIONS
+                .contains(&option.as_str())
+                .then_some((option, value))
+        })
+        .collect();
+    // Order of the environment isn't fixed
+    vars.sort();
+    let mut args = vec![];
+    for (option, value) in vars {
+        match value.as_str() {
+            "true" => args.push(vec![format!("--{}", option)]),
+            "false" => {}
+            _ => args.push(vec![format!("--{}", option), value]),
+        }
+    }
+    args
+}
+
+/// Reads exclude globs from a file
+fn read_exclude_from(path: &Path) -> Result<Vec<String>, SofiduError> {
+    std::fs::read_to_string(path)
+        .map(|contents| parse_exclude_patterns(&contents))
+        .map_err(|e| SofiduError::InvalidExcludeFile(path.display().to_string(), e.to_string()))
+}
+
+/// Gets globs from newline separated patterns, skipping blank lines and `#` comments
+fn parse_exclude_patterns(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Converts TOML config into a list of arguments, with keys being long names of options,
+/// grouped so each option is together with its value.
+/// `true` turns a flag on, arrays give an option multiple times.
+fn config_to_args(contents: &str) -> Result<Vec<Vec<String>>, String> {
+    let table: toml::value::Table = toml::from_str(contents).map_err(|e| e.to_string())?;
+    let mut args = vec![];
+    for (key, value) in table {
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => args.push(vec![format!("--{}", key)]),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(v) => args.push(vec![format!("--{}", key), v]),
+                toml::Value::Integer(v) => args.push(vec![format!("--{}", key), v.to_string()]),
+                toml::Value::Float(v) => args.push(vec![format!("--{}", key), v.to_string()]),
+                _ => return Err(format!("Unsupported value for '{}'", key)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Leaves out groups of default arguments (an option with its value) that conflict with
+/// the `given` ones, so those just override them instead of making parsing fail
+fn without_conflicts(
+    program: &[String],
+    defaults: Vec<Vec<String>>,
+    given: &[String],
+) -> Vec<String> {
+    defaults
+        .into_iter()
+        .filter(|group| {
+            let args = program.iter().chain(group).chain(given);
+            !matches!(
+                AppSettings::app().get_matches_from_safe(args),
+                Err(e) if e.kind == clap::ErrorKind::ArgumentConflict
+            )
+        })
+        .flatten()
+        .collect()
+}
+
+/// Walks the path (or reads the archive), then prunes and sorts the tree as settings say
+pub fn scan(settings: &AppSettings) -> Node {
+    match &settings.cache {
+        Some(cache_path) => {
+            let node = walk_with_cache(cache_path, settings);
+            transform(node, settings)
+        }
+        None => scan_path(&settings.path, settings),
+    }
+}
+
+/// Same as `scan`, but of another path
+fn scan_path(path: &Path, settings: &AppSettings) -> Node {
+    let node = if let Some(node) = read_archive(path, settings) {
+        node
+    } else if let Some(manifest) = &settings.from_manifest {
+        read_manifest(manifest, path, settings)
+    } else {
+        let mut opts = settings.walk_options();
+        opts.abort = settings.timeout.map(timeout_flag);
+        let node = walk_dir(path, &opts);
+        if opts.aborted() {
+            note(
+                settings.quiet,
+                format_args!(
+                    "Warning: scan of {} timed out, sizes only include what was walked until then",
+                    path.display()
+                ),
+            );
+        }
+        node
+    };
+    transform(node, settings)
+}
+
+/// Walks the path reusing what's unchanged since the walk saved at `cache_path`,
+/// then saves this walk there for the next time
+fn walk_with_cache(cache_path: &Path, settings: &AppSettings) -> Node {
+    let cache = match cache::WalkCache::load(cache_path) {
+        Ok(cache) => cache,
+        // Nothing there yet on the first run
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+        Err(e) => {
+            note(
+                settings.quiet,
+                format_args!("Warning: not using cache {}: {}", cache_path.display(), e),
+            );
+            Default::default()
+        }
+    };
+    let (node, cache) = cache::walk_dir_cached(&settings.path, &settings.walk_options(), &cache);
+    if let Err(e) = cache.save(cache_path) {
+        exit_with_error(
+            settings.json_errors,
+            "cache_save",
+            format_args!("Failed to save cache {}: {}", cache_path.display(), e),
+        );
+    }
+    node
+}
+
+/// Applies the changes to a scanned tree that settings ask for, and sorts it
+fn transform(mut node: Node, settings: &AppSettings) -> Node {
+    if settings.dedupe_dirs {
+        for (original, copy) in node.dedupe_subtrees() {
+            note(
+                settings.quiet,
+                format_args!(
+                    "Note: {} looks like a copy of {}, counting it once",
+                    copy.display(),
+                    original.display()
+                ),
+            );
+        }
+    }
+    if settings.exclude_empty {
+        node.prune_empty();
+    }
+    if let Some(size) = settings.prune_below {
+        node.prune_below(settings.size_in_unit(size));
+    }
+    if let Some(levels) = settings.prune_depth {
+        node.prune_depth(levels);
+    }
+    if !settings.only_paths.is_empty() {
+        node.keep_paths(&settings.only_paths);
+    }
+    settings.apply_sort(&mut node);
+    node
+}
+
+/// Reads the tree from the manifest, exiting if it can't be
+fn read_manifest(manifest: &Path, root: &Path, settings: &AppSettings) -> Node {
+    match manifest::read_manifest(manifest, root, settings.depth) {
+        Ok(node) => node,
+        Err(e) => exit_with_error(
+            settings.json_errors,
+            "manifest_read",
+            format_args!("Failed to read manifest {}: {}", manifest.display(), e),
+        ),
+    }
+}
+
+/// Gets sets of duplicate files as a string, if settings ask for them (`--find-dupes`)
+#[cfg(feature = "dupes")]
+fn get_as_string_duplicates(
+    node: &Node,
+    settings: &AppSettings,
+    opts: &DisplayOptions,
+) -> Option<String> {
+    settings
+        .find_dupes
+        .then(|| dupes::get_as_string_duplicates(node, opts))
+}
+
+#[cfg(not(feature = "dupes"))]
+fn get_as_string_duplicates(
+    _node: &Node,
+    _settings: &AppSettings,
+    _opts: &DisplayOptions,
+) -> Option<String> {
+    None
+}
+
+/// Reads the path as an archive, if settings ask for it (`--archive`)
+#[cfg(feature = "archive")]
+fn read_archive(path: &Path, settings: &AppSettings) -> Option<Node> {
+    if !settings.archive {
+        return None;
+    }
+    match archive::read_archive(path, settings.depth) {
+        Ok(node) => Some(node),
+        Err(e) => exit_with_error(
+            settings.json_errors,
+            "archive_read",
+            format_args!("Failed to read archive {}: {}", path.display(), e),
+        ),
+    }
+}
+
+#[cfg(not(feature = "archive"))]
+fn read_archive(_path: &Path, _settings: &AppSettings) -> Option<Node> {
+    None
+}
+
+/// Loads the snapshot to compare against, if there's one in settings.
+/// With `--compare` the other directory is scanned instead and used as the snapshot.
+pub fn load_diff_snapshot(settings: &AppSettings) -> Option<Node> {
+    if let Some(other) = &settings.compare {
+        return Some(scan_path(other, settings));
+    }
+    settings
+        .diff
+        .as_ref()
+        .map(|path| read_snapshot(path, settings))
+}
+
+/// Writes `value` as JSON to a temporary file next to `path`, then moves it there,
+/// so a failed write doesn't leave a truncated file behind
+pub(crate) fn save_json<T: Serialize>(value: &T, path: &Path) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let result = std::fs::File::create(&temporary).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, value)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => std::fs::rename(&temporary, path),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temporary);
+            Err(e)
+        }
+    }
+}
+
+/// Loads a snapshot saved with `--save`, exiting if it can't be
+fn read_snapshot(path: &Path, settings: &AppSettings) -> Node {
+    match diff::load_snapshot(path) {
+        Ok(node) => node,
+        Err(e) => exit_with_error(
+            settings.json_errors,
+            "snapshot_load",
+            format_args!("Failed to load snapshot {}: {}", path.display(), e),
+        ),
+    }
+}
+
+/// Writes the whole output for the node as settings say, reversed if asked to
+/// and followed by the total line.
+/// Returns whether any entries were written, i.e. not everything was filtered out.
+pub fn render<W: Write>(
+    w: &mut W,
+    node: &Node,
+    snapshot: Option<&Node>,
+    settings: &AppSettings,
+) -> io::Result<bool> {
+    render_with(w, node, snapshot, settings, &settings.display_options())
+}
+
+/// Walks and writes the tree at the same time (`--low-memory`), see `write_tree_low_memory`.
+/// Returns whether any entries were written, like `render`.
+pub fn render_low_memory<W: Write>(w: &mut W, settings: &AppSettings) -> io::Result<bool> {
+    let threshold = settings.threshold.map(|t| settings.size_in_unit(t));
+    let mut tracking = TrackingWriter {
+        inner: &mut *w,
+        written: false,
+    };
+    let root = write_tree_low_memory(
+        &mut tracking,
+        &settings.path,
+        threshold,
+        &settings.walk_options(),
+        &settings.display_options(),
+        (!settings.unsorted).then_some(settings.collation),
+    )?;
+    let any_entries = tracking.written;
+    if settings.show_total_line() {
+        writeln!(w, "{}", root.get_total_line())?;
+    }
+    writeln!(w)?;
+    Ok(any_entries)
+}
+
+/// Same as `render`, but with other display options than the ones from settings
+fn render_with<W: Write>(
+    w: &mut W,
+    node: &Node,
+    snapshot: Option<&Node>,
+    settings: &AppSettings,
+    display_options: &DisplayOptions,
+) -> io::Result<bool> {
+    // Details that need the whole tree
+    let with_tree_details;
+    let display_options = if settings.baseline.is_some() || settings.show_rank {
+        let baseline = settings
+            .baseline
+            .as_ref()
+            .map(|path| diff::size_deltas(&read_snapshot(path, settings), node));
+        with_tree_details = DisplayOptions {
+            baseline: baseline.unwrap_or_default(),
+            ranks: if settings.show_rank {
+                node.sibling_ranks()
+            } else {
+                HashMap::new()
+            },
+            ..display_options.clone()
+        };
+        &with_tree_details
+    } else {
+        display_options
+    };
+    let any_entries;
+    if settings.reverse {
+        let mut output = vec![];
+        write_output(&mut output, node, snapshot, settings, display_options)?;
+        any_entries = !output.is_empty();
+        // Not sure if this can be more concise
+        let output = String::from_utf8_lossy(&output)
+            .lines()
+            .rev()
+            .map(|l| l.to_owned() + "\n")
+            .collect::<String>();
+        write!(w, "{}", output)?;
+    } else {
+        // Stream it straight to the writer
+        let mut tracking = TrackingWriter {
+            inner: &mut *w,
+            written: false,
+        };
+        write_output(&mut tracking, node, snapshot, settings, display_options)?;
+        any_entries = tracking.written;
+    }
+    if settings.show_total_line() && snapshot.is_none() {
+        writeln!(w, "{}", node.get_total_line())?;
+    }
+    if settings.highlight_largest && snapshot.is_none() {
+        if let Some(line) = node.get_largest_file_line(display_options) {
+            writeln!(w, "{}", line)?;
+        }
+    }
+    if settings.git_summary && snapshot.is_none() {
+        match git::git_summary(&settings.path, node, &settings.walk_options()) {
+            Ok(Some(summary)) => {
+                let format_size = |size| {
+                    let size = format_size(size, &display_options.format_options());
+                    display_options.theme.size.paint(size)
+                };
+                writeln!(
+                    w,
+                    "Git: .git {}, tracked {}, untracked {}",
+                    format_size(summary.git_dir),
+                    format_size(summary.tracked),
+                    format_size(summary.untracked)
+                )?;
+            }
+            Ok(None) => note(
+                settings.quiet,
+                format_args!(
+                    "Note: {} isn't in a git repository",
+                    settings.path.display()
+                ),
+            ),
+            Err(e) => note(
+                settings.quiet,
+                format_args!("Warning: can't read git index: {}", e),
+            ),
+        }
+    }
+    // Every line of ndjson has to be an object
+    if !settings.ndjson {
+        writeln!(w)?;
+    }
+    Ok(any_entries)
+}
+
+/// Passes writes through, remembering whether anything was written
+struct TrackingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    written: bool,
+}
+
+impl<W: Write> Write for TrackingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written |= n > 0;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the node in the display mode chosen in settings,
+/// or its changes if there's a snapshot to compare against
+fn write_output<W: Write>(
+    w: &mut W,
+    node: &Node,
+    snapshot: Option<&Node>,
+    settings: &AppSettings,
+    display_options: &DisplayOptions,
+) -> io::Result<()> {
+    let threshold = settings.threshold.map(|t| settings.size_in_unit(t));
+
+    if let Some(snapshot) = snapshot {
+        // Display changes
+        for entry in diff::diff_trees(snapshot, node) {
+            writeln!(w, "{}", entry.get_as_string_line(display_options))?;
+        }
+        Ok(())
+    } else if settings.ndjson {
+        // One JSON object per line
+        node.write_ndjson(w)
+    } else if settings.by_owner {
+        // Sizes by owner
+        let names = owners::Names::load();
+        write!(
+            w,
+            "{}",
+            owners::get_as_string_by_owner(node, &names, display_options)
+        )
+    } else if let Some(output) = get_as_string_duplicates(node, settings, display_options) {
+        // Files with the same contents
+        write!(w, "{}", output)
+    } else if settings.by_level {
+        // Totals at each depth
+        write!(w, "{}", node.get_as_string_by_level(display_options))
+    } else if settings.histogram {
+        // Number of files by size
+        write!(w, "{}", node.get_as_string_histogram(display_options))
+    } else if settings.percent_only {
+        // Paths with their share of the total
+        write!(w, "{}", node.get_as_string_percent_only(display_options))
+    } else if settings.table {
+        // Paths and sizes filling the terminal, measured again every time
+        write!(
+            w,
+            "{}",
+            node.get_as_string_table(threshold, table_width(), display_options)
+        )
+    } else if let Some(n) = settings.deepest {
+        // Display deepest entries as list
+        write!(w, "{}", node.get_as_string_deepest(n, display_options))
+    } else if let Some(percent) = settings.cumulative {
+        // Display largest entries as list
+        write!(
+            w,
+            "{}",
+            node.get_as_string_cumulative(percent, display_options)
+        )
+    } else if settings.markdown {
+        // List view as a Markdown table
+        write!(
+            w,
+            "{}",
+            node.get_as_string_markdown(
+                settings.only_files,
+                threshold,
+                &settings.path_filter,
+                display_options,
+            )
+        )
+    } else if settings.list {
+        // Display as list
+        write!(
+            w,
+            "{}",
+            node.get_as_string_list(
+                settings.only_files,
+                threshold,
+                &settings.path_filter,
+                display_options,
+            )
+        )
+    } else if settings.per_child {
+        // Display a tree for each child
+        node.write_per_child(w, threshold, display_options)
+    } else {
+        // Display as tree
+        node.write_tree(w, 0, threshold, None, display_options)
+    }
+}
+
+/// Scans and renders everything into a string, so it can be done repeatedly (e.g. `--watch`)
+pub fn scan_and_render(settings: &AppSettings) -> String {
+    scan_and_render_since(settings, None).1
+}
+
+/// Same as `scan_and_render`, but entries that changed in size since `previous` scan
+/// show by how much. Returns the new scan too, to compare the next one against.
+pub fn scan_and_render_since(settings: &AppSettings, previous: Option<&Node>) -> (Node, String) {
+    let node = scan(settings);
+    let snapshot = load_diff_snapshot(settings);
+    let mut display_options = settings.display_options();
+    if let Some(previous) = previous {
+        display_options.deltas = diff::size_deltas(previous, &node);
+    }
+    let mut output = vec![];
+    render_with(
+        &mut output,
+        &node,
+        snapshot.as_ref(),
+        settings,
+        &display_options,
+    )
+    .expect("Writing to a Vec can't fail");
+    (node, String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Writes a non-essential message (a note or a warning) to `w`, unless `quiet`
+pub fn write_note<W: Write>(
+    w: &mut W,
+    quiet: bool,
+    message: std::fmt::Arguments,
+) -> io::Result<()> {
+    if quiet {
+        return Ok(());
+    }
+    writeln!(w, "{}", message)
+}
+
+/// Prints a non-essential message to stderr, unless `quiet` (`--quiet`)
+pub fn note(quiet: bool, message: std::fmt::Arguments) {
+    // Nothing else to do if stderr is gone
+    let _ = write_note(&mut io::stderr(), quiet, message);
+}
+
+/// Checks if path exists and, if it's a directory, whether its contents can be read
+pub fn validate_path(path: &Path) -> Result<(), SofiduError> {
+    // A deleted working directory can still be looked at (and read as empty),
+    // so check it's there at all when the path is relative to it
+    if path.is_relative() {
+        if let Err(e) = std::env::current_dir() {
+            return Err(SofiduError::UnreadablePath(
+                path.display().to_string(),
+                format!("current directory is gone ({})", e),
+            ));
+        }
+    }
+    let metadata = path
+        .metadata()
+        .map_err(|_| SofiduError::InvalidPath(path.display().to_string()))?;
+    if metadata.is_dir() {
+        if let Err(e) = path.read_dir() {
+            return Err(SofiduError::UnreadablePath(
+                path.display().to_string(),
+                e.to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fills in a line template for the node. Placeholders are
+/// `{path}`, `{name}`, `{size}` (human readable), `{bytes}`, `{pct}` (of parent) and `{is_dir}`,
+/// use `{{` and `}}` for literal braces.
+pub fn expand_template(
+    tmpl: &str,
+    node: &Node,
+    parent_size: Option<u64>,
+    opts: &DisplayOptions,
+) -> Result<String, SofiduError> {
+    let mut output = String::with_capacity(tmpl.len());
+    let mut chars = tmpl.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let rest = chars.as_str();
+                if let Some(rest) = rest.strip_prefix('{') {
+                    output.push('{');
+                    chars = rest.chars();
+                    continue;
+                }
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| SofiduError::InvalidTemplate("unclosed '{'".to_string()))?;
+                let value = match &rest[..end] {
+                    "path" => node.get_display_name(true).into_owned(),
+                    "name" => node.get_display_name(false).into_owned(),
+                    "size" => format_size(
+                        node.size,
+                        &FormatOptions {
+                            machine_readable: false,
+                            ..opts.format_options()
+                        },
+                    ),
+                    "bytes" => node.size.to_string(),
+                    "pct" => match parent_size {
+                        None => "".to_string(),
+                        Some(0) => "100.0%".to_string(),
+                        Some(p) => format_percentage(
+                            node.get_metric(opts.pct_metric) as f32 / p as f32 * 100.0,
+                        ),
+                    },
+                    "is_dir" => node.is_dir.to_string(),
+                    other => {
+                        return Err(SofiduError::InvalidTemplate(format!(
+                            "unknown placeholder '{{{}}}'",
+                            other
+                        )))
+                    }
+                };
+                output += &opts.escape_name(&value);
+                chars = rest[end + 1..].chars();
+            }
+            '}' => {
+                if chars.as_str().starts_with('}') {
+                    chars.next();
+                }
+                output.push('}');
+            }
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+/// Checks if the template can be expanded, so errors show up before walking
+fn check_template(tmpl: &str) -> Result<(), SofiduError> {
+    let node = Node::new(PathBuf::new(), 0, vec![]);
+    expand_template(tmpl, &node, None, &DisplayOptions::default()).map(|_| ())
+}
+
+/// Whether both --depth and --max-depth were given, but don't agree
+fn depths_conflict(depth: Option<i32>, max_depth: Option<i32>) -> bool {
+    matches!((depth, max_depth), (Some(a), Some(b)) if a != b)
+}
+
+/// Parses depth a from str
+fn parse_depth(input: &str) -> Result<i32, SofiduError> {
+    let mut depth = {
+        if let Ok(depth) = input.parse::<i32>() {
+            depth
+        } else {
+            return Err(SofiduError::InvalidDepth(input.to_string()));
+        }
+    };
+    if depth < -1 {
+        return Err(SofiduError::DepthOutOfRange);
+    }
+    if depth == -1 {
+        depth = i32::MAX;
+    }
+    Ok(depth)
+}
+
+/// Parses number of levels from a str
+fn parse_levels(input: &str) -> Result<usize, SofiduError> {
+    input
+        .parse::<usize>()
+        .map_err(|_| SofiduError::InvalidLevels(input.to_string()))
+}
+
+/// Parses percentage (between 0 and 100) from a str
+fn parse_percent(input: &str) -> Result<f64, SofiduError> {
+    input
+        .parse::<f64>()
+        .ok()
+        .filter(|p| (0.0..=100.0).contains(p))
+        .ok_or_else(|| SofiduError::InvalidPercent(input.to_string()))
+}
+
+/// Parses interval in seconds (can be fractional) from a str
+fn parse_interval(input: &str) -> Result<std::time::Duration, SofiduError> {
+    input
+        .parse::<f64>()
+        .ok()
+        .filter(|s| *s > 0.0)
+        .and_then(|s| std::time::Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| SofiduError::InvalidInterval(input.to_string()))
+}
+
+/// Parses range of sizes, as `<min>..<max>` with both bounds like `str_to_file_size_base`
+fn parse_size_range(input: &str, base: u64) -> Result<(u64, u64), SofiduError> {
+    let invalid = |reason: String| SofiduError::InvalidSizeRange(input.to_string(), reason);
+    let (min, max) = input
+        .split_once("..")
+        .ok_or_else(|| invalid("missing '..'".to_string()))?;
+    let min = str_to_file_size_base(min, base).map_err(|e| invalid(e.to_string()))?;
+    let max = str_to_file_size_base(max, base).map_err(|e| invalid(e.to_string()))?;
+    if min > max {
+        return Err(invalid("min is bigger than max".to_string()));
+    }
+    Ok((min, max))
+}
+
+/// Parses glob pattern from a str
+fn parse_glob(input: &str) -> Result<glob::Pattern, SofiduError> {
+    glob::Pattern::new(input).map_err(|e| SofiduError::InvalidGlob(e.to_string()))
+}
+
+/// Parses precision (number of decimal places) from a str
+fn parse_precision(input: &str) -> Result<usize, SofiduError> {
+    input
+        .parse::<usize>()
+        .ok()
+        .filter(|&p| p <= MAX_PRECISION)
+        .ok_or_else(|| SofiduError::InvalidPrecision(input.to_string()))
+}
+
+/// Parses field delimiter from a str, unescaping "\t" into a tab
+fn parse_delimiter(input: &str) -> String {
+    input.replace("\\t", "\t")
+}
+
+/// Compiles a regex from a str
+fn parse_regex(input: &str) -> Result<Regex, SofiduError> {
+    Regex::new(input).map_err(|e| SofiduError::InvalidRegex(e.to_string()))
+}
+
+/// Converts file size in bytes to human readable string (in SI units),
+/// with `precision` decimal places (plain bytes are always whole)
+pub fn file_size_to_str(size: u64, precision: usize) -> String {
+    format_size(
+        size,
+        &FormatOptions {
+            precision,
+            ..Default::default()
+        },
+    )
+}
+
+/// Formats a size the way it's displayed, as set in `opts`
+/// Most decimal places of sizes and percentages, beyond which they'd be just noise
+const MAX_PRECISION: usize = 6;
+
+pub fn format_size(size: u64, opts: &FormatOptions) -> String {
+    if opts.machine_readable {
+        return match opts.digit_separator {
+            Some(separator) => group_digits(size, separator),
+            None => size.to_string(),
+        };
+    }
+    let (base, exp, units) = match opts.units {
+        UnitSystem::Si => (
+            1000f64,
+            (size as f64).log10() as i32 / 3,
+            ["KB", "MB", "GB"],
+        ),
+        UnitSystem::Iec => (
+            1024f64,
+            (size as f64).log2() as i32 / 10,
+            ["KiB", "MiB", "GiB"],
+        ),
+    };
+    // Zero has no logarithm, so it ends up negative
+    match exp.clamp(0, 3) {
+        0 => size.to_string() + "B",
+        exp => format!(
+            "{:.*}{}",
+            opts.precision,
+            size as f64 / base.powi(exp),
+            units[exp as usize - 1]
+        ),
+    }
+}
+
+/// Formats the number with `sep` between each group of three digits, e.g. `3,233,333`
+pub fn group_digits(n: u64, sep: char) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() * 4 / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(sep);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Converts human readable string to number of bytes
+pub fn str_to_file_size(input: &str) -> Result<u64, SofiduError> {
+    str_to_file_size_base(input, 1000)
+}
+
+/// Converts human readable string to number of bytes,
+/// with units being powers of `base` (1000 for SI, 1024 for IEC)
+pub fn str_to_file_size_base(input: &str, base: u64) -> Result<u64, SofiduError> {
+    let value;
+    let mut exponent = 0;
+    let pos = input.find(|c: char| c.is_ascii_alphabetic());
+    if let Some(pos) = pos {
+        let (value_s, unit) = input.split_at(pos);
+        exponent = match unit.to_uppercase().as_str() {
+            "G" | "GB" => 3,
+            "M" | "MB" => 2,
+            "K" | "KB" => 1,
+            "" | "B" => 0,
+            u => return Err(SofiduError::UnknownUnit(u.to_string())),
+        };
+        if let Ok(v) = value_s.parse::<f32>() {
+            value = v;
+        } else {
+            return Err(SofiduError::ParseValue(value_s.to_string()));
+        }
+    } else if let Ok(v) = input.parse::<f32>() {
+        value = v;
+    } else {
+        return Err(SofiduError::ParseValue(input.to_string()));
+    }
+    if value < 0.0 {
+        return Err(SofiduError::InvalidSize(input.to_string()));
+    }
+
+    Ok((value * base.pow(exponent) as f32) as u64)
+}
+
+/// Parses threshold like `str_to_file_size_base`, or as a percentage with a trailing `%`,
+/// of `fs_size` (size of the filesystem the scanned path is on), e.g. `5%`.
+/// `fs_size` is only called for percentages.
+pub fn str_to_threshold(
+    input: &str,
+    base: u64,
+    fs_size: impl FnOnce() -> Result<u64, SofiduError>,
+) -> Result<u64, SofiduError> {
+    match input.strip_suffix('%') {
+        Some(percent) => {
+            let percent = parse_percent(percent)
+                .map_err(|_| SofiduError::InvalidPercent(input.to_string()))?;
+            Ok((fs_size()? as f64 * percent / 100.0) as u64)
+        }
+        None => str_to_file_size_base(input, base),
+    }
+}
+
+/// Gets total size of the filesystem `path` is on, in bytes
+#[cfg(unix)]
+pub fn filesystem_size(path: &Path) -> io::Result<u64> {
+    let stats = nix::sys::statvfs::statvfs(path)?;
+    // Block counts are in units of the fragment size,
+    // types of both are smaller than u64 on some platforms
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stats.blocks()) * u64::from(stats.fragment_size()))
+}
+
+/// Gets total size of the filesystem `path` is on, in bytes
+#[cfg(not(unix))]
+pub fn filesystem_size(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem sizes are only known on Unix",
+    ))
+}
+
+/// Creates an empty directory in the system temp dir, unique to this test
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sofidu_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    /// Parses settings like `AppSettings::from_args`, but without `SOFIDU_*` variables
+    /// and config files other than `--config`, so tests don't depend on where they run
+    fn settings_from(args: Vec<String>) -> AppSettings {
+        AppSettings::from_args_with_env(args, vec![], false)
+    }
+
+    #[test]
+    fn file_size_to_str_test() {
+        assert_eq!("1B", file_size_to_str(1, 1));
+        assert_eq!("999B", file_size_to_str(999, 1));
+        assert_eq!("1.0KB", file_size_to_str(1_000, 1));
+        assert_eq!("2.1KB", file_size_to_str(2_100, 1));
+        assert_eq!("1.0MB", file_size_to_str(1_000_000, 1));
+        assert_eq!("4.2MB", file_size_to_str(4_233_333, 1));
+        assert_eq!("5.0GB", file_size_to_str(5_000_000_000, 1));
+    }
+
+    #[test]
+    fn group_digits_test() {
+        assert_eq!("0", group_digits(0, ','));
+        assert_eq!("999", group_digits(999, ','));
+        assert_eq!("1,000", group_digits(1_000, ','));
+        assert_eq!("3,233,333", group_digits(3_233_333, ','));
+        assert_eq!("3 233 333", group_digits(3_233_333, ' '));
+
+        // Human readable sizes don't change
+        let opts = FormatOptions {
+            digit_separator: Some(','),
+            ..Default::default()
+        };
+        assert_eq!("3.2MB", format_size(3_233_333, &opts));
+        let opts = FormatOptions {
+            machine_readable: true,
+            ..opts
+        };
+        assert_eq!("3,233,333", format_size(3_233_333, &opts));
+    }
+
+    #[test]
+    fn format_size_test() {
+        let format = |units, precision, machine_readable| {
+            format_size(
+                1_234_567,
+                &FormatOptions {
+                    units,
+                    precision,
+                    machine_readable,
+                    digit_separator: None,
+                },
+            )
+        };
+        assert_eq!("1MB", format(UnitSystem::Si, 0, false));
+        assert_eq!("1.23MB", format(UnitSystem::Si, 2, false));
+        assert_eq!("1MiB", format(UnitSystem::Iec, 0, false));
+        assert_eq!("1.18MiB", format(UnitSystem::Iec, 2, false));
+        for units in [UnitSystem::Si, UnitSystem::Iec] {
+            for precision in [0, 2] {
+                assert_eq!("1234567", format(units, precision, true));
+            }
+        }
+        let iec = |size| {
+            format_size(
+                size,
+                &FormatOptions {
+                    units: UnitSystem::Iec,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!("1023B", iec(1_023));
+        assert_eq!("1.0KiB", iec(1_024));
+        assert_eq!("5.0GiB", iec(5 * 1024 * 1024 * 1024));
+        assert_eq!("0B", iec(0));
+        // All decimal places up to the most allowed are exact, even for big sizes
+        let opts = FormatOptions {
+            precision: MAX_PRECISION,
+            ..Default::default()
+        };
+        assert_eq!("123.456789GB", format_size(123_456_789_012, &opts));
+        assert_eq!("1234.567890GB", format_size(1_234_567_890_123, &opts));
+    }
+
+    #[test]
+    fn file_size_to_str_precision_test() {
+        assert_eq!("4MB", file_size_to_str(4_233_333, 0));
+        assert_eq!("4.23MB", file_size_to_str(4_233_333, 2));
+        assert_eq!("999B", file_size_to_str(999, 0));
+        assert_eq!("999B", file_size_to_str(999, 2));
+    }
+
+    #[test]
+    fn str_to_file_size_test() {
+        assert_eq!(1, str_to_file_size("1").unwrap());
+        assert_eq!(1, str_to_file_size("1.1").unwrap());
+        assert_eq!(999, str_to_file_size("999B").unwrap());
+        assert_eq!(1_000, str_to_file_size("1KB").unwrap());
+        assert_eq!(1_000, str_to_file_size("1K").unwrap());
+        assert_eq!(1_000, str_to_file_size("1.0KB").unwrap());
+        assert_eq!(1_000, str_to_file_size("1.0K").unwrap());
+        assert_eq!(4_200, str_to_file_size("4.2KB").unwrap());
+        assert_eq!(1_000_000, str_to_file_size("1.0MB").unwrap());
+        assert_eq!(1_000_000, str_to_file_size("1.0M").unwrap());
+        assert_eq!(4_200_000, str_to_file_size("4.2MB").unwrap());
+        assert_eq!(5_000_000_000, str_to_file_size("5.0GB").unwrap());
+        assert_eq!(5_000_000_000, str_to_file_size("5.0G").unwrap());
+        assert_eq!(
+            Err(SofiduError::ParseValue("5..".to_string())),
+            str_to_file_size("5..GB")
+        );
+        assert_eq!(
+            Err(SofiduError::UnknownUnit("TB".to_string())),
+            str_to_file_size("5..TB")
+        );
+        assert_eq!(
+            Err(SofiduError::ParseValue("".to_string())),
+            str_to_file_size("")
+        );
+        assert_eq!(
+            Err(SofiduError::InvalidSize("-5KB".to_string())),
+            str_to_file_size("-5KB")
+        );
+    }
+
+    #[test]
+    fn str_to_threshold_test() {
+        let terabyte = || Ok(1_000_000_000_000);
+        assert_eq!(Ok(50_000_000_000), str_to_threshold("5%", 1000, terabyte));
+        assert_eq!(Ok(1_500_000_000), str_to_threshold("0.15%", 1000, terabyte));
+        assert_eq!(Ok(1_024), str_to_threshold("1KB", 1024, terabyte));
+        // Filesystem isn't looked at unless needed
+        let unknown = || Err(SofiduError::InvalidPath("foo".to_string()));
+        assert_eq!(Ok(1_000), str_to_threshold("1KB", 1000, unknown));
+        assert_eq!(
+            Err(SofiduError::InvalidPath("foo".to_string())),
+            str_to_threshold("5%", 1000, unknown)
+        );
+        for input in ["101%", "-1%", "foo%"] {
+            assert_eq!(
+                Err(SofiduError::InvalidPercent(input.to_string())),
+                str_to_threshold(input, 1000, terabyte)
+            );
+        }
+        #[cfg(unix)]
+        assert!(filesystem_size(Path::new(".")).is_ok_and(|size| size > 0));
+    }
+
+    #[test]
+    fn str_to_file_size_base_test() {
+        assert_eq!(1_000, str_to_file_size_base("1KB", 1000).unwrap());
+        assert_eq!(1_024, str_to_file_size_base("1KB", 1024).unwrap());
+        assert_eq!(1_536, str_to_file_size_base("1.5K", 1024).unwrap());
+        assert_eq!(1_048_576, str_to_file_size_base("1MB", 1024).unwrap());
+        assert_eq!(999, str_to_file_size_base("999", 1024).unwrap());
+    }
+
+    #[test]
+    fn node_sort_test() {
+        let node_1 = Node::new(PathBuf::from("foo"), 0, vec![]);
+        let node_2 = Node::new(PathBuf::from("bar"), 1, vec![]);
+        let node_3 = Node::new(PathBuf::from("baz"), 100, vec![]);
+        let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
+
+        let mut node = Node::new(PathBuf::from("quaz"), 0, children);
+
+        node.sort();
+
+        let children_out = vec![node_3, node_2, node_1];
+        assert_eq!(children_out, node.children);
+    }
+
+    #[test]
+    fn node_sort_wide_test() {
+        // Enough children to be sorted in parallel, with lots of ties
+        let children: Vec<_> = (0..PAR_SORT_MIN_LEN as u64 * 2)
+            .map(|i| {
+                Node::new(
+                    PathBuf::from(format!("foo/{}", i * 7 % 1_000)),
+                    i % 100,
+                    vec![],
+                )
+            })
+            .collect();
+        let mut expected = children.clone();
+        expected.sort_by_key(|c| (std::cmp::Reverse(c.size), c.path.clone()));
+
+        let mut node = Node::new(PathBuf::from("foo"), 0, children);
+        node.sort();
+        assert_eq!(expected, node.children);
+    }
+
+    #[test]
+    fn node_sort_test_recursive() {
+        let node_1 = Node::new(PathBuf::from("foo"), 0, vec![]);
+        let node_2 = Node::new(PathBuf::from("bar"), 1, vec![]);
+        let mut node_3 = Node::new(
+            PathBuf::from("baz"),
+            100,
+            vec![node_1.clone_childless(), node_2.clone_childless()],
+        );
+
+        let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
+        node_3.sort();
+
+        let mut node = Node::new(PathBuf::from("quaz"), 0, children);
+
+        let children_out = vec![node_3, node_2, node_1];
+        node.sort();
+
+        assert_eq!(children_out, node.children);
+    }
+
+    #[test]
+    fn node_sort_top_level_test() {
+        let node_1 = Node::new(PathBuf::from("quaz/foo"), 0, vec![]);
+        let node_2 = Node::new(PathBuf::from("quaz/bar"), 1, vec![]);
+        let grandchildren = vec![
+            Node::new(PathBuf::from("quaz/baz/small"), 10, vec![]),
+            Node::new(PathBuf::from("quaz/baz/big"), 90, vec![]),
+        ];
+        let node_3 = Node::new(PathBuf::from("quaz/baz"), 100, grandchildren.clone());
+        let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
+        let node = Node::new(PathBuf::from("quaz"), 101, children);
+
+        let settings = settings_from(
+            "sofidu --no-config --sort-top-level-only src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        let mut sorted = node;
+        settings.apply_sort(&mut sorted);
+        assert_eq!(vec![node_3, node_2, node_1], sorted.children);
+        // Not sorted by size, nor by name
+        assert_eq!(grandchildren, sorted.children[0].children);
+    }
+
+    #[test]
+    fn sort_by_max_child_test() {
+        // Bigger in total, but spread across many files
+        let many: Vec<_> = (0..10)
+            .map(|i| Node::new(PathBuf::from(format!("quaz/many/{}", i)), 100, vec![]))
+            .collect();
+        let node_1 = Node::new(PathBuf::from("quaz/many"), 1_000, many);
+        let one = vec![
+            Node::new(PathBuf::from("quaz/one/small"), 10, vec![]),
+            Node::new(PathBuf::from("quaz/one/big"), 500, vec![]),
+        ];
+        let node_2 = Node::new(PathBuf::from("quaz/one"), 510, one);
+        let node_3 = Node::new(PathBuf::from("quaz/file"), 200, vec![]);
+        let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
+        let node = Node::new(PathBuf::from("quaz"), 1_710, children);
+        assert_eq!(500, node.max_leaf_size());
+
+        let settings = settings_from(
+            "sofidu --no-config --sort-by max-child src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        let mut sorted = node.clone();
+        settings.apply_sort(&mut sorted);
+        let paths: Vec<_> = sorted.children.iter().map(|c| c.path.clone()).collect();
+        assert_eq!(vec![node_2.path, node_3.path, node_1.path.clone()], paths);
+        // Deeper levels too
+        assert_eq!(
+            Path::new("quaz/one/big"),
+            sorted.children[0].children[0].path
+        );
+
+        let mut sorted = node;
+        sorted.sort();
+        assert_eq!(node_1.path, sorted.children[0].path);
+
+        // An empty dir isn't a file bigger than what's elsewhere
+        let mut empty = Node::new(PathBuf::from("quaz/p/empty"), 4_096, vec![]);
+        empty.is_dir = true;
+        empty.file_count = 0;
+        let file = Node::new(PathBuf::from("quaz/p/f"), 100, vec![]);
+        let p = Node::new(PathBuf::from("quaz/p"), 8_292, vec![empty, file]);
+        assert_eq!(100, p.max_leaf_size());
+        let q = Node::new(
+            PathBuf::from("quaz/q"),
+            7_096,
+            vec![Node::new(PathBuf::from("quaz/q/g"), 3_000, vec![])],
+        );
+        let mut sorted = Node::new(PathBuf::from("quaz"), 15_388, vec![p, q]);
+        settings.apply_sort(&mut sorted);
+        assert_eq!(Path::new("quaz/q"), sorted.children[0].path);
+    }
+
+    #[test]
+    fn node_as_string_line_test() {
+        // Disable coloring
+        colored::control::set_override(false);
+        let node = Node::new(PathBuf::from("foo"), 3_233_333, vec![]);
+        assert_eq!(
+            "foo 3.2MB",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+        let node = Node::new(PathBuf::from("src"), 3_233_333, vec![]);
+        assert_eq!(
+            "src/ 3.2MB",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+        let node = Node::new(PathBuf::from("src/main.rs"), 3_233_333, vec![]);
+        assert_eq!(
+            "src/main.rs 3.2MB",
+            node.get_as_string_line(true, None, &DisplayOptions::default())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_links_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("resolve_links");
+        std::fs::create_dir(dir.join("data")).unwrap();
+        std::fs::write(dir.join("data").join("foo"), vec![1u8; 100]).unwrap();
+        std::os::unix::fs::symlink(dir.join("data").join("foo"), dir.join("link")).unwrap();
+        let opts = WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let mut node = walk_dir(&dir, &opts);
+        node.sort_by_name();
+        let list = |resolve_links| {
+            let opts = DisplayOptions {
+                resolve_links,
+                ..Default::default()
+            };
+            node.get_as_string_list(true, None, &PathFilter::default(), &opts)
+        };
+        let target = dir.join("data").join("foo").canonicalize().unwrap();
+        assert_eq!(
+            format!(
+                "{} 100B\n{} 100B\n",
+                dir.join("data").join("foo").display(),
+                dir.join("link").display()
+            ),
+            list(false)
+        );
+        // Only the link changes
+        assert_eq!(
+            format!(
+                "{} 100B\n{} 100B\n",
+                dir.join("data").join("foo").display(),
+                target.display()
+            ),
+            list(true)
+        );
+        assert_eq!(None, resolve_link(&dir.join("data")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn node_invalid_utf8_test() {
+        use std::os::unix::ffi::OsStrExt;
+        colored::control::set_override(false);
+        let name = std::ffi::OsStr::from_bytes(b"foo\xffbar");
+
+        let node = Node::new(PathBuf::from("baz").join(name), 333, vec![]);
+        assert_eq!(
+            "foo\u{FFFD}bar 333B",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+        assert_eq!(
+            "baz/foo\u{FFFD}bar 333B",
+            node.get_as_string_line(true, None, &DisplayOptions::default())
+        );
+
+        // Such entries are still walked and listed
+        let dir = test_dir("invalid_utf8");
+        std::fs::write(dir.join(name), vec![1u8; 333]).unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
+        assert_eq!(
+            vec![dir.join(name)],
+            node.flatten()[1..]
+                .iter()
+                .map(|n| n.path.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(node
+            .get_as_string_list(
+                false,
+                None,
+                &PathFilter::default(),
+                &DisplayOptions::default()
+            )
+            .contains("foo\u{FFFD}bar 333B"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn node_as_string_line_test_machine_readable() {
+        // Disable coloring
+        colored::control::set_override(false);
+        let machine = DisplayOptions {
+            machine_readable: true,
+            ..Default::default()
+        };
+        let node = Node::new(PathBuf::from("foo"), 3_233_333, vec![]);
+        assert_eq!(
+            "foo 3233333",
+            node.get_as_string_line(false, None, &machine)
+        );
+        let node = Node::new(PathBuf::from("foo"), 3, vec![]);
+        assert_eq!("foo 3", node.get_as_string_line(false, None, &machine));
+    }
+
+    #[test]
+    fn node_as_string_tree_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
+        let node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+
+        assert_eq!(
+            "foo 3.7GB\n| bar 4.3KB <0.1%\n| | biz 333B 7.7%\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
+            node_top.get_as_string_tree(0, None, None, &DisplayOptions::default()).0
+        );
+        assert_eq!(
+            "foo 3.7GB\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
+            node_top
+                .get_as_string_tree(0, Some(1_000_000), None, &DisplayOptions::default())
+                .0
+        );
+        assert_eq!(
+            "foo 3.7GB\n| bar 4.3KB <0.1%\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
+            node_top
+                .get_as_string_tree(0, Some(4_000), None, &DisplayOptions::default())
+                .0
+        );
+        // Nothing in bar is big enough by itself
+        let strict = DisplayOptions {
+            strict_threshold: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo 3.7GB\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
+            node_top.get_as_string_tree(0, Some(4_000), None, &strict).0
+        );
+        assert!(!node_top.children[0].passes_threshold_strict(Some(4_000)));
+        assert!(node_top.children[0].passes_threshold(Some(4_000)));
+        let passing = node_top.passing_paths(4_000, false);
+        let expected: HashSet<&Path> = ["foo", "foo/bar", "foo/baz", "foo/baz/qiz"]
+            .into_iter()
+            .map(Path::new)
+            .collect();
+        assert_eq!(expected, passing);
+        let passing = node_top.passing_paths(4_000, true);
+        let expected: HashSet<&Path> = ["foo", "foo/baz", "foo/baz/qiz"]
+            .into_iter()
+            .map(Path::new)
+            .collect();
+        assert_eq!(expected, passing);
+    }
+
+    #[test]
+    fn max_children_test() {
+        colored::control::set_override(false);
+        let children = (1..=5)
+            .map(|i| Node::new(PathBuf::from(format!("foo/{}", i)), i * 1_000, vec![]))
+            .collect();
+        let mut node_top = Node::new(PathBuf::from("foo"), 15_000, children);
+        node_top.sort();
+
+        let opts = DisplayOptions {
+            max_children: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo 15.0KB\n| 5 5.0KB 33.3%\n| 4 4.0KB 26.7%\n| ... and 3 more (total 6.0KB)\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+        // Nothing to summarize
+        let opts = DisplayOptions {
+            max_children: Some(5),
+            ..Default::default()
+        };
+        assert!(!node_top
+            .get_as_string_tree(0, None, None, &opts)
+            .0
+            .contains("more"));
+    }
+
+    #[test]
+    fn root_own_size_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_000, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_000, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2]);
+        assert_eq!(4_000, node_top.get_own_size());
+
+        let opts = DisplayOptions::default();
+        assert_eq!(
+            "foo 10.0KB\n| bar 4.0KB 40.0%\n| baz 2.0KB 20.0%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+        let opts = DisplayOptions {
+            root_own_size: true,
+            ..Default::default()
+        };
+        // Children are still a share of the total
+        assert_eq!(
+            "foo 4.0KB\n| bar 4.0KB 40.0%\n| baz 2.0KB 20.0%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+    }
+
+    #[test]
+    fn expand_min_size_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
+        let node_top = Node::new(PathBuf::from("foo"), 2_237_666, vec![node_1, node_2]);
+
+        let settings = settings_from(
+            "sofidu --no-config --expand-min-size 1MB src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert_eq!(Some(1_000_000), settings.expand_min_size);
+        // bar is collapsed, with its whole size still shown
+        assert_eq!(
+            "foo 2.2MB\n| bar 4.3KB 0.2%\n| baz 2.2MB 99.8%\n| | qiz 1.2MB 55.2%\n",
+            node_top
+                .get_as_string_tree(0, None, None, &settings.display_options())
+                .0
+        );
+        // Either condition is enough, so bar with its one child is expanded too
+        let settings = settings_from(
+            "sofidu --no-config --expand-min-size 1MB --expand-min-children 1 src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert_eq!(Some(1), settings.expand_min_children);
+        assert_eq!(
+            "foo 2.2MB\n| bar 4.3KB 0.2%\n| | biz 333B 7.7%\n| baz 2.2MB 99.8%\n| | qiz 1.2MB 55.2%\n",
+            node_top
+                .get_as_string_tree(0, None, None, &settings.display_options())
+                .0
+        );
+        let mut opts = DisplayOptions {
+            expand_min_children: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo 2.2MB\n| bar 4.3KB 0.2%\n| baz 2.2MB 99.8%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+        opts.expand_min_children = Some(1);
+        assert_eq!(
+            "foo 2.2MB\n| bar 4.3KB 0.2%\n| | biz 333B 7.7%\n| baz 2.2MB 99.8%\n| | qiz 1.2MB 55.2%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+    }
+
+    #[test]
+    fn root_label_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_000, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1]);
+
+        let settings = settings_from(
+            vec![
+                "sofidu",
+                "--no-config",
+                "--root-label",
+                "Backup Drive",
+                "src",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+        assert_eq!(Some("Backup Drive".to_string()), settings.root_label);
+        let opts = settings.display_options();
+        assert_eq!(
+            "Backup Drive 10.0KB\n| bar 4.0KB 40.0%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+        assert_eq!(
+            "foo 10.0KB\n| bar 4.0KB 40.0%\n",
+            node_top
+                .get_as_string_tree(0, None, None, &DisplayOptions::default())
+                .0
+        );
+        // List paths stay as they are
+        assert_eq!(
+            "foo 10.0KB\nfoo/bar 4.0KB\n",
+            node_top.get_as_string_list(false, None, &PathFilter::default(), &opts)
+        );
+    }
+
+    #[test]
+    fn mono_theme_test() {
+        let opts = DisplayOptions {
+            theme: theme::Theme::MONO,
+            bars: true,
+            ..Default::default()
+        };
+        let mut dir = Node::new(PathBuf::from("foo"), 1_000, vec![]);
+        dir.is_dir = true;
+        let file = Node::new(PathBuf::from("foo/bar"), 500, vec![]);
+        // Plain strings are written without escape codes, even with the color override on
+        assert!(dir.get_styled_name(false, &opts).is_plain());
+        assert!(file.get_styled_name(false, &opts).is_plain());
+        assert!(file.get_styled_size(&opts).is_plain());
+        assert!(get_connectors(3, &opts).is_plain());
+        for percentage in [50.0, 20.0, 1.0] {
+            assert!(opts.theme.percent(percentage).paint("50%").is_plain());
+        }
+
+        let default = theme::Theme::DEFAULT;
+        assert_eq!("50%".red().bold(), default.percent(50.0).paint("50%"));
+        assert_eq!("20%".bright_red(), default.percent(20.0).paint("20%"));
+        assert_eq!("| ".dimmed(), default.connectors.paint("| "));
+        assert_eq!(
+            Some(theme::Theme::SOLARIZED),
+            theme::Theme::by_name("solarized")
+        );
+        assert_eq!(None, theme::Theme::by_name("nope"));
+    }
+
+    #[test]
+    fn compact_test() {
+        colored::control::set_override(false);
+        let dir = |path: &str, size, children| {
+            let mut node = Node::new(PathBuf::from(path), size, children);
+            node.is_dir = true;
+            node
+        };
+        let file = Node::new(PathBuf::from("foo/a/b/c/file"), 1_000, vec![]);
+        let c = dir("foo/a/b/c", 2_000, vec![file]);
+        let b = dir("foo/a/b", 3_000, vec![c]);
+        let a = dir("foo/a", 4_000, vec![b]);
+        let qiz = Node::new(PathBuf::from("foo/qiz"), 1_000, vec![]);
+        let node_top = dir("foo", 5_000, vec![a, qiz]);
+        let opts = DisplayOptions {
+            compact: true,
+            ..Default::default()
+        };
+
+        // Size of the deepest dir, percentage of the chain's parent
+        assert_eq!(
+            "foo/ 5.0KB\n| a/b/c/ 2.0KB 40.0%\n| | file 1.0KB 50.0%\n| qiz 1.0KB 20.0%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+        // The root stays as it is, even with only a dir inside
+        let node_top = dir("foo", 5_000, vec![node_top.children[0].clone()]);
+        assert_eq!(
+            "foo/ 5.0KB\n| a/b/c/ 2.0KB 40.0%\n| | file 1.0KB 50.0%\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+    }
+
+    #[test]
+    fn node_write_tree_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
+        let node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+
+        for threshold in [None, Some(4_000), Some(1_000_000)] {
+            let mut output = vec![];
+            node_top
+                .write_tree(&mut output, 0, threshold, None, &DisplayOptions::default())
+                .unwrap();
+            assert_eq!(
+                node_top
+                    .get_as_string_tree(0, threshold, None, &DisplayOptions::default())
+                    .0,
+                String::from_utf8(output).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn connector_color_test() {
+        // Compare styles directly, so the global color override doesn't matter
+        let opts = DisplayOptions::default();
+        assert_eq!("| | ".dimmed(), get_connectors(2, &opts));
+        assert_eq!("".dimmed(), get_connectors(0, &opts));
+        let opts = DisplayOptions {
+            connector_color: false,
+            ..Default::default()
+        };
+        assert_eq!("| | ".normal(), get_connectors(2, &opts));
+        let opts = DisplayOptions {
+            rainbow: true,
+            ..Default::default()
+        };
+        assert_eq!("| ".yellow(), get_connectors(1, &opts));
+        assert_eq!("| | ".green(), get_connectors(2, &opts));
+        assert_ne!(
+            get_connectors(1, &opts).fgcolor(),
+            get_connectors(2, &opts).fgcolor()
+        );
+        // Palette starts over for deeper levels
+        assert_eq!(
+            get_connectors(1, &opts).fgcolor(),
+            get_connectors(1 + DEPTH_COLORS.len(), &opts).fgcolor()
+        );
+
+        // Names keep their own colors
+        let node = Node::new(PathBuf::from("foo"), 333, vec![]);
+        assert_eq!("foo".cyan(), node.get_styled_name(false, &opts));
+        let mut node = Node::new(PathBuf::from("foo/bar"), 333, vec![]);
+        node.is_dir = true;
+        let dir_name = format!("bar{}", std::path::MAIN_SEPARATOR);
+        assert_eq!(dir_name.bright_blue(), node.get_styled_name(false, &opts));
+    }
+
+    #[test]
+    fn escape_control_chars_test() {
+        colored::control::set_override(false);
+        let node = Node::new(PathBuf::from("foo\nbar\x1b[31m"), 333, vec![]);
+        assert_eq!(
+            "foo\\x0abar\\x1b[31m 333B",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+        let opts = DisplayOptions {
+            raw_names: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo\nbar\x1b[31m 333B",
+            node.get_as_string_line(false, None, &opts)
+        );
+        assert_eq!("\\x85żółw", escape_control_chars("\u{85}żółw"));
+    }
+
+    #[test]
+    fn count_only_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("count_only");
+        std::fs::create_dir_all(dir.join("many").join("deeper")).unwrap();
+        for i in 0..3 {
+            std::fs::write(dir.join("many").join(i.to_string()), vec![1u8; 10]).unwrap();
+        }
+        std::fs::write(dir.join("many").join("deeper").join("foo"), [1u8]).unwrap();
+        std::fs::write(dir.join("big"), vec![1u8; 10_000]).unwrap();
+        let args = format!("sofidu --no-config --count-only -s -d 1 {}", dir.display());
+        let settings = settings_from(args.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(SortKey::Count, settings.sort_key);
+        assert_eq!(PctMetric::Entries, settings.pct_metric);
+
+        let node = scan(&settings);
+        let opts = settings.display_options();
+        // Sorted by count, even though `big` is bigger
+        let many = &node.children[0];
+        // 4 files and `deeper`, which is past the depth but still counted
+        assert_eq!((4, 1), (many.file_count, many.dir_count));
+        assert_eq!(7, node.entry_count());
+        assert_eq!(
+            "many/ 5 entries 71.4%",
+            many.get_as_string_line(false, Some(node.entry_count()), &opts)
+        );
+        assert_eq!(
+            "big 1 entry 14.3%",
+            node.children[1].get_as_string_line(false, Some(node.entry_count()), &opts)
+        );
+        let opts = DisplayOptions {
+            machine_readable: true,
+            ..opts
+        };
+        assert_eq!("many/ 5", many.get_as_string_line(false, None, &opts));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn baseline_test() {
+        let dir = test_dir("baseline");
+        let baseline = Node::new(
+            PathBuf::from("foo"),
+            3_000,
+            vec![
+                Node::new(PathBuf::from("foo/bar"), 1_000, vec![]),
+                Node::new(PathBuf::from("foo/baz"), 2_000, vec![]),
+            ],
+        );
+        let snapshot = dir.join("snapshot.json");
+        diff::save_snapshot(&baseline, &snapshot).unwrap();
+        let node = Node::new(
+            PathBuf::from("foo"),
+            3_500,
+            vec![
+                Node::new(PathBuf::from("foo/bar"), 3_000, vec![]),
+                Node::new(PathBuf::from("foo/baz"), 500, vec![]),
+            ],
+        );
+        let args = format!("sofidu --no-config --baseline {} src", snapshot.display());
+        let settings = settings_from(args.split(' ').map(|a| a.to_string()).collect());
+        let opts = DisplayOptions {
+            baseline: diff::size_deltas(&read_snapshot(&snapshot, &settings), &node),
+            ..settings.display_options()
+        };
+        // Compare styles directly, so the global color override doesn't matter
+        assert_eq!("3.5KB".red(), node.get_styled_size(&opts));
+        assert_eq!("3.0KB".red(), node.children[0].get_styled_size(&opts));
+        assert_eq!(
+            "500B".bright_green(),
+            node.children[1].get_styled_size(&opts)
+        );
+        // Not in the baseline, or without one
+        let new = Node::new(PathBuf::from("foo/qiz"), 100, vec![]);
+        assert_eq!("100B".green(), new.get_styled_size(&opts));
+        let opts = DisplayOptions::default();
+        assert_eq!("3.5KB".green(), node.get_styled_size(&opts));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn show_rank_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 1_000, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/qiz"), 1_000, vec![]);
+        let node_4 = Node::new(PathBuf::from("foo/zed"), 500, vec![]);
+        let node_top = Node::new(
+            PathBuf::from("foo"),
+            5_500,
+            vec![node_1, node_2, node_3, node_4],
+        );
+
+        let ranks = node_top.sibling_ranks();
+        assert_eq!(Some(&(1, 4)), ranks.get(Path::new("foo/baz")));
+        // Ties share the rank, the next one comes after both
+        assert_eq!(Some(&(2, 4)), ranks.get(Path::new("foo/bar")));
+        assert_eq!(Some(&(2, 4)), ranks.get(Path::new("foo/qiz")));
+        assert_eq!(Some(&(4, 4)), ranks.get(Path::new("foo/zed")));
+        assert_eq!(None, ranks.get(Path::new("foo")));
+
+        let opts = DisplayOptions {
+            ranks,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo 5.5KB\n| bar 1.0KB 18.2% [#2/4]\n| baz 3.0KB 54.5% [#1/4]\n| qiz 1.0KB 18.2% [#2/4]\n| zed 500B 9.1% [#4/4]\n",
+            node_top.get_as_string_tree(0, None, None, &opts).0
+        );
+    }
+
+    #[test]
+    fn size_balance_test() {
+        let children = |sizes: &[u64]| -> Vec<Node> {
+            sizes
+                .iter()
+                .enumerate()
+                .map(|(i, size)| Node::new(PathBuf::from(format!("foo/{}", i)), *size, vec![]))
+                .collect()
+        };
+        let dominant = size_balance(&children(&[1_000_000_000, 10, 10, 10]));
+        assert!(dominant < 0.01, "{}", dominant);
+        let even = size_balance(&children(&[1_000, 1_000, 1_000, 1_000]));
+        assert!((even - 1.0).abs() < 1e-6, "{}", even);
+        let between = size_balance(&children(&[3_000, 1_000]));
+        assert!(between > dominant && between < even, "{}", between);
+        assert_eq!(1.0, size_balance(&children(&[1_000])));
+        assert_eq!(1.0, size_balance(&children(&[0, 0])));
+
+        colored::control::set_override(false);
+        let opts = DisplayOptions {
+            balance: true,
+            ..Default::default()
+        };
+        let node = Node::new(PathBuf::from("foo"), 2_000, children(&[1_000, 1_000]));
+        assert_eq!(
+            "foo 2.0KB balance 1.00",
+            node.get_as_string_line(false, None, &opts)
+        );
+        // Nothing to be balanced between
+        let file = Node::new(PathBuf::from("foo"), 2_000, vec![]);
+        assert_eq!("foo 2.0KB", file.get_as_string_line(false, None, &opts));
+    }
+
+    #[test]
+    fn icon_for_test() {
+        let rust = Node::new(PathBuf::from("src/lib.rs"), 333, vec![]);
+        assert_eq!('\u{e7a8}', icon_for(&rust));
+        let upper = Node::new(PathBuf::from("src/LIB.RS"), 333, vec![]);
+        assert_eq!('\u{e7a8}', icon_for(&upper));
+        let mut dir = Node::new(PathBuf::from("src/foo.rs"), 333, vec![]);
+        dir.is_dir = true;
+        assert_eq!(DIR_ICON, icon_for(&dir));
+        for path in ["src/foo.unknown", "src/foo"] {
+            assert_eq!(
+                FILE_ICON,
+                icon_for(&Node::new(PathBuf::from(path), 333, vec![]))
+            );
+        }
+
+        colored::control::set_override(false);
+        let opts = DisplayOptions {
+            icons: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "\u{e7a8} lib.rs 333B",
+            rust.get_as_string_line(false, None, &opts)
+        );
+    }
+
+    #[test]
+    fn ascii_escape_test() {
+        colored::control::set_override(false);
+        let node = Node::new(PathBuf::from("café\n"), 333, vec![]);
+        assert_eq!(
+            "café\\x0a 333B",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+        let opts = DisplayOptions {
+            ascii_escape: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "caf\\u{00e9}\\x0a 333B",
+            node.get_as_string_line(false, None, &opts)
+        );
+        assert_eq!("\\u{017c}\\u{1f422}", escape_non_ascii("ż🐢"));
+    }
+
+    #[test]
+    fn pct_metric_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("pct_metric");
+        std::fs::create_dir(dir.join("many")).unwrap();
+        for i in 0..99 {
+            std::fs::write(dir.join("many").join(i.to_string()), [1u8]).unwrap();
+        }
+        std::fs::create_dir(dir.join("big")).unwrap();
+        std::fs::write(dir.join("big").join("foo"), vec![1u8; 9_901]).unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
+        assert_eq!(100, node.file_count);
+        let many = node
+            .children
+            .iter()
+            .find(|c| c.path.ends_with("many"))
+            .unwrap();
+        assert_eq!(99, many.file_count);
+
+        // Sizes of dirs themselves would get in the way
+        let mut node = tree_from_entries(
+            &dir,
+            walk_dir(&dir, &WalkOptions::default())
+                .flatten()
+                .into_iter()
+                .filter(|n| !n.is_dir)
+                .map(|n| {
+                    (
+                        n.path.strip_prefix(&dir).unwrap().to_path_buf(),
+                        n.size,
+                        false,
+                    )
+                })
+                .collect(),
+            i32::MAX,
+        );
+        node.sort_by_name();
+        let opts = DisplayOptions::default();
+        let tree = node.get_as_string_tree(0, None, None, &opts).0;
+        assert!(tree.contains("| big/ 9.9KB 99.0%\n"));
+        assert!(tree.contains("| many/ 99B 1.0%\n"));
+
+        let opts = DisplayOptions {
+            pct_metric: PctMetric::Count,
+            ..Default::default()
+        };
+        let tree = node.get_as_string_tree(0, None, None, &opts).0;
+        assert!(tree.contains("| big/ 9.9KB 1.0%\n"));
+        assert!(tree.contains("| many/ 99B 99.0%\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bars_test() {
+        assert_eq!("          ", fraction_to_bar(0.0, 10));
+        assert_eq!("█████     ", fraction_to_bar(0.5, 10));
+        assert_eq!("█▌  ", fraction_to_bar(0.375, 4));
+        assert_eq!("████", fraction_to_bar(1.5, 4));
+
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 8_000, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_000, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2]);
+        let opts = DisplayOptions {
+            bars: true,
+            ..Default::default()
+        };
+        let tree = node_top.get_as_string_tree(0, None, None, &opts).0;
+        assert_eq!(
+            "foo 10.0KB\n| bar 8.0KB 80.0% ████████  \n| baz 2.0KB 20.0% ██        \n",
+            tree
+        );
+        let bar_len = |line: &str| line.chars().filter(|c| *c == '█').count();
+        let lines: Vec<_> = tree.lines().collect();
+        assert!(bar_len(lines[1]) > bar_len(lines[2]));
+    }
+
+    #[test]
+    fn dot_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/b\"ar"), 1_000, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 4_000, vec![node_1]);
+        node_top.is_dir = true;
+        let mut output = vec![];
+        node_top
+            .write_dot(&mut output, &DisplayOptions::default())
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with("digraph sofidu {\n"));
+        assert!(output.contains(
+            "    n0 [label=\"foo\\n4.0KB\", fontsize=30.0, style=filled, fillcolor=lightblue];\n"
+        ));
+        assert!(output.contains("    n1 [label=\"b\\\"ar\\n1.0KB\", fontsize=15.0];\n"));
+        assert!(output.contains("    n0 -> n1;\n"));
+        assert!(output.ends_with("}\n"));
+    }
+
+    #[test]
+    fn ndjson_test() {
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 1_000, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 5_000, vec![node_1]);
+        node_top.is_dir = true;
+        let mut output = vec![];
+        node_top.write_ndjson(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let values: Vec<serde_json::Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                serde_json::json!({"path": "foo", "size": 5000, "is_dir": true}),
+                serde_json::json!({"path": "foo/bar", "size": 1000, "is_dir": false}),
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn node_per_child_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+
+        let mut output = vec![];
+        node_top
+            .write_per_child(&mut output, None, &DisplayOptions::default())
+            .unwrap();
+        assert_eq!(
+            "foo/baz 2.2MB\nfoo/bar 4.3KB\n| biz 333B 7.7%\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let mut output = vec![];
+        node_top
+            .write_per_child(&mut output, Some(1_000_000), &DisplayOptions::default())
+            .unwrap();
+        assert_eq!("foo/baz 2.2MB\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn node_prune_below_test() {
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let node_1_2 = Node::new(PathBuf::from("foo/bar/qux"), 4_000, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1, node_1_2]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 900, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/qiz"), 1_200, vec![]);
+        let mut node_top = Node::new(
+            PathBuf::from("foo"),
+            6_433,
+            vec![node_1.clone(), node_2, node_3.clone()],
+        );
+
+        node_top.prune_below(1_000);
+        let mut node_1_pruned = node_1;
+        node_1_pruned.children.remove(0);
+        assert_eq!(vec![node_1_pruned, node_3], node_top.children);
+        assert_eq!(6_433, node_top.size);
+        assert_eq!(
+            vec!["foo", "foo/bar", "foo/bar/qux", "foo/qiz"],
+            node_top
+                .flatten()
+                .iter()
+                .map(|n| n.path.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn node_prune_empty_test() {
+        let mut empty = Node::new(PathBuf::from("foo/empty"), 4_096, vec![]);
+        empty.is_dir = true;
+        empty.file_count = 0;
+        assert!(empty.is_effectively_empty());
+        // Only holds an empty dir
+        let mut outer = Node::new(PathBuf::from("foo/outer"), 8_192, vec![empty.clone()]);
+        outer.is_dir = true;
+        assert!(outer.is_effectively_empty());
+        let file = Node::new(PathBuf::from("foo/bar"), 100, vec![]);
+        assert!(!file.is_effectively_empty());
+        let mut node_top = Node::new(PathBuf::from("foo"), 16_484, vec![empty, outer, file]);
+        node_top.is_dir = true;
+        assert!(!node_top.is_effectively_empty());
+
+        node_top.prune_empty();
+        assert_eq!(
+            vec![PathBuf::from("foo/bar")],
+            node_top
+                .children
+                .iter()
+                .map(|c| c.path.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(16_484, node_top.size);
+
+        let dir = test_dir("exclude_empty");
+        std::fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        std::fs::write(dir.join("foo"), [1u8]).unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
+        assert!(node.children.iter().any(|c| c.is_effectively_empty()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_subtrees_test() {
+        let subtree = |root: &str| {
+            let file_1 = Node::new(PathBuf::from(format!("{}/foo", root)), 1_000, vec![]);
+            let file_2 = Node::new(PathBuf::from(format!("{}/bar", root)), 2_000, vec![]);
+            let mut inner = Node::new(PathBuf::from(format!("{}/inner", root)), 500, vec![]);
+            inner.children.push(Node::new(
+                PathBuf::from(format!("{}/inner/baz", root)),
+                500,
+                vec![],
+            ));
+            Node::new(PathBuf::from(root), 3_500, vec![file_2, file_1, inner])
+        };
+        let other = Node::new(PathBuf::from("top/other"), 3_500, vec![]);
+        let node_b = Node::new(PathBuf::from("top/b"), 3_500, vec![subtree("top/b/c")]);
+        let mut node_top = Node::new(
+            PathBuf::from("top"),
+            10_500,
+            vec![subtree("top/a"), other, node_b],
+        );
+
+        // Copy inside the copy isn't repeated
+        let duplicates = vec![(PathBuf::from("top/a"), PathBuf::from("top/b/c"))];
+        assert_eq!(duplicates, node_top.find_duplicate_subtrees());
+
+        assert_eq!(duplicates, node_top.dedupe_subtrees());
+        assert_eq!(7_000, node_top.size);
+        assert_eq!(0, node_top.children[2].size);
+        assert!(node_top.children[2].children.is_empty());
+        assert!(node_top.find_duplicate_subtrees().is_empty());
+    }
+
+    #[test]
+    fn node_prune_depth_test() {
+        // foo/bar/biz/quz is the deepest branch, foo/baz and foo/bar/qiz are shallow ones
+        let node_1_1_1 = Node::new(PathBuf::from("foo/bar/biz/quz"), 100, vec![]);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 200, vec![node_1_1_1]);
+        let node_1_2 = Node::new(PathBuf::from("foo/bar/qiz"), 300, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 600, vec![node_1_1, node_1_2]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 400, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 1_000, vec![node_1, node_2]);
+
+        let paths = |node: &Node| {
+            node.flatten()
+                .iter()
+                .map(|n| n.path.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let mut node = node_top.clone();
+        node.prune_depth(0);
+        assert_eq!(node_top, node);
+
+        let mut node = node_top.clone();
+        node.prune_depth(1);
+        assert_eq!(vec!["foo", "foo/bar", "foo/bar/biz"], paths(&node));
+        assert_eq!(600, node.children[0].size);
+
+        let mut node = node_top.clone();
+        node.prune_depth(2);
+        assert_eq!(vec!["foo", "foo/bar"], paths(&node));
+
+        let mut node = node_top;
+        node.prune_depth(5);
+        assert_eq!(vec!["foo"], paths(&node));
+        assert_eq!(1_000, node.size);
+    }
+
+    #[test]
+    fn total_line_test() {
+        let node = Node::new(PathBuf::from("foo"), 3_233_333, vec![]);
+        assert_eq!("TOTAL\t3233333", node.get_total_line());
+
+        let settings = settings_from("sofidu -m src".split(' ').map(|a| a.to_string()).collect());
+        assert!(settings.show_total_line());
+        let settings = settings_from(
+            "sofidu -m --no-summary src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert!(!settings.show_total_line());
+        let settings = settings_from(vec!["sofidu".to_string(), "src".to_string()]);
+        assert!(!settings.show_total_line());
+    }
+
+    #[test]
+    fn node_flatten_test() {
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 4_333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 333, vec![node_1_1.clone()]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000_233_333, vec![]);
+        let node_top = Node::new(
+            PathBuf::from("foo"),
+            3_233_333,
+            vec![node_1.clone(), node_2.clone()],
+        );
+        let result = vec![
+            node_top.clone_childless(),
+            node_1.clone_childless(),
+            node_1_1.clone_childless(),
+            node_2.clone_childless(),
+        ];
+        assert_eq!(result, node_top.flatten());
+    }
+
+    #[test]
+    fn node_flatten_bfs_test() {
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 4_333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 333, vec![node_1_1.clone()]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000_233_333, vec![]);
+        let node_top = Node::new(
+            PathBuf::from("foo"),
+            3_233_333,
+            vec![node_1.clone(), node_2.clone()],
+        );
+        // `biz` is deeper, so after `baz` unlike in `flatten`
+        let result = vec![
+            node_top.clone_childless(),
+            node_1.clone_childless(),
+            node_2.clone_childless(),
+            node_1_1.clone_childless(),
+        ];
+        assert_eq!(result, node_top.flatten_bfs());
+
+        colored::control::set_override(false);
+        let settings = settings_from(
+            "sofidu --no-config -l --order bfs src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert_eq!(ListOrder::BreadthFirst, settings.order);
+        assert_eq!(
+            "foo 3.2MB\nfoo/bar 333B\nfoo/baz 3.0GB\nfoo/bar/biz 4.3KB\n",
+            node_top.get_as_string_list(
+                false,
+                None,
+                &PathFilter::default(),
+                &settings.display_options()
+            )
+        );
+    }
+
+    #[test]
+    fn node_as_list_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        node_1.is_dir = true;
+        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
+        let mut node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
+        node_2.is_dir = true;
+        let mut node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+        node_top.is_dir = true;
+
+        assert_eq!(
+            "foo/ 3.7GB\nfoo/bar/ 4.3KB\nfoo/bar/biz 333B\nfoo/baz/ 2.2MB\nfoo/baz/qiz 1.2MB\n",
+            node_top.get_as_string_list(
+                false,
+                None,
+                &PathFilter::default(),
+                &DisplayOptions::default()
+            )
+        );
+        assert_eq!(
+            "foo/bar/biz 333B\nfoo/baz/qiz 1.2MB\n",
+            node_top.get_as_string_list(
+                true,
+                None,
+                &PathFilter::default(),
+                &DisplayOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn node_as_list_percent_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
+        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
+        node_1.is_dir = true;
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 5_667, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2]);
+        node_top.is_dir = true;
+
+        let opts = DisplayOptions {
+            list_percent: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo/ 10.0KB\nfoo/bar/ 4.3KB 43.3%\nfoo/bar/biz 333B 7.7%\nfoo/baz 5.7KB 56.7%\n",
+            node_top.get_as_string_list(false, None, &PathFilter::default(), &opts)
+        );
+    }
+
+    #[test]
+    fn percent_only_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 2_500, vec![]);
+        let node_1_2 = Node::new(PathBuf::from("foo/bar/qiz"), 1_250, vec![]);
+        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 3_750, vec![node_1_1, node_1_2]);
+        node_1.is_dir = true;
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 6_250, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2]);
+        node_top.is_dir = true;
+
+        let percentages = node_top.percentages_of_total();
+        let leaves_sum: f64 = percentages
+            .iter()
+            .filter(|(node, _)| !node.is_dir)
+            .map(|(_, percentage)| percentage)
+            .sum();
+        assert!((leaves_sum - 100.0).abs() < 1e-9);
+        // Children of a dir add up to the dir's share
+        assert!((percentages[2].1 + percentages[3].1 - percentages[1].1).abs() < 1e-9);
+
+        let opts = DisplayOptions {
+            precision: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo/\t100.00\nfoo/bar/\t37.50\nfoo/bar/biz\t25.00\nfoo/bar/qiz\t12.50\nfoo/baz\t62.50\n",
+            node_top.get_as_string_percent_only(&opts)
+        );
+    }
+
+    #[test]
+    fn table_test() {
+        colored::control::set_override(false);
+        assert_eq!(71, table_path_width(80, 8));
+        assert_eq!(TABLE_MIN_PATH_WIDTH, table_path_width(15, 8));
+
+        let node_1 = Node::new(PathBuf::from("foo/some_long_name"), 2_500, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 500, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 3_000, vec![node_1, node_2]);
+        node_top.is_dir = true;
+        let opts = DisplayOptions::default();
+        assert_eq!(
+            "foo/         3.0KB\n…e_long_name 2.5KB\nfoo/baz       500B\n",
+            node_top.get_as_string_table(None, 18, &opts)
+        );
+        // Threshold drops entries
+        assert_eq!(
+            "foo/                3.0KB\nfoo/some_long_name  2.5KB\n",
+            node_top.get_as_string_table(Some(1_000), 25, &opts)
+        );
+    }
+
+    #[test]
+    fn no_percent_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 2_500, vec![]);
+        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 2_500, vec![node_1_1]);
+        node_1.is_dir = true;
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 500, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 3_000, vec![node_1, node_2]);
+        node_top.is_dir = true;
+
+        let with_percent = node_top
+            .get_as_string_tree(0, None, None, &DisplayOptions::default())
+            .0;
+        let opts = DisplayOptions {
+            no_percent: true,
+            ..Default::default()
+        };
+        let without_percent = node_top.get_as_string_tree(0, None, None, &opts).0;
+        assert!(!without_percent.contains('%'));
+        // Same lines, just without the last field
+        let stripped: Vec<_> = with_percent
+            .lines()
+            .map(|line| match line.rsplit_once(' ') {
+                Some((rest, pct)) if pct.ends_with('%') => rest,
+                _ => line,
+            })
+            .collect();
+        assert_eq!(stripped, without_percent.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn markdown_test() {
+        let node_1 = Node::new(PathBuf::from("foo/a|b"), 2_500, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 500, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 3_000, vec![node_1, node_2]);
+        node_top.is_dir = true;
+        let opts = DisplayOptions::default();
+        assert_eq!(
+            "| Path | Size | % |\n| --- | ---: | ---: |\n| foo/ | 3.0KB |  |\n| foo/a\\|b | 2.5KB | 83.3% |\n| foo/baz | 500B | 16.7% |\n",
+            node_top.get_as_string_markdown(false, None, &PathFilter::default(), &opts)
+        );
+    }
+
+    #[test]
+    fn size_histogram_test() {
+        let files: Vec<_> = [0, 999, 1_000, 5_000, 20_000, 1_000_000_000, 5_000_000_000]
+            .iter()
+            .map(|&size| Node::new(PathBuf::from("foo"), size, vec![]))
+            .collect();
+        assert_eq!(
+            vec![
+                (1_000, 2, 999),
+                (10_000, 2, 6_000),
+                (100_000, 1, 20_000),
+                (u64::MAX, 2, 6_000_000_000)
+            ],
+            size_histogram(&files, &[1_000, 10_000, 100_000])
+        );
+        assert_eq!(vec![(u64::MAX, 0, 0)], size_histogram(&[], &[]));
+    }
+
+    #[test]
+    fn node_as_cumulative_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 3_000, vec![]);
+        let node_1_2 = Node::new(PathBuf::from("foo/bar/qiz"), 1_500, vec![]);
+        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 4_500, vec![node_1_1, node_1_2]);
+        node_1.is_dir = true;
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 5_000, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/zed"), 500, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2, node_3]);
+        node_top.is_dir = true;
+
+        let sizes = |percent| {
+            node_top
+                .cumulative_entries(percent)
+                .iter()
+                .map(|n| n.size)
+                .collect::<Vec<_>>()
+        };
+        // Exactly reaching the percentage is enough
+        assert_eq!(vec![5_000, 3_000], sizes(80.0));
+        assert_eq!(vec![5_000, 3_000, 1_500], sizes(80.1));
+        assert_eq!(vec![5_000, 3_000, 1_500, 500], sizes(100.0));
+        assert_eq!(Vec::<u64>::new(), sizes(0.0));
+
+        assert_eq!(
+            "foo/baz 5.0KB\nfoo/bar/biz 3.0KB\n",
+            node_top.get_as_string_cumulative(50.1, &DisplayOptions::default())
+        );
+        // Dirs' own sizes don't make the percentage unreachable
+        let dir = test_dir("cumulative");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a"), vec![1u8; 6_000]).unwrap();
+        std::fs::write(dir.join("sub").join("b"), vec![1u8; 3_000]).unwrap();
+        std::fs::write(dir.join("sub").join("c"), vec![1u8; 1_000]).unwrap();
+        std::fs::write(dir.join("empty"), "").unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
+        let paths: Vec<_> = node
+            .cumulative_entries(80.0)
+            .into_iter()
+            .map(|n| n.path)
+            .collect();
+        assert_eq!(vec![dir.join("a"), dir.join("sub").join("b")], paths);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Ok(12.5), parse_percent("12.5"));
+        assert!(matches!(
+            parse_percent("101"),
+            Err(SofiduError::InvalidPercent(_))
+        ));
+        assert!(matches!(
+            parse_percent("foo"),
+            Err(SofiduError::InvalidPercent(_))
+        ));
+    }
+
+    #[test]
+    fn sizes_by_level_test() {
+        colored::control::set_override(false);
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 3_000, vec![]);
+        let node_1_2 = Node::new(PathBuf::from("foo/bar/qiz"), 1_500, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_600, vec![node_1_1, node_1_2]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 5_000, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/zed"), 300, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node_1, node_2, node_3]);
+
+        assert_eq!(
+            vec![(1, 10_000), (3, 9_900), (2, 4_500)],
+            node_top.sizes_by_level()
+        );
+        assert_eq!(
+            "Level 0: 1 entry, 10.0KB\nLevel 1: 3 entries, 9.9KB\nLevel 2: 2 entries, 4.5KB\n",
+            node_top.get_as_string_by_level(&DisplayOptions::default())
+        );
+    }
+
+    #[test]
+    fn deepest_entries_test() {
+        colored::control::set_override(false);
+        let deep = Node::new(PathBuf::from("foo/a/b/c"), 10, vec![]);
+        let b = Node::new(PathBuf::from("foo/a/b"), 10, vec![deep]);
+        let qiz = Node::new(PathBuf::from("foo/a/qiz"), 20, vec![]);
+        let a = Node::new(PathBuf::from("foo/a"), 30, vec![b, qiz]);
+        let baz = Node::new(PathBuf::from("foo/baz/zed"), 500, vec![]);
+        let baz = Node::new(PathBuf::from("foo/baz"), 500, vec![baz]);
+        let node = Node::new(PathBuf::from("foo"), 530, vec![a, baz]);
+
+        let paths = |n| {
+            node.deepest_entries(n)
+                .iter()
+                .map(|(n, depth)| (n.path.to_str().unwrap().to_owned(), *depth))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![("foo/a/b/c".to_owned(), 3)], paths(1));
+        // Same depth keeps the order of the tree, not sizes
+        assert_eq!(
+            vec![
+                ("foo/a/b/c".to_owned(), 3),
+                ("foo/a/b".to_owned(), 2),
+                ("foo/a/qiz".to_owned(), 2),
+                ("foo/baz/zed".to_owned(), 2),
+            ],
+            paths(4)
+        );
+        assert_eq!(7, paths(100).len());
+        assert_eq!(
+            "foo/a/b/c 10B depth 3\nfoo/a/b 10B depth 2\n",
+            node.get_as_string_deepest(2, &DisplayOptions::default())
+        );
+    }
+
+    #[test]
+    fn truncate_middle_test() {
+        assert_eq!("src/lib.rs", truncate_middle("src/lib.rs", 10));
+        assert_eq!("src/lib.rs", truncate_middle("src/lib.rs", 80));
+        let path =
+            ["", "very", "long", "path", "to", "file.rs"].join(std::path::MAIN_SEPARATOR_STR);
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            format!("{s}very{s}long{s}…{s}file.rs", s = sep),
+            truncate_middle(&path, 20)
+        );
+        assert_eq!(format!("…{}file.rs", sep), truncate_middle(&path, 9));
+        // Not even the name fits
+        assert_eq!("…le.rs", truncate_middle(&path, 6));
+        assert_eq!("", truncate_middle(&path, 0));
+        assert_eq!("…wik", truncate_middle("żółwik", 4));
+    }
+
+    #[test]
+    fn max_width_list_test() {
+        colored::control::set_override(false);
+        let path = ["foo", "long_directory_name", "main.rs"].join(std::path::MAIN_SEPARATOR_STR);
+        let node_1 = Node::new(PathBuf::from(&path), 333, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 333, vec![node_1]);
+        node_top.is_dir = true;
+        let opts = DisplayOptions {
+            max_width: Some(20),
+            ..Default::default()
+        };
+        let list = node_top.get_as_string_list(false, None, &PathFilter::default(), &opts);
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            format!("foo{} 333B\nfoo{}lo…{}main.rs 333B\n", sep, sep, sep),
+            list
+        );
+        assert!(list.lines().all(|l| l.chars().count() <= 20));
+        // Colors don't count
+        assert_eq!(4, visible_width(&"foo/".bright_blue().bold().to_string()));
+
+        // Raw names can have escape sequences of their own
+        let node_1 = Node::new(
+            PathBuf::from("foo/\x1b[31mred_and_long_name\x1b[0m"),
+            333,
+            vec![],
+        );
+        let mut node_top = Node::new(PathBuf::from("foo"), 333, vec![node_1]);
+        node_top.is_dir = true;
+        let opts = DisplayOptions {
+            max_width: Some(20),
+            raw_names: true,
+            ..Default::default()
+        };
+        let list = node_top.get_as_string_list(false, None, &PathFilter::default(), &opts);
+        let line = list.lines().nth(1).unwrap();
+        assert!(line.contains('…') && line.ends_with(" 333B"));
+        assert!(visible_width(line) <= 20);
+    }
+
+    #[test]
+    fn largest_file_test() {
+        colored::control::set_override(false);
+        // Big dirs don't count, only files
+        let mut node_1 = Node::new(PathBuf::from("foo/bar/baz"), 300, vec![]);
+        node_1.is_dir = true;
+        let node_2 = Node::new(PathBuf::from("foo/bar/huge.iso"), 4_000, vec![]);
+        let mut node_3 = Node::new(PathBuf::from("foo/bar"), 4_300, vec![node_1, node_2]);
+        node_3.is_dir = true;
+        let node_4 = Node::new(PathBuf::from("foo/qiz"), 1_000, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 5_300, vec![node_3, node_4]);
+        node_top.is_dir = true;
+
+        let largest = node_top.largest_file().unwrap();
+        assert!(std::ptr::eq(&node_top.children[0].children[1], largest));
+        assert_eq!(
+            Some("Largest file: foo/bar/huge.iso (4.0KB)".to_string()),
+            node_top.get_largest_file_line(&DisplayOptions::default())
+        );
+        // No files at all
+        assert_eq!(None, node_top.children[0].children[0].largest_file());
+        // File on its own
+        let file = &node_top.children[1];
+        assert!(std::ptr::eq(file, file.largest_file().unwrap()));
+    }
+
+    #[test]
+    fn tiny_percentage_test() {
+        colored::control::set_override(false);
+        let opts = DisplayOptions::default();
+        let tiny = Node::new(PathBuf::from("foo/tiny"), 1, vec![]);
+        assert_eq!(
+            "tiny 1B <0.1%",
+            tiny.get_as_string_line(false, Some(1_000_000_000), &opts)
+        );
+        let empty = Node::new(PathBuf::from("foo/empty"), 0, vec![]);
+        assert_eq!(
+            "empty 0B 0.0%",
+            empty.get_as_string_line(false, Some(1_000_000_000), &opts)
+        );
+        assert_eq!("0.1%", format_percentage(0.06));
+        assert_eq!("<0.1%", format_percentage(0.04));
+    }
+
+    #[test]
+    fn list_entries_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/main.rs"), 333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/README.md"), 4_333, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/lib.rs"), 1_233, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 5_899, vec![node_1, node_2, node_3]);
+        node_top.is_dir = true;
+        let paths = PathFilter {
+            include: Some(parse_regex(r"\.rs$|^foo$").unwrap()),
+            exclude: None,
+        };
+
+        for (only_files, threshold) in [(false, None), (true, None), (false, Some(1_000))] {
+            let filter = EntryFilter {
+                only_files,
+                paths: paths.clone(),
+                ..Default::default()
+            };
+            let entries = node_top.list_entries(filter, threshold);
+            let list = node_top.get_as_string_list(
+                only_files,
+                threshold,
+                &paths,
+                &DisplayOptions::default(),
+            );
+            let lines: Vec<_> = entries
+                .iter()
+                .map(|n| n.get_as_string_line(true, None, &DisplayOptions::default()) + "\n")
+                .collect();
+            assert_eq!(list, lines.concat());
+        }
+        // Borrowed straight from the tree
+        let entries = node_top.list_entries(EntryFilter::default(), Some(4_000));
+        assert_eq!(2, entries.len());
+        assert!(std::ptr::eq(&node_top, entries[0]));
+        assert!(std::ptr::eq(&node_top.children[1], entries[1]));
+    }
+
+    #[test]
+    fn tree_lines_test() {
+        let node_1 = Node::new(PathBuf::from("foo/bar/baz"), 300, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/bar"), 400, vec![node_1]);
+        let node_3 = Node::new(PathBuf::from("foo/qiz"), 100, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 500, vec![node_2, node_3]);
+
+        let lines: Vec<_> = node_top
+            .tree_lines(None)
+            .map(|l| (l.depth, l.node.path.clone(), l.percent))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, PathBuf::from("foo"), None),
+                (1, PathBuf::from("foo/bar"), Some(80.0)),
+                (2, PathBuf::from("foo/bar/baz"), Some(75.0)),
+                (1, PathBuf::from("foo/qiz"), Some(20.0)),
+            ],
+            lines
+        );
+        let lines: Vec<_> = node_top.tree_lines(Some(200)).map(|l| l.node).collect();
+        assert_eq!(3, lines.len());
+        assert!(std::ptr::eq(&node_top.children[0].children[0], lines[2]));
+    }
+
+    #[test]
+    fn node_as_list_regex_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/main.rs"), 333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/README.md"), 4_333, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/lib.rs"), 1_233, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 5_899, vec![node_1, node_2, node_3]);
+        node_top.is_dir = true;
+
+        let filter = PathFilter {
+            include: Some(parse_regex(r".*\.rs$").unwrap()),
+            exclude: None,
+        };
+        assert_eq!(
+            "foo/main.rs 333B\nfoo/lib.rs 1.2KB\n",
+            node_top.get_as_string_list(false, None, &filter, &DisplayOptions::default())
+        );
+        let filter = PathFilter {
+            include: None,
+            exclude: Some(parse_regex(r".*\.rs$").unwrap()),
+        };
+        assert_eq!(
+            "foo/ 5.9KB\nfoo/README.md 4.3KB\n",
+            node_top.get_as_string_list(false, None, &filter, &DisplayOptions::default())
+        );
+        assert!(matches!(
+            parse_regex("foo("),
+            Err(SofiduError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn expand_template_test() {
+        let mut node = Node::new(PathBuf::from("foo/bar"), 4_333, vec![]);
+        node.is_dir = true;
+        let opts = DisplayOptions::default();
+        assert_eq!(
+            Ok("4.3KB\tfoo/bar\t43.3%".to_string()),
+            expand_template("{size}\t{path}\t{pct}", &node, Some(10_000), &opts)
+        );
+        assert_eq!(
+            Ok("{bar} 4333 true ".to_string()),
+            expand_template("{{{name}}} {bytes} {is_dir} {pct}", &node, None, &opts)
+        );
+        assert_eq!(
+            Err(SofiduError::InvalidTemplate(
+                "unknown placeholder '{foo}'".to_string()
+            )),
+            expand_template("{size} {foo}", &node, None, &opts)
+        );
+        assert!(check_template("{path").is_err());
+        assert!(check_template("{path}").is_ok());
+
+        colored::control::set_override(false);
+        let mut node_top = Node::new(PathBuf::from("foo"), 10_000, vec![node]);
+        node_top.is_dir = true;
+        let opts = DisplayOptions {
+            format: Some("{bytes},{path}".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "10000,foo\n4333,foo/bar\n",
+            node_top.get_as_string_list(false, None, &PathFilter::default(), &opts)
+        );
+    }
+
+    #[test]
+    fn node_as_list_delimiter_test() {
+        colored::control::set_override(false);
+        let node_1 = Node::new(PathBuf::from("foo/bar baz"), 4_333, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 5_000, vec![node_1]);
+        node_top.is_dir = true;
+
+        let opts = DisplayOptions {
+            delimiter: parse_delimiter("\\t"),
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo/\t5.0KB\nfoo/bar baz\t4.3KB\n",
+            node_top.get_as_string_list(false, None, &PathFilter::default(), &opts)
+        );
+        let opts = DisplayOptions {
+            machine_readable: true,
+            ..opts
+        };
+        assert_eq!(
+            "foo/\t5000\nfoo/bar baz\t4333\n",
+            node_top.get_as_string_list(false, None, &PathFilter::default(), &opts)
+        );
+    }
+
+    #[test]
+    fn node_clone_childless_test() {
+        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 4_333, vec![]);
+        let node_1 = Node::new(PathBuf::from("foo/bar"), 333, vec![node_1_1]);
+        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000_233_333, vec![]);
+        let node_top = Node::new(PathBuf::from("foo"), 3_233_333, vec![node_1, node_2]);
+
+        assert_eq!(Vec::<Node>::new(), node_top.clone_childless().children);
+    }
+
+    #[test]
+    fn sofidu_error_message_test() {
+        assert_eq!(
+            "Invalid depth provided, expected integer value, got 'foo'",
+            SofiduError::InvalidDepth("foo".to_string()).to_string()
+        );
+        assert_eq!(
+            "Depth must be 0 or greater or -1 for max depth",
+            SofiduError::DepthOutOfRange.to_string()
+        );
+        assert_eq!(
+            "Invalid file size unit: TB.\n Supported file size units: B, MB, KB, GB.",
+            SofiduError::UnknownUnit("TB".to_string()).to_string()
+        );
+        assert_eq!(
+            "Failed to parse value: 5..",
+            SofiduError::ParseValue("5..".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn parse_depth_test() {
+        assert_eq!(i32::MAX, parse_depth("-1").unwrap());
+        assert_eq!(1, parse_depth("1").unwrap());
+        assert_eq!(Err(SofiduError::DepthOutOfRange), parse_depth("-2"));
+        assert_eq!(
+            Err(SofiduError::InvalidDepth("foo".to_string())),
+            parse_depth("foo")
+        );
+    }
+
+    #[test]
+    fn max_depth_test() {
+        let args = |arguments: &str| arguments.split(' ').map(|a| a.to_string()).collect();
+        assert_eq!(3, settings_from(args("sofidu --max-depth 3 src")).depth);
+        assert_eq!(3, settings_from(args("sofidu --depth 3 src")).depth);
+        assert_eq!(i32::MAX, settings_from(args("sofidu src")).depth);
+        // --max-depth wins
+        assert_eq!(
+            2,
+            settings_from(args("sofidu -d 3 --max-depth 2 src")).depth
+        );
+
+        assert!(depths_conflict(Some(3), Some(2)));
+        assert!(!depths_conflict(Some(3), Some(3)));
+        assert!(!depths_conflict(None, Some(2)));
+        assert!(!depths_conflict(Some(3), None));
+    }
+
+    #[test]
+    fn no_recurse_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("no_recurse");
+        std::fs::create_dir_all(dir.join("sub").join("deeper")).unwrap();
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("sub").join("deeper").join("bar"), vec![1u8; 2_000]).unwrap();
+        let args = format!("sofidu --no-config -R -m --no-summary {}", dir.display());
+        let settings = settings_from(args.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(1, settings.depth);
+
+        let output = scan_and_render(&settings);
+        let lines: Vec<_> = output.lines().filter(|l| !l.is_empty()).collect();
+        // Root and its two children, but nothing deeper
+        assert_eq!(3, lines.len(), "{}", output);
+        assert!(!output.contains("deeper"));
+        // Size of `sub` still includes the file deep inside
+        let sub_line = lines.iter().find(|l| l.contains("sub")).unwrap();
+        let sub_size: u64 = sub_line.split(' ').nth(2).unwrap().parse().unwrap();
+        assert!(sub_size >= 2_000, "{}", sub_line);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_dir_blocks_test() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = test_dir("blocks");
+        std::fs::write(dir.join("foo"), vec![1u8; 10_000]).unwrap();
+        std::fs::write(dir.join("bar"), vec![1u8; 100]).unwrap();
+        let opts = WalkOptions {
+            blocks: true,
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+
+        let expected = ["foo", "bar"]
+            .iter()
+            .map(|f| dir.join(f).metadata().unwrap().blocks())
+            .sum::<u64>()
+            + dir.metadata().unwrap().blocks();
+        assert_eq!(expected, node.size);
+        for child in &node.children {
+            assert_eq!(child.path.metadata().unwrap().blocks(), child.size);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_dir_dereference_args_test() {
+        let dir = test_dir("dereference_args");
+        let target = dir.join("target");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::write(target.join("foo"), vec![1u8; 1_000]).unwrap();
+        std::fs::create_dir(target.join("inner")).unwrap();
+        std::os::unix::fs::symlink(&target, target.join("inner").join("loop")).unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let opts = WalkOptions {
+            dereference_args: true,
+            ..Default::default()
+        };
+        let node = walk_dir(&link, &opts);
+        assert!(node.is_dir);
+        let mut children: Vec<_> = node.children.iter().map(|c| c.path.clone()).collect();
+        children.sort();
+        assert_eq!(vec![link.join("foo"), link.join("inner")], children);
+        // Symlinks inside aren't followed
+        let inner = node.children.iter().find(|c| c.is_dir).unwrap();
+        assert!(inner.children.is_empty());
+
+        let node = walk_dir(&link, &WalkOptions::default());
+        assert!(!node.is_dir);
+        assert!(node.children.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn walk_dir_time_test() {
+        let dir = test_dir("time");
+        std::fs::write(dir.join("foo"), [1u8]).unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
+        assert_eq!(None, node.children[0].time);
+
+        let opts = WalkOptions {
+            time: Some(TimeKind::Modified),
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+        assert_eq!(dir.metadata().unwrap().modified().ok(), node.time);
+        assert_eq!(
+            dir.join("foo").metadata().unwrap().modified().ok(),
+            node.children[0].time
+        );
+        // Not the creation time, which doesn't change along with permissions
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            let file = dir.join("foo");
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+            let opts = WalkOptions {
+                time: Some(TimeKind::Changed),
+                ..Default::default()
+            };
+            let node = walk_dir(&dir, &opts);
+            let metadata = file.metadata().unwrap();
+            let ctime = std::time::UNIX_EPOCH
+                + std::time::Duration::new(metadata.ctime() as u64, metadata.ctime_nsec() as u32);
+            assert_eq!(Some(ctime), node.children[0].time);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sparse_sizes_test() {
+        let dir = test_dir("sparse");
+        let file = std::fs::File::create(dir.join("sparse")).unwrap();
+        // Hole, without any data written
+        file.set_len(100_000_000).unwrap();
+        std::fs::write(dir.join("dense"), vec![1u8; 8_192]).unwrap();
+
+        let (apparent, allocated) = sparse_sizes(&dir.join("sparse").metadata().unwrap()).unwrap();
+        assert_eq!(100_000_000, apparent);
+        assert!(allocated < apparent);
+        let (apparent, allocated) = sparse_sizes(&dir.join("dense").metadata().unwrap()).unwrap();
+        assert_eq!(8_192, apparent);
+        assert!(allocated >= apparent);
+
+        colored::control::set_override(false);
+        let opts = WalkOptions {
+            sparse: true,
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+        let sparse = node
+            .children
+            .iter()
+            .find(|c| c.size == 100_000_000)
+            .unwrap();
+        let opts = DisplayOptions {
+            sparse: true,
+            ..Default::default()
+        };
+        assert!(sparse
+            .get_as_string_line(false, None, &opts)
+            .starts_with("sparse 100.0MB 100.0MB (apparent) / "));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn show_both_test() {
+        colored::control::set_override(false);
+        let mut node = Node::new(PathBuf::from("foo"), 40_000_000_000, vec![]);
+        node.sparse_sizes = Some((40_000_000_000, 2_100_000_000));
+        let opts = DisplayOptions {
+            show_both: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "foo 2.1GB disk / 40.0GB apparent 50.0%",
+            node.get_as_string_line(false, Some(80_000_000_000), &opts)
+        );
+        // Sizes that aren't known leave just the size
+        node.sparse_sizes = None;
+        assert_eq!("foo 40.0GB", node.get_as_string_line(false, None, &opts));
+
+        // Dirs add up sizes of everything inside
+        let dir = test_dir("show_both");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::File::create(dir.join("sub").join("sparse"))
+            .unwrap()
+            .set_len(100_000_000)
+            .unwrap();
+        let node = walk_dir(
+            &dir,
+            &WalkOptions {
+                sparse: true,
+                ..Default::default()
+            },
+        );
+        let (apparent, allocated) = node.sparse_sizes.unwrap();
+        assert!(apparent >= 100_000_000);
+        assert!(allocated < apparent);
+        assert_eq!(
+            node.children[0].sparse_sizes.unwrap().0 + dir.metadata().unwrap().len(),
+            apparent
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_mount_point_test() {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = Path::new("src").metadata().unwrap();
+        assert!(!is_mount_point(&metadata, metadata.dev()));
+        assert!(is_mount_point(&metadata, metadata.dev() + 1));
+
+        colored::control::set_override(false);
+        let mut node = Node::new(PathBuf::from("mnt"), 4_000, vec![]);
+        node.mount_point = true;
+        assert_eq!(
+            "mnt [mount] 4.0KB",
+            node.get_as_string_line(false, None, &DisplayOptions::default())
+        );
+    }
+
+    #[test]
+    fn walk_dir_streaming_test() {
+        use std::sync::Mutex;
+        let dir = test_dir("streaming");
+        std::fs::create_dir(dir.join("foo")).unwrap();
+        std::fs::write(dir.join("foo").join("bar"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("baz"), vec![1u8; 200]).unwrap();
+
+        let entries = Mutex::new(vec![]);
+        let total = walk_dir_streaming(&dir, &WalkOptions::default(), |path, size, is_dir| {
+            entries
+                .lock()
+                .unwrap()
+                .push((path.to_path_buf(), size, is_dir))
+        });
+        let mut entries = entries.into_inner().unwrap();
+        entries.sort();
+
+        // Same entries as in the tree
+        let node = walk_dir(&dir, &WalkOptions::default());
+        assert_eq!(node.size, total);
+        let mut expected: Vec<_> = node
+            .flatten()
+            .into_iter()
+            .map(|n| (n.path, n.size, n.is_dir))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, entries);
+        assert_eq!(4, entries.len());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    Ok(depth)
-}
 
-/// Converts file size in bytes to human readable string
-pub fn file_size_to_str(size: u64) -> String {
-    let exp = (size as f32).log10() as u32;
-    match exp {
-        0..=2 => size.to_string() + "B",
-        3..=5 => {
-            format!("{:.1}KB", size as f32 / 1000.0)
-        }
-        6..=8 => {
-            format!("{:.1}MB", size as f32 / 1000000.0)
-        }
-        9.. => {
-            format!("{:.1}GB", size as f32 / 1000000000.0)
+    #[cfg(unix)]
+    #[test]
+    fn walk_dir_max_symlink_depth_test() {
+        let dir = test_dir("max_symlink_depth");
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+        let opts = WalkOptions {
+            follow_symlinks: true,
+            max_symlink_depth: 3,
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+
+        // Cycle is followed exactly 3 times
+        let mut levels = 0;
+        let mut current = &node;
+        while let Some(next) = current.children.iter().find(|c| c.is_dir) {
+            levels += 1;
+            current = next;
         }
+        assert_eq!(3, levels);
+        assert!(node.size >= 400);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-/// Converts human readable string to number of bytes
-pub fn str_to_file_size(input: &str) -> Result<u64, String> {
-    let value;
-    let mut exponent = 0;
-    let pos = input.find(|c: char| c.is_ascii_alphabetic());
-    if let Some(pos) = pos {
-        let (value_s, unit) = input.split_at(pos);
-        exponent = match unit.to_uppercase().as_str() {
-            "G" | "GB" => 3,
-            "M" | "MB" => 2,
-            "K" | "KB" => 1,
-            "" | "B" => 0,
-            u => {
-                return Err(format!(
-                    "Invalid file size unit: {}.\n Supported file size units: B, MB, KB, GB.",
-                    u
-                ))
-            }
+    #[cfg(unix)]
+    #[test]
+    fn walk_dir_follow_under_test() {
+        let dir = test_dir("follow_under");
+        let outside = test_dir("follow_under_outside");
+        std::fs::create_dir(dir.join("inside")).unwrap();
+        std::fs::write(dir.join("inside").join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(outside.join("bar"), vec![1u8; 100]).unwrap();
+        std::os::unix::fs::symlink(dir.join("inside"), dir.join("link_in")).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("link_out")).unwrap();
+        let opts = WalkOptions {
+            follow_under: Some(dir.canonicalize().unwrap()),
+            ..Default::default()
         };
-        if let Ok(v) = value_s.parse::<f32>() {
-            value = v;
-        } else {
-            return Err(format!("Failed to parse value: {}", value_s));
-        }
-    } else if let Ok(v) = input.parse::<f32>() {
-        value = v;
-    } else {
-        return Err(format!("Failed to parse value: {}", input));
+        let node = walk_dir(&dir, &opts);
+
+        let link_in = node.children.iter().find(|c| c.path == dir.join("link_in"));
+        assert!(link_in.is_some_and(|l| l.is_dir && l.file_count == 1));
+        assert!(!node.children.iter().any(|c| c.path == dir.join("link_out")));
+        assert!(opts.follows(&dir.join("link_in")));
+        assert!(!opts.follows(&dir.join("link_out")));
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
     }
 
-    Ok((value * 1000u64.pow(exponent) as f32) as u64)
-}
+    #[test]
+    fn walk_dir_skip_recent_test() {
+        use std::time::{Duration, SystemTime};
+        let dir = test_dir("skip_recent");
+        std::fs::create_dir(dir.join("old")).unwrap();
+        std::fs::create_dir(dir.join("recent")).unwrap();
+        std::fs::write(dir.join("old").join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("recent").join("bar"), vec![1u8; 100]).unwrap();
+        let day_ago = SystemTime::now() - Duration::from_secs(24 * 3600);
+        std::fs::File::open(dir.join("old"))
+            .unwrap()
+            .set_modified(day_ago)
+            .unwrap();
+        let opts = WalkOptions {
+            skip_recent: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let node = walk_dir(&dir, &opts);
+
+        assert!(node.children.iter().any(|c| c.path == dir.join("old")));
+        assert!(!node.children.iter().any(|c| c.path == dir.join("recent")));
+        assert_eq!(1, node.file_count);
+        let all = walk_dir(&dir, &WalkOptions::default());
+        assert_eq!(2, all.file_count);
+        assert!(all.size > node.size);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-#[cfg(test)]
-mod lib_tests {
-    use super::*;
     #[test]
-    fn file_size_to_str_test() {
-        assert_eq!("1B", file_size_to_str(1));
-        assert_eq!("999B", file_size_to_str(999));
-        assert_eq!("1.0KB", file_size_to_str(1_000));
-        assert_eq!("2.1KB", file_size_to_str(2_100));
-        assert_eq!("1.0MB", file_size_to_str(1_000_000));
-        assert_eq!("4.2MB", file_size_to_str(4_233_333));
-        assert_eq!("5.0GB", file_size_to_str(5_000_000_000));
+    fn only_path_test() {
+        let dir = test_dir("only_path");
+        for sub in ["a/src/deeper", "a/doc", "b", "src"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::fs::write(dir.join("a").join("src").join("deeper").join("foo"), "foo").unwrap();
+        std::fs::write(dir.join("a").join("doc").join("bar"), "bar").unwrap();
+        std::fs::write(dir.join("b").join("baz"), "baz").unwrap();
+        std::fs::write(dir.join("notes"), "notes").unwrap();
+        let arguments = format!("sofidu --no-config --only-path **/src {}", dir.display());
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        let node = scan(&settings);
+
+        let mut paths: Vec<_> = node
+            .flatten()
+            .into_iter()
+            .map(|n| n.path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        paths.sort();
+        let expected: Vec<PathBuf> = ["", "a", "a/src", "a/src/deeper", "a/src/deeper/foo", "src"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(expected, paths);
+        // Sizes still include everything
+        assert_eq!(walk_dir(&dir, &settings.walk_options()).size, node.size);
+        // Low memory mode writes the tree before it could be filtered
+        let arguments = ["sofidu", "--low-memory", "--only-path", "**/src"];
+        assert!(AppSettings::app().get_matches_from_safe(arguments).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn str_to_file_size_test() {
-        assert_eq!(1, str_to_file_size("1").unwrap());
-        assert_eq!(1, str_to_file_size("1.1").unwrap());
-        assert_eq!(999, str_to_file_size("999B").unwrap());
-        assert_eq!(1_000, str_to_file_size("1KB").unwrap());
-        assert_eq!(1_000, str_to_file_size("1K").unwrap());
-        assert_eq!(1_000, str_to_file_size("1.0KB").unwrap());
-        assert_eq!(1_000, str_to_file_size("1.0K").unwrap());
-        assert_eq!(4_200, str_to_file_size("4.2KB").unwrap());
-        assert_eq!(1_000_000, str_to_file_size("1.0MB").unwrap());
-        assert_eq!(1_000_000, str_to_file_size("1.0M").unwrap());
-        assert_eq!(4_200_000, str_to_file_size("4.2MB").unwrap());
-        assert_eq!(5_000_000_000, str_to_file_size("5.0GB").unwrap());
-        assert_eq!(5_000_000_000, str_to_file_size("5.0G").unwrap());
-        assert!(str_to_file_size("5..GB").is_err());
-        assert!(str_to_file_size("5..TB").is_err());
-        assert!(str_to_file_size("").is_err());
+    fn threshold_tree_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("threshold_tree");
+        std::fs::create_dir_all(dir.join("big_inside")).unwrap();
+        std::fs::create_dir_all(dir.join("small_inside")).unwrap();
+        std::fs::write(dir.join("big_inside").join("big"), vec![1u8; 50_000]).unwrap();
+        std::fs::write(dir.join("big_inside").join("tiny"), "tiny").unwrap();
+        std::fs::write(dir.join("small_inside").join("tiny"), "tiny").unwrap();
+        let arguments = format!("sofidu --no-config -t 20KB {}", dir.display());
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        let output = scan_and_render(&settings);
+
+        // Root, the dir leading to the big file and the big file itself
+        assert_eq!(3, output.lines().filter(|l| !l.is_empty()).count());
+        assert!(output.contains("big_inside/"));
+        assert!(!output.contains("small_inside"));
+        assert!(!output.contains("tiny"));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn node_sort_test() {
-        let node_1 = Node::new(PathBuf::from("foo"), 0, vec![]);
-        let node_2 = Node::new(PathBuf::from("bar"), 1, vec![]);
-        let node_3 = Node::new(PathBuf::from("baz"), 100, vec![]);
-        let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
+    fn recompute_size_test() {
+        let dir = test_dir("recompute_size");
+        std::fs::create_dir_all(dir.join("sub").join("deeper")).unwrap();
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("sub").join("bar"), vec![1u8; 200]).unwrap();
+        std::fs::write(dir.join("sub").join("baz"), vec![1u8; 300]).unwrap();
+        std::fs::write(dir.join("sub").join("deeper").join("qiz"), vec![1u8; 400]).unwrap();
+        let find_sub = |node: &Node| {
+            let sub = node.children.iter().find(|c| c.path == dir.join("sub"));
+            sub.unwrap().clone_childless()
+        };
 
-        let mut node = Node::new(PathBuf::from("quaz"), 0, children);
+        let mut node = walk_dir(&dir, &WalkOptions::default());
+        let size = node.size;
+        assert_eq!(size, node.recompute_size());
+        // Only the tree matters, not what's on the disk now
+        std::fs::remove_file(dir.join("foo")).unwrap();
+        let sub = node.children.iter_mut().find(|c| c.path == dir.join("sub"));
+        let sub = sub.unwrap();
+        let sub_size = sub.size;
+        let baz = sub
+            .children
+            .iter()
+            .find(|c| c.path == dir.join("sub").join("baz"));
+        let baz_size = baz.unwrap().size;
+        sub.children
+            .retain(|c| c.path != dir.join("sub").join("baz"));
+        // Stale until recomputed
+        assert_eq!(size, node.size);
+        assert_eq!(size - baz_size, node.recompute_size());
+        assert_eq!(size - baz_size, node.size);
+        assert_eq!(sub_size - baz_size, find_sub(&node).size);
+        assert_eq!(
+            (2, 1),
+            (find_sub(&node).file_count, find_sub(&node).dir_count)
+        );
+        assert_eq!(3, node.file_count);
 
-        node.sort();
+        // Without any children left it's just the dir itself
+        let sub = node.children.iter_mut().find(|c| c.path == dir.join("sub"));
+        sub.unwrap().children.clear();
+        node.recompute_size();
+        let own_size = dir.join("sub").metadata().unwrap().len();
+        assert_eq!(own_size, find_sub(&node).size);
+        assert_eq!(
+            (0, 0),
+            (find_sub(&node).file_count, find_sub(&node).dir_count)
+        );
 
-        let children_out = vec![node_3, node_2, node_1];
-        assert_eq!(children_out, node.children);
+        // Dirs cut off by depth keep what's inside
+        let mut node = walk_dir(
+            &dir,
+            &WalkOptions {
+                depth: 1,
+                ..Default::default()
+            },
+        );
+        let size = node.size;
+        assert_eq!(size, node.recompute_size());
+        assert_eq!(3, find_sub(&node).file_count);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn node_sort_test_recursive() {
-        let node_1 = Node::new(PathBuf::from("foo"), 0, vec![]);
-        let node_2 = Node::new(PathBuf::from("bar"), 1, vec![]);
-        let mut node_3 = Node::new(
-            PathBuf::from("baz"),
-            100,
-            vec![node_1.clone_childless(), node_2.clone_childless()],
+    fn walk_dir_abort_test() {
+        use std::time::Duration;
+        let dir = test_dir("walk_abort");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("foo"), vec![1u8; 100]).unwrap();
+        let abort = Arc::new(AtomicBool::new(false));
+        let opts = WalkOptions {
+            abort: Some(Arc::clone(&abort)),
+            ..Default::default()
+        };
+        assert!(!opts.aborted());
+        assert_eq!(1, walk_dir(&dir, &opts).file_count);
+
+        // Nothing inside is walked after aborting, just the root itself
+        abort.store(true, Ordering::Relaxed);
+        assert!(opts.aborted());
+        let node = walk_dir(&dir, &opts);
+        assert!(node.children.is_empty());
+        assert_eq!(0, node.file_count);
+
+        let flag = timeout_flag(Duration::from_millis(50));
+        assert!(!flag.load(Ordering::Relaxed));
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(flag.load(Ordering::Relaxed));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_root_test() {
+        colored::control::set_override(false);
+        let settings = settings_from(vec!["sofidu".to_string(), "Cargo.toml".to_string()]);
+        assert_eq!(PathBuf::from("Cargo.toml"), settings.path);
+
+        let node = walk_dir(&settings.path, &WalkOptions::default());
+        assert!(!node.is_dir);
+        assert!(node.children.is_empty());
+        assert_eq!(settings.path.metadata().unwrap().len(), node.size);
+        assert_eq!(
+            format!("Cargo.toml {}\n", file_size_to_str(node.size, 1)),
+            node.get_as_string_tree(0, None, None, &DisplayOptions::default())
+                .0
         );
+    }
+
+    #[test]
+    fn parse_precision_test() {
+        assert_eq!(0, parse_precision("0").unwrap());
+        assert_eq!(2, parse_precision("2").unwrap());
+        assert_eq!(
+            Err(SofiduError::InvalidPrecision("-1".to_string())),
+            parse_precision("-1")
+        );
+        assert!(matches!(
+            parse_precision("foo"),
+            Err(SofiduError::InvalidPrecision(_))
+        ));
+        assert_eq!(6, parse_precision("6").unwrap());
+        assert_eq!(
+            Err(SofiduError::InvalidPrecision("99".to_string())),
+            parse_precision("99")
+        );
+    }
 
+    #[test]
+    fn sort_by_name_by_default_test() {
+        let node_1 = Node::new(PathBuf::from("quaz/foo"), 0, vec![]);
+        let node_2 = Node::new(PathBuf::from("quaz/bar"), 1, vec![]);
+        let node_3 = Node::new(PathBuf::from("quaz/baz"), 100, vec![]);
         let children = vec![node_1.clone(), node_3.clone(), node_2.clone()];
-        node_3.sort();
+        let node = Node::new(PathBuf::from("quaz"), 0, children.clone());
 
-        let mut node = Node::new(PathBuf::from("quaz"), 0, children);
+        let settings = settings_from(vec!["sofidu".to_string(), "src".to_string()]);
+        let mut sorted = node.clone();
+        settings.apply_sort(&mut sorted);
+        assert_eq!(
+            vec![node_2.clone(), node_3.clone(), node_1.clone()],
+            sorted.children
+        );
 
-        let children_out = vec![node_3, node_2, node_1];
-        node.sort();
+        let settings = settings_from(
+            "sofidu --unsorted src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        let mut unsorted = node;
+        settings.apply_sort(&mut unsorted);
+        assert_eq!(children, unsorted.children);
+    }
 
-        assert_eq!(children_out, node.children);
+    #[test]
+    fn quiet_test() {
+        let settings = settings_from(
+            "sofidu --no-config -q src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert!(settings.quiet);
+        assert!(settings.walk_options().quiet);
+
+        let mut output = vec![];
+        write_note(&mut output, false, format_args!("Note: {}", 1)).unwrap();
+        assert_eq!(b"Note: 1\n".to_vec(), output);
+        output.clear();
+        write_note(&mut output, true, format_args!("Note: {}", 1)).unwrap();
+        assert!(output.is_empty());
     }
 
     #[test]
-    fn node_as_string_line_test() {
-        // Disable coloring
-        colored::control::set_override(false);
-        let node = Node::new(PathBuf::from("foo"), 3_233_333, vec![]);
-        assert_eq!("foo 3.2MB", node.get_as_string_line(false, false, None));
-        let node = Node::new(PathBuf::from("src"), 3_233_333, vec![]);
-        assert_eq!("src/ 3.2MB", node.get_as_string_line(false, false, None));
-        let node = Node::new(PathBuf::from("src/main.rs"), 3_233_333, vec![]);
+    fn error_json_test() {
+        let error = parse_depth("foo").unwrap_err();
         assert_eq!(
-            "src/main.rs 3.2MB",
-            node.get_as_string_line(true, false, None)
+            r#"{"error":"Invalid depth provided, expected integer value, got 'foo'","kind":"invalid_depth"}"#,
+            error_json(error.kind(), format_args!("{}", error))
+        );
+        assert_eq!(
+            r#"{"error":"a \"quoted\"\nline","kind":"io"}"#,
+            error_json("io", format_args!("a \"quoted\"\nline"))
+        );
+
+        // Clap's own errors, without usage after them
+        let error = AppSettings::app()
+            .get_matches_from_safe(["sofidu", "--watch", "1", "--save", "foo", "src"])
+            .unwrap_err();
+        assert_eq!(
+            "The argument '--save <file>' cannot be used with '--watch <seconds>'",
+            clap_error_message(&error)
+        );
+        // Names of what's missing are on the following lines
+        let error = AppSettings::app()
+            .get_matches_from_safe(["sofidu", "--match", "x", "src"])
+            .unwrap_err();
+        assert_eq!(clap::ErrorKind::MissingRequiredArgument, error.kind);
+        assert_eq!(
+            "The following required arguments were not provided: --list",
+            clap_error_message(&error)
         );
+        assert_eq!("red", strip_escapes("\x1b[1;31mred\x1b[0m"));
     }
 
     #[test]
-    fn node_as_string_line_test_machine_readable() {
-        // Disable coloring
-        colored::control::set_override(false);
-        let node = Node::new(PathBuf::from("foo"), 3_233_333, vec![]);
-        assert_eq!("foo 3233333", node.get_as_string_line(false, true, None));
-        let node = Node::new(PathBuf::from("foo"), 3, vec![]);
-        assert_eq!("foo 3", node.get_as_string_line(false, true, None));
+    fn validate_path_test() {
+        assert_eq!(
+            Err(SofiduError::InvalidPath("does/not/exist".to_string())),
+            validate_path(Path::new("does/not/exist"))
+        );
+        assert_eq!(Ok(()), validate_path(Path::new("Cargo.toml")));
+        assert_eq!(Ok(()), validate_path(Path::new("src")));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn node_as_string_tree_test() {
-        colored::control::set_override(false);
-        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
-        let node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
-        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
-        let node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
-        let node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+    fn validate_unreadable_path_test() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("unreadable");
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't apply to root
+        if dir.read_dir().is_err() {
+            assert!(matches!(
+                validate_path(&dir),
+                Err(SofiduError::UnreadablePath(path, _)) if path == dir.display().to_string()
+            ));
+        }
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
+    #[test]
+    fn config_to_args_test() {
         assert_eq!(
-            "foo 3.7GB\n| bar 4.3KB 0.0%\n| | biz 333B 7.7%\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
-            node_top.get_as_string_tree(0, None, false, None).0
+            vec![
+                vec!["--depth", "3"],
+                vec!["--machine-readable"],
+                vec!["--threshold", "1KB"]
+            ],
+            config_to_args(
+                "depth = 3\nmachine-readable = true\nsort = false\nthreshold = \"1KB\"\n"
+            )
+            .unwrap()
         );
+        assert!(config_to_args("depth = [").is_err());
+    }
+
+    #[test]
+    fn env_to_args_test() {
+        let vars = [
+            ("SOFIDU_SORT", "true"),
+            ("HOME", "/root"),
+            ("SOFIDU_MAX_DEPTH", "3"),
+            ("SOFIDU_TEST_HELPER", "1"),
+        ];
+        let vars = vars.map(|(k, v)| (k.to_string(), v.to_string()));
         assert_eq!(
-            "foo 3.7GB\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
-            node_top
-                .get_as_string_tree(0, Some(1_000_000), false, None)
-                .0
+            vec![vec!["--max-depth", "3"], vec!["--sort"]],
+            env_to_args(vars)
         );
+
+        let from_args = |arguments: &str, env: &[(&str, &str)]| {
+            let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+            AppSettings::from_args_with_env(
+                arguments.split(' ').map(|a| a.to_string()).collect(),
+                env_to_args(env),
+                false,
+            )
+        };
+        let settings = from_args("sofidu --no-config src", &[("SOFIDU_DEPTH", "3")]);
+        assert_eq!(3, settings.depth);
+        let settings = from_args("sofidu --no-config -d 5 src", &[("SOFIDU_DEPTH", "3")]);
+        assert_eq!(5, settings.depth);
+        let settings = from_args("sofidu --no-config src", &[("SOFIDU_SORT", "false")]);
+        assert!(!settings.sort);
+        // Unrelated variables don't matter, and the CLI wins over conflicting ones
+        let settings = from_args("sofidu --no-config src", &[("SOFIDU_TEST_HELPER", "1")]);
         assert_eq!(
-            "foo 3.7GB\n| bar 4.3KB 0.0%\n| baz 2.2MB 0.1%\n| | qiz 1.2MB 55.2%\n",
-            node_top.get_as_string_tree(0, Some(4_000), false, None).0
+            from_args("sofidu --no-config src", &[]).depth,
+            settings.depth
         );
+        let settings = from_args(
+            "sofidu --no-config --unsorted src",
+            &[("SOFIDU_SORT", "true")],
+        );
+        assert!(settings.unsorted);
+        assert!(!settings.sort);
+
+        // Above the config file
+        let dir = test_dir("env_config");
+        let config = dir.join("config.toml");
+        std::fs::write(&config, "depth = 4\nsort = true\n").unwrap();
+        let arguments = format!("sofidu --config {} src", config.display());
+        let settings = from_args(&arguments, &[("SOFIDU_DEPTH", "3")]);
+        assert_eq!(3, settings.depth);
+        assert!(settings.sort);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn node_flatten_test() {
-        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 4_333, vec![]);
-        let node_1 = Node::new(PathBuf::from("foo/bar"), 333, vec![node_1_1.clone()]);
-        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000_233_333, vec![]);
-        let node_top = Node::new(
-            PathBuf::from("foo"),
-            3_233_333,
-            vec![node_1.clone(), node_2.clone()],
+    fn config_file_test() {
+        let dir = test_dir("config");
+        let config = dir.join("config.toml");
+        std::fs::write(&config, "depth = 3\nsort = true\n").unwrap();
+        let config = config.to_str().unwrap();
+
+        let arguments = format!("sofidu --config {} src", config);
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(3, settings.depth);
+        assert!(settings.sort);
+
+        let arguments = format!("sofidu -d 5 --config {} src", config);
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(5, settings.depth);
+        assert!(settings.sort);
+
+        // Conflicting flag given overrides the config's one instead of failing
+        let arguments = format!("sofidu --unsorted --config {} src", config);
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert!(settings.unsorted);
+        assert!(!settings.sort);
+        assert_eq!(3, settings.depth);
+
+        let arguments = "sofidu --no-config -d 5 src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert!(!settings.sort);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_size_range_test() {
+        assert_eq!(
+            Ok((100_000_000, 1_000_000_000)),
+            parse_size_range("100MB..1GB", 1000)
         );
-        let result = vec![
-            node_top.clone_childless(),
-            node_1.clone_childless(),
-            node_1_1.clone_childless(),
-            node_2.clone_childless(),
-        ];
-        assert_eq!(result, node_top.flatten());
+        assert_eq!(Ok((1024, 1024)), parse_size_range("1KB..1KB", 1024));
+        for input in [
+            "100MB",
+            "1GB..100MB",
+            "..1GB",
+            "1GB..",
+            "foo..1GB",
+            "1KB...2KB",
+        ] {
+            assert!(matches!(
+                parse_size_range(input, 1000),
+                Err(SofiduError::InvalidSizeRange(i, _)) if i == input
+            ));
+        }
     }
 
     #[test]
-    fn node_as_list_test() {
+    fn size_range_list_test() {
         colored::control::set_override(false);
-        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 333, vec![]);
-        let mut node_1 = Node::new(PathBuf::from("foo/bar"), 4_333, vec![node_1_1]);
-        node_1.is_dir = true;
-        let node_2_1 = Node::new(PathBuf::from("foo/baz/qiz"), 1_233_333, vec![]);
-        let mut node_2 = Node::new(PathBuf::from("foo/baz"), 2_233_333, vec![node_2_1]);
-        node_2.is_dir = true;
-        let mut node_top = Node::new(PathBuf::from("foo"), 3_666_233_333, vec![node_1, node_2]);
+        let node_1 = Node::new(PathBuf::from("foo/small"), 333, vec![]);
+        let node_2 = Node::new(PathBuf::from("foo/medium"), 4_333, vec![]);
+        let node_3 = Node::new(PathBuf::from("foo/big"), 12_333, vec![]);
+        let mut node_top = Node::new(PathBuf::from("foo"), 16_999, vec![node_1, node_2, node_3]);
         node_top.is_dir = true;
 
+        let settings = settings_from(
+            "sofidu --no-config -l --size-between 1KB..12.333KB src"
+                .split(' ')
+                .map(|a| a.to_string())
+                .collect(),
+        );
+        assert_eq!(Some((1_000, 12_333)), settings.size_range);
         assert_eq!(
-            "foo/ 3.7GB\nfoo/bar/ 4.3KB\nfoo/bar/biz 333B\nfoo/baz/ 2.2MB\nfoo/baz/qiz 1.2MB\n",
-            node_top.get_as_string_list(false, None, false)
+            "foo/medium 4.3KB\nfoo/big 12.3KB\n",
+            node_top.get_as_string_list(
+                false,
+                None,
+                &PathFilter::default(),
+                &settings.display_options()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_interval_test() {
+        use std::time::Duration;
+        assert_eq!(Ok(Duration::from_secs(2)), parse_interval("2"));
+        assert_eq!(Ok(Duration::from_millis(500)), parse_interval("0.5"));
+        for input in ["0", "-1", "foo", "inf"] {
+            assert_eq!(
+                Err(SofiduError::InvalidInterval(input.to_string())),
+                parse_interval(input)
+            );
+        }
+
+        let arguments = "sofidu --watch 3 src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(Some(Duration::from_secs(3)), settings.watch);
+        // Files are only written once, not on every refresh
+        let arguments = ["sofidu", "--watch", "3", "--dot", "tree.dot", "src"];
+        assert!(AppSettings::app().get_matches_from_safe(arguments).is_err());
+    }
+
+    #[test]
+    fn scan_and_render_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("scan_and_render");
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        let arguments = format!("sofidu -l -m --no-config {}", dir.to_str().unwrap());
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+
+        // Same output every time, if nothing changes
+        let output = scan_and_render(&settings);
+        assert!(output.contains(&format!("{} 100\n", dir.join("foo").display())));
+        assert!(output.contains("\nTOTAL\t"));
+        assert_eq!(output, scan_and_render(&settings));
+
+        std::fs::write(dir.join("bar"), vec![1u8; 100]).unwrap();
+        assert!(
+            scan_and_render(&settings).contains(&format!("{} 100\n", dir.join("bar").display()))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn low_memory_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("low_memory");
+        std::fs::create_dir_all(dir.join("foo").join("deeper")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::write(dir.join("foo").join("bar"), vec![1u8; 2_000]).unwrap();
+        std::fs::write(dir.join("foo").join("deeper").join("baz"), vec![1u8; 3_000]).unwrap();
+        std::fs::write(dir.join("qiz"), vec![1u8; 100]).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        for arguments in [
+            format!("sofidu --no-config {}", dir),
+            format!("sofidu --no-config -m -d 1 --bars {}", dir),
+            format!("sofidu --no-config -t 1KB --balance {}", dir),
+            format!(
+                "sofidu --no-config --max-children 1 --no-total-in-root {}",
+                dir
+            ),
+        ] {
+            let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+            let mut expected = vec![];
+            render(&mut expected, &scan(&settings), None, &settings).unwrap();
+            let mut output = vec![];
+            assert!(render_low_memory(&mut output, &settings).unwrap());
+            assert_eq!(String::from_utf8(expected), String::from_utf8(output));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compare_test() {
+        let (a, b) = (test_dir("compare_a"), test_dir("compare_b"));
+        for dir in [&a, &b] {
+            std::fs::create_dir(dir.join("sub")).unwrap();
+            std::fs::write(dir.join("same"), vec![1u8; 100]).unwrap();
+        }
+        std::fs::write(a.join("gone"), vec![1u8; 100]).unwrap();
+        std::fs::write(a.join("sub").join("changed"), vec![1u8; 100]).unwrap();
+        std::fs::write(b.join("sub").join("changed"), vec![1u8; 300]).unwrap();
+        std::fs::write(b.join("sub").join("added"), vec![1u8; 50]).unwrap();
+
+        let arguments = format!(
+            "sofidu --no-config --compare {} {}",
+            a.to_str().unwrap(),
+            b.to_str().unwrap()
         );
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        let snapshot = load_diff_snapshot(&settings).unwrap();
+        let changes: std::collections::HashMap<PathBuf, diff::Change> =
+            diff::diff_trees(&snapshot, &scan(&settings))
+                .into_iter()
+                .map(|e| (e.path, e.change))
+                .collect();
+
         assert_eq!(
-            "foo/bar/biz 333B\nfoo/baz/qiz 1.2MB\n",
-            node_top.get_as_string_list(true, None, false)
+            Some(&diff::Change::Added(50)),
+            changes.get(Path::new("sub/added"))
+        );
+        assert_eq!(
+            Some(&diff::Change::Removed(100)),
+            changes.get(Path::new("gone"))
+        );
+        assert_eq!(
+            Some(&diff::Change::Resized { old: 100, new: 300 }),
+            changes.get(Path::new("sub/changed"))
         );
+        assert_eq!(None, changes.get(Path::new("same")));
+        std::fs::remove_dir_all(&a).unwrap();
+        std::fs::remove_dir_all(&b).unwrap();
     }
 
     #[test]
-    fn node_clone_childless_test() {
-        let node_1_1 = Node::new(PathBuf::from("foo/bar/biz"), 4_333, vec![]);
-        let node_1 = Node::new(PathBuf::from("foo/bar"), 333, vec![node_1_1]);
-        let node_2 = Node::new(PathBuf::from("foo/baz"), 3_000_233_333, vec![]);
-        let node_top = Node::new(PathBuf::from("foo"), 3_233_333, vec![node_1, node_2]);
+    fn render_no_entries_test() {
+        colored::control::set_override(false);
+        let dir = test_dir("render_no_entries");
+        std::fs::write(dir.join("foo"), vec![1u8; 100]).unwrap();
+        let node = walk_dir(&dir, &WalkOptions::default());
 
-        assert_eq!(Vec::<Node>::new(), node_top.clone_childless().children);
+        for flags in ["-l -s -t 1000GB", "-l -s -r -t 1000GB"] {
+            let arguments = format!("sofidu {} --no-config {}", flags, dir.to_str().unwrap());
+            let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+            let mut output = vec![];
+            assert!(!render(&mut output, &node, None, &settings).unwrap());
+            assert_eq!(b"\n".to_vec(), output);
+        }
+        let arguments = format!("sofidu -l -s --no-config {}", dir.to_str().unwrap());
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert!(render(&mut vec![], &node, None, &settings).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn parse_depth_test() {
-        assert_eq!(i32::MAX, parse_depth("-1").unwrap());
-        assert_eq!(1, parse_depth("1").unwrap());
-        assert!(parse_depth("-2").is_err());
-        assert!(parse_depth("foo").is_err());
+    fn exclude_from_test() {
+        let dir = test_dir("exclude_from");
+        let patterns = dir.join("patterns");
+        std::fs::write(&patterns, "# build output\ntarget\n\n  *.log\n").unwrap();
+        assert_eq!(
+            Ok(vec!["target".to_string(), "*.log".to_string()]),
+            read_exclude_from(&patterns)
+        );
+
+        // Merged with the inline ones
+        let arguments = format!(
+            "sofidu --exclude *.tmp --exclude-from {} src",
+            patterns.to_str().unwrap()
+        );
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(
+            vec!["*.tmp", "target", "*.log"],
+            settings
+                .exclude
+                .iter()
+                .map(|p| p.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // Excluded entries aren't walked
+        std::fs::create_dir(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target").join("foo"), vec![1u8; 100]).unwrap();
+        std::fs::write(dir.join("bar.log"), vec![1u8; 100]).unwrap();
+        let node = walk_dir(&dir, &settings.walk_options());
+        assert_eq!(
+            vec![dir.join("patterns")],
+            node.children
+                .iter()
+                .map(|c| c.path.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            read_exclude_from(&dir.join("missing")),
+            Err(SofiduError::InvalidExcludeFile(_, _))
+        ));
+        assert!(matches!(parse_glob("[a"), Err(SofiduError::InvalidGlob(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
     fn parse_arguments_test() {
-        let arguments = "sofidu -d 10 -s -r -l -m -f -t 1gb src";
-        let settings =
-            AppSettings::from_args(arguments.split(' ').map(|a| a.to_string()).collect());
+        let arguments = "sofidu -d 10 -s -r -l -m -f -t 1gb -b -L -H src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
         assert_eq!(10, settings.depth);
         assert!(settings.sort);
         assert!(settings.reverse);
@@ -633,6 +7695,27 @@ mod lib_tests {
         assert!(settings.machine);
         assert!(settings.only_files);
         assert_eq!(Some(1_000_000_000), settings.threshold);
+        assert!(settings.blocks);
+        assert!(settings.follow_symlinks);
+        assert!(settings.dereference_args);
+        assert_eq!(" ", settings.delimiter);
+        assert_eq!(1, settings.precision);
+        assert!(!settings.iec_input);
+
+        let arguments = "sofidu -t 1gb --iec-input src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert!(settings.iec_input);
+        assert_eq!(Some(1_073_741_824), settings.threshold);
         assert_eq!(PathBuf::from("src"), settings.path);
+
+        let arguments = "sofidu -ll src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert!(settings.list);
+        assert!(settings.long);
+        assert_eq!(PctMetric::Size, settings.pct_metric);
+
+        let arguments = "sofidu --pct-metric count src";
+        let settings = settings_from(arguments.split(' ').map(|a| a.to_string()).collect());
+        assert_eq!(PctMetric::Count, settings.pct_metric);
     }
 }
@@ -1,32 +1,142 @@
 extern crate sofidu;
 
 use std::env::args;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
     // Parse arguments
     let settings = sofidu::AppSettings::from_args(args().collect());
 
+    if settings.check {
+        match sofidu::validate_path(&settings.path) {
+            Ok(()) => println!("OK"),
+            Err(e) => {
+                sofidu::exit_with_error(settings.json_errors, e.kind(), format_args!("{}", e))
+            }
+        }
+        return;
+    }
+
+    if let Some(interval) = settings.watch {
+        // Runs until interrupted, Ctrl-C just ends the process
+        let mut previous = None;
+        loop {
+            let (node, output) = sofidu::scan_and_render_since(&settings, previous.as_ref());
+            previous = Some(node);
+            // Clear the screen and move to the top before redrawing
+            print!(
+                "\x1b[2J\x1b[HEvery {:?}: {}\t{}\n\n{}",
+                interval,
+                settings.path.display(),
+                current_time(),
+                output
+            );
+            if let Err(e) = std::io::stdout().flush() {
+                handle_write_error(e, settings.json_errors);
+                return;
+            }
+            std::thread::sleep(interval);
+        }
+    }
+
+    if settings.low_memory {
+        let stdout = std::io::stdout();
+        let mut stdout = BufWriter::new(stdout.lock());
+        if let Err(e) =
+            sofidu::render_low_memory(&mut stdout, &settings).and_then(|_| stdout.flush())
+        {
+            handle_write_error(e, settings.json_errors);
+        }
+        return;
+    }
+
     // Do the magic
-    let mut node = sofidu::walk_dir(&settings.path, settings.depth, false);
+    let node = sofidu::scan(&settings);
 
-    if settings.sort {
-        node.sort();
+    if let Some(path) = &settings.save {
+        if let Err(e) = sofidu::diff::save_snapshot(&node, path) {
+            sofidu::exit_with_error(
+                settings.json_errors,
+                "snapshot_save",
+                format_args!("Failed to save snapshot {}: {}", path.display(), e),
+            );
+        }
+    }
+    if let Some(path) = &settings.dot {
+        let result = std::fs::File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            node.write_dot(&mut writer, &settings.display_options())?;
+            writer.flush()
+        });
+        if let Err(e) = result {
+            sofidu::exit_with_error(
+                settings.json_errors,
+                "dot_save",
+                format_args!("Failed to write DOT graph {}: {}", path.display(), e),
+            );
+        }
     }
-    let mut output = if settings.list {
-        // Display as list
-        node.get_as_string_list(settings.only_files, settings.threshold, settings.machine)
+    let snapshot = sofidu::load_diff_snapshot(&settings);
+    let stdout = std::io::stdout();
+    let mut stdout = BufWriter::new(stdout.lock());
+    let result = sofidu::render(&mut stdout, &node, snapshot.as_ref(), &settings)
+        .and_then(|any_entries| stdout.flush().map(|_| any_entries));
+    match result {
+        // Not on stdout, so scripts just see empty output
+        // (no changes since the snapshot isn't about filters though)
+        Ok(false) if snapshot.is_none() => sofidu::note(
+            settings.quiet,
+            format_args!("{}", empty_output_note(&settings)),
+        ),
+        Ok(_) => {}
+        Err(e) => handle_write_error(e, settings.json_errors),
+    }
+}
+
+/// Says why nothing was written, e.g. that no files have duplicates with `--find-dupes`
+#[cfg(feature = "dupes")]
+fn empty_output_note(settings: &sofidu::AppSettings) -> &'static str {
+    if settings.find_dupes {
+        "No duplicate files found."
     } else {
-        // Display as tree
-        node.get_as_string_tree(0, settings.threshold, settings.machine, None)
-            .0
-    };
-    if settings.reverse {
-        // Not sure if this can be more concise
-        output = output
-            .lines()
-            .rev()
-            .map(|l| l.to_owned() + "\n")
-            .collect::<String>();
-    }
-    println!("{}", output);
+        "No entries matched the given filters."
+    }
+}
+
+#[cfg(not(feature = "dupes"))]
+fn empty_output_note(_settings: &sofidu::AppSettings) -> &'static str {
+    "No entries matched the given filters."
+}
+
+fn handle_write_error(e: std::io::Error, json_errors: bool) {
+    // Output being closed early (e.g. piped into `head`) is fine
+    if e.kind() != ErrorKind::BrokenPipe {
+        if json_errors {
+            eprintln!(
+                "{}",
+                sofidu::error_json(
+                    "write_output",
+                    format_args!("Failed to write output: {}", e)
+                )
+            );
+        } else {
+            eprintln!("Failed to write output: {}", e);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Gets current time of day as HH:MM:SS (UTC)
+fn current_time() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!(
+        "{:02}:{:02}:{:02} UTC",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
 }
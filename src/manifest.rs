@@ -0,0 +1,107 @@
+//! Reading trees from manifests of `size\tpath` lines generated elsewhere (`--from-manifest`),
+//! e.g. with `find /srv -type f -printf '%s\t%p\n'` on a remote system
+
+use crate::{tree_from_entries, Node};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Parses lines of a manifest into entries for `tree_from_entries`.
+/// Paths are made relative to `root` if they start with it.
+/// Paths ending with `/` are dirs (their size is their own), everything else is a file.
+/// Empty lines are skipped, other lines without a size or a path are an error,
+/// and so are absolute paths outside of `root` and paths with `..`.
+pub fn parse_manifest(contents: &str, root: &Path) -> Result<Vec<(PathBuf, u64, bool)>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            let invalid = |reason: &str| format!("line {}: {}", i + 1, reason);
+            let (size, path) = line
+                .split_once('\t')
+                .ok_or_else(|| invalid("expected <size>\\t<path>"))?;
+            let size = size
+                .trim()
+                .parse()
+                .map_err(|_| invalid(&format!("invalid size '{}'", size)))?;
+            if path.is_empty() {
+                return Err(invalid("empty path"));
+            }
+            let is_dir = path.ends_with('/');
+            let path = Path::new(path);
+            if path.components().any(|c| c == Component::ParentDir) {
+                return Err(invalid(&format!(
+                    "path '{}' goes up with '..'",
+                    path.display()
+                )));
+            }
+            let relative = match path.strip_prefix(root) {
+                Ok(relative) => relative,
+                Err(_) if path.has_root() => {
+                    return Err(invalid(&format!(
+                        "path '{}' is outside of '{}'",
+                        path.display(),
+                        root.display()
+                    )))
+                }
+                Err(_) => path,
+            };
+            Ok((relative.to_path_buf(), size, is_dir))
+        })
+        .collect()
+}
+
+/// Reads the manifest at `path` into a tree of nodes rooted at `root`,
+/// with dirs synthesized from prefixes of paths, without touching anything else
+pub fn read_manifest(path: &Path, root: &Path, depth: i32) -> io::Result<Node> {
+    let contents = std::fs::read_to_string(path)?;
+    let entries = parse_manifest(&contents, root)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(tree_from_entries(root, entries, depth))
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+
+    #[test]
+    fn manifest_to_node_test() {
+        let manifest = "100\t/srv/foo/bar\n\n20\t/srv/foo/baz/qiz\n4096\t/srv/empty/\n3\ttop\n";
+        let node = read_manifest_str(manifest);
+        assert_eq!(4_219, node.size);
+        assert_eq!(3, node.file_count);
+        assert_eq!(PathBuf::from("/srv/empty"), node.children[0].path);
+        assert!(node.children[0].is_dir);
+        assert_eq!(4_096, node.children[0].size);
+        let foo = &node.children[1];
+        assert!(foo.is_dir);
+        assert_eq!(120, foo.size);
+        assert_eq!(2, foo.file_count);
+        // Synthesized from the prefix of "foo/baz/qiz"
+        assert_eq!(PathBuf::from("/srv/foo/baz"), foo.children[1].path);
+        assert_eq!(20, foo.children[1].size);
+        assert!(!node.children[2].is_dir);
+        assert_eq!(3, node.children[2].size);
+
+        assert_eq!(
+            Err("line 2: invalid size 'x'".to_string()),
+            parse_manifest("1\tfoo\nx\tbar", Path::new("."))
+        );
+        assert!(parse_manifest("1 foo", Path::new(".")).is_err());
+        assert_eq!(
+            Err("line 2: path '/etc/passwd' is outside of '/srv'".to_string()),
+            parse_manifest("1\t/srv/foo\n2\t/etc/passwd", Path::new("/srv"))
+        );
+        for path in ["/srv/../etc/passwd", "foo/../../bar"] {
+            assert_eq!(
+                Err(format!("line 1: path '{}' goes up with '..'", path)),
+                parse_manifest(&format!("1\t{}", path), Path::new("/srv"))
+            );
+        }
+    }
+
+    fn read_manifest_str(manifest: &str) -> Node {
+        let root = Path::new("/srv");
+        tree_from_entries(root, parse_manifest(manifest, root).unwrap(), i32::MAX)
+    }
+}
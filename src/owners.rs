@@ -0,0 +1,171 @@
+//! Owner, group and permission display for the long list view (`--long`),
+//! and sizes summed up by owner (`--by-owner`)
+use crate::{format_size, DisplayOptions, Node};
+use std::collections::HashMap;
+
+/// User and group names by their ids, so they can be shown instead of numbers
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Names {
+    /// Reads names from `/etc/passwd` and `/etc/group`.
+    /// If they can't be read, ids are shown as numbers.
+    pub fn load() -> Self {
+        let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+        Self {
+            users: parse_id_names(&read("/etc/passwd")),
+            groups: parse_id_names(&read("/etc/group")),
+        }
+    }
+
+    /// Gets name of the user, or the uid if it's unknown
+    pub fn user(&self, uid: u32) -> String {
+        self.users
+            .get(&uid)
+            .cloned()
+            .unwrap_or_else(|| uid.to_string())
+    }
+
+    /// Gets name of the group, or the gid if it's unknown
+    pub fn group(&self, gid: u32) -> String {
+        self.groups
+            .get(&gid)
+            .cloned()
+            .unwrap_or_else(|| gid.to_string())
+    }
+}
+
+/// Parses `name:password:id:...` lines, as in `/etc/passwd` and `/etc/group`
+fn parse_id_names(contents: &str) -> HashMap<u32, String> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((id, name.to_string()))
+        })
+        .collect()
+}
+
+/// Formats permission bits of a mode like `ls -l` does, e.g. `rwxr-xr-x`
+pub fn format_mode(mode: u32) -> String {
+    let mut string = String::with_capacity(9);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        string.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        string.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        string.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    string
+}
+
+/// Sums up sizes and numbers of files in the tree by uid of their owners,
+/// as (uid, size, files), biggest first.
+/// Each entry counts only its own size, so a dir cut off by depth counts everything
+/// inside of it as its owner's. Entries without a known owner are left out.
+pub fn sizes_by_owner(node: &Node) -> Vec<(u32, u64, u64)> {
+    let mut totals: HashMap<u32, (u64, u64)> = HashMap::new();
+    add_by_owner(node, &mut totals);
+    let mut totals: Vec<_> = totals
+        .into_iter()
+        .map(|(uid, (size, files))| (uid, size, files))
+        .collect();
+    // Biggest first, then by uid so the order is always the same
+    totals.sort_by_key(|&(uid, size, _)| (std::cmp::Reverse(size), uid));
+    totals
+}
+
+fn add_by_owner(node: &Node, totals: &mut HashMap<u32, (u64, u64)>) {
+    if let Some(uid) = node.uid {
+        let total = totals.entry(uid).or_default();
+        total.0 += node.get_own_size();
+        total.1 += u64::from(!node.is_dir);
+    }
+    for child in &node.children {
+        add_by_owner(child, totals);
+    }
+}
+
+/// Returns a string with a line for each owner, with the size and number of their files
+pub fn get_as_string_by_owner(node: &Node, names: &Names, opts: &DisplayOptions) -> String {
+    let mut output = "".to_owned();
+    for (uid, size, files) in sizes_by_owner(node) {
+        let files = match files {
+            1 => "(1 file)".to_string(),
+            files => format!("({} files)", files),
+        };
+        output += &format!(
+            "{}{d}{}{d}{}\n",
+            names.user(uid),
+            opts.theme
+                .size
+                .paint(format_size(size, &opts.format_options())),
+            files,
+            d = opts.delimiter
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod owners_tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn format_mode_test() {
+        assert_eq!("rwxr-xr-x", format_mode(0o755));
+        assert_eq!("rw-r-----", format_mode(0o640));
+        // File type bits are ignored
+        assert_eq!("rw-r--r--", format_mode(0o100644));
+    }
+
+    #[test]
+    fn names_test() {
+        let names = Names {
+            users: parse_id_names(
+                "# comment\nroot:x:0:0:root:/root:/bin/sh\nfoo:x:1000:1000::/home/foo:/bin/sh\n",
+            ),
+            groups: parse_id_names("wheel:x:10:foo\n"),
+        };
+        assert_eq!("root", names.user(0));
+        assert_eq!("foo", names.user(1000));
+        assert_eq!("1001", names.user(1001));
+        assert_eq!("wheel", names.group(10));
+        assert_eq!("0", names.group(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sizes_by_owner_test() {
+        colored::control::set_override(false);
+        let owned = |path: &str, size, uid, children| {
+            let mut node = Node::new(std::path::PathBuf::from(path), size, children);
+            node.uid = Some(uid);
+            node
+        };
+        let bar = owned("foo/bar", 1_000, 1000, vec![]);
+        let baz = owned("foo/sub/baz", 3_000, 1000, vec![]);
+        let qiz = owned("foo/sub/qiz", 5_000, 0, vec![]);
+        let mut sub = owned("foo/sub", 8_100, 1000, vec![baz, qiz]);
+        sub.is_dir = true;
+        let mut node = owned("foo", 9_200, 0, vec![bar, sub]);
+        node.is_dir = true;
+
+        // Dirs only add their own size, not what's inside of them
+        assert_eq!(vec![(0, 5_100, 1), (1000, 4_100, 2)], sizes_by_owner(&node));
+        let names = Names {
+            users: parse_id_names("root:x:0:0:root:/root:/bin/sh\n"),
+            groups: HashMap::new(),
+        };
+        assert_eq!(
+            "root 5.1KB (1 file)\n1000 4.1KB (2 files)\n",
+            get_as_string_by_owner(&node, &names, &DisplayOptions::default())
+        );
+    }
+}
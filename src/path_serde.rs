@@ -0,0 +1,82 @@
+//! Serializing paths without losing any of them (`#[serde(with = "path_serde")]`),
+//! as strings if they're valid UTF-8, otherwise as their bytes (only possible on Unix)
+
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+
+/// A path as it's stored
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredPath {
+    Utf8(String),
+    Bytes(Vec<u8>),
+}
+
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(string) => serializer.serialize_str(string),
+        None => serializer.collect_seq(path_bytes(path).map_err(S::Error::custom)?),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    match StoredPath::deserialize(deserializer)? {
+        StoredPath::Utf8(string) => Ok(PathBuf::from(string)),
+        StoredPath::Bytes(bytes) => path_from_bytes(bytes).map_err(D::Error::custom),
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Result<&[u8], &'static str> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(_path: &Path) -> Result<&[u8], &'static str> {
+    Err("path contains invalid UTF-8 characters")
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, &'static str> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(_bytes: Vec<u8>) -> Result<PathBuf, &'static str> {
+    Err("paths that aren't UTF-8 can only be read on Unix")
+}
+
+/// Maps with paths as keys (`#[serde(with = "path_serde::keys")]`), stored as lists of pairs,
+/// since keys of JSON objects have to be strings
+pub mod keys {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize)]
+    struct Borrowed<'a>(#[serde(with = "super")] &'a Path);
+
+    #[derive(Deserialize)]
+    struct Owned(#[serde(with = "super")] PathBuf);
+
+    pub fn serialize<V: Serialize, S: Serializer>(
+        map: &HashMap<PathBuf, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter().map(|(path, value)| (Borrowed(path), value)))
+    }
+
+    pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<PathBuf, V>, D::Error> {
+        let pairs = Vec::<(Owned, V)>::deserialize(deserializer)?;
+        Ok(pairs
+            .into_iter()
+            .map(|(path, value)| (path.0, value))
+            .collect())
+    }
+}
@@ -0,0 +1,194 @@
+//! Colors of the output, so they can suit the terminal (`--theme`)
+
+use colored::{Color, ColoredString, Colorize};
+
+/// How one part of the output is styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ThemeColor {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub dimmed: bool,
+}
+
+impl ThemeColor {
+    /// No styling at all
+    pub const PLAIN: Self = Self {
+        color: None,
+        bold: false,
+        dimmed: false,
+    };
+    /// Not colored, just dimmed
+    pub const DIMMED: Self = Self {
+        dimmed: true,
+        ..Self::PLAIN
+    };
+
+    /// Just the color, not bold or dimmed
+    pub const fn fg(color: Color) -> Self {
+        Self {
+            color: Some(color),
+            ..Self::PLAIN
+        }
+    }
+
+    /// Same color, but bold
+    pub const fn bold(self) -> Self {
+        Self { bold: true, ..self }
+    }
+
+    /// Styles the string
+    pub fn paint<S: Into<String>>(self, string: S) -> ColoredString {
+        let string = string.into();
+        let mut string = match self.color {
+            Some(color) => string.color(color),
+            None => string.normal(),
+        };
+        if self.bold {
+            string = string.bold();
+        }
+        if self.dimmed {
+            string = string.dimmed();
+        }
+        string
+    }
+}
+
+/// Colors of the parts of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub dir: ThemeColor,
+    pub file: ThemeColor,
+    pub size: ThemeColor,
+    /// Sizes that grew since the baseline
+    pub grew: ThemeColor,
+    /// Sizes that shrunk since the baseline
+    pub shrunk: ThemeColor,
+    /// Percentages above 30%, above 16%, and the rest
+    pub percent_tiers: [ThemeColor; 3],
+    /// Changes in size, since the previous scan of `--watch` or in `--diff`
+    pub increase: ThemeColor,
+    pub decrease: ThemeColor,
+    /// Entries only in the new or only in the old tree of `--diff`
+    pub added: ThemeColor,
+    pub removed: ThemeColor,
+    /// Sparse files, which take up less space than they seem to
+    pub highlight: ThemeColor,
+    pub mount: ThemeColor,
+    /// Extra fields, like the balance
+    pub detail: ThemeColor,
+    /// Indentation of the tree
+    pub connectors: ThemeColor,
+}
+
+impl Theme {
+    pub const DEFAULT: Self = Self {
+        dir: ThemeColor::fg(Color::BrightBlue),
+        file: ThemeColor::fg(Color::Cyan),
+        size: ThemeColor::fg(Color::Green),
+        grew: ThemeColor::fg(Color::Red),
+        shrunk: ThemeColor::fg(Color::BrightGreen),
+        percent_tiers: [
+            ThemeColor::fg(Color::Red).bold(),
+            ThemeColor::fg(Color::BrightRed),
+            ThemeColor::fg(Color::White),
+        ],
+        increase: ThemeColor::fg(Color::Yellow),
+        decrease: ThemeColor::fg(Color::Cyan),
+        added: ThemeColor::fg(Color::Green),
+        removed: ThemeColor::fg(Color::Red),
+        highlight: ThemeColor::fg(Color::Yellow),
+        mount: ThemeColor::fg(Color::Magenta),
+        detail: ThemeColor::DIMMED,
+        connectors: ThemeColor::DIMMED,
+    };
+
+    /// No colors or styles at all, for terminals where they're unreadable
+    pub const MONO: Self = Self {
+        dir: ThemeColor::PLAIN,
+        file: ThemeColor::PLAIN,
+        size: ThemeColor::PLAIN,
+        grew: ThemeColor::PLAIN,
+        shrunk: ThemeColor::PLAIN,
+        percent_tiers: [ThemeColor::PLAIN; 3],
+        increase: ThemeColor::PLAIN,
+        decrease: ThemeColor::PLAIN,
+        added: ThemeColor::PLAIN,
+        removed: ThemeColor::PLAIN,
+        highlight: ThemeColor::PLAIN,
+        mount: ThemeColor::PLAIN,
+        detail: ThemeColor::PLAIN,
+        connectors: ThemeColor::PLAIN,
+    };
+
+    /// Accent colors of the Solarized palette, readable on both its light and dark backgrounds
+    pub const SOLARIZED: Self = Self {
+        dir: ThemeColor::fg(solarized::BLUE),
+        file: ThemeColor::fg(solarized::CYAN),
+        size: ThemeColor::fg(solarized::GREEN),
+        grew: ThemeColor::fg(solarized::RED),
+        shrunk: ThemeColor::fg(solarized::VIOLET),
+        percent_tiers: [
+            ThemeColor::fg(solarized::RED).bold(),
+            ThemeColor::fg(solarized::ORANGE),
+            ThemeColor::fg(solarized::BASE0),
+        ],
+        increase: ThemeColor::fg(solarized::YELLOW),
+        decrease: ThemeColor::fg(solarized::CYAN),
+        added: ThemeColor::fg(solarized::GREEN),
+        removed: ThemeColor::fg(solarized::RED),
+        highlight: ThemeColor::fg(solarized::YELLOW),
+        mount: ThemeColor::fg(solarized::MAGENTA),
+        detail: ThemeColor::fg(solarized::BASE01),
+        connectors: ThemeColor::fg(solarized::BASE01),
+    };
+
+    /// Names of the built-in themes, for `by_name`
+    pub const NAMES: [&'static str; 3] = ["default", "mono", "solarized"];
+
+    /// Gets a built-in theme by its name
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::DEFAULT),
+            "mono" => Some(Self::MONO),
+            "solarized" => Some(Self::SOLARIZED),
+            _ => None,
+        }
+    }
+
+    /// Gets the color of a percentage, by how big it is
+    pub fn percent(&self, percentage: f32) -> ThemeColor {
+        if percentage > 30.0 {
+            self.percent_tiers[0]
+        } else if percentage > 16.0 {
+            self.percent_tiers[1]
+        } else {
+            self.percent_tiers[2]
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Colors of https://ethanschoonover.com/solarized
+mod solarized {
+    use colored::Color;
+
+    const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color::TrueColor { r, g, b }
+    }
+
+    pub const BASE01: Color = rgb(0x58, 0x6e, 0x75);
+    pub const BASE0: Color = rgb(0x83, 0x94, 0x96);
+    pub const YELLOW: Color = rgb(0xb5, 0x89, 0x00);
+    pub const ORANGE: Color = rgb(0xcb, 0x4b, 0x16);
+    pub const RED: Color = rgb(0xdc, 0x32, 0x2f);
+    pub const MAGENTA: Color = rgb(0xd3, 0x36, 0x82);
+    pub const VIOLET: Color = rgb(0x6c, 0x71, 0xc4);
+    pub const BLUE: Color = rgb(0x26, 0x8b, 0xd2);
+    pub const CYAN: Color = rgb(0x2a, 0xa1, 0x98);
+    pub const GREEN: Color = rgb(0x85, 0x99, 0x00);
+}
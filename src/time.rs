@@ -0,0 +1,74 @@
+//! Formatting timestamps for the time column of the list view (`--show-time`, `--age`)
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::fmt::Display;
+use std::time::SystemTime;
+
+/// Formats time as date and time in the local timezone
+pub fn format_time(time: SystemTime) -> String {
+    format_time_in(time, &Local)
+}
+
+/// Formats time as date and time in the given timezone, e.g. `2022-01-02 13:45`
+pub fn format_time_in<Tz: TimeZone>(time: SystemTime, tz: &Tz) -> String
+where
+    Tz::Offset: Display,
+{
+    DateTime::<Utc>::from(time)
+        .with_timezone(tz)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Formats how long before `now` the time `from` was, in the biggest whole unit, e.g. `3d ago`.
+/// Months are 30 days and years 365 days.
+pub fn humanize_age(from: SystemTime, now: SystemTime) -> String {
+    const UNITS: [(u64, &str); 6] = [
+        (365 * 24 * 3600, "y"),
+        (30 * 24 * 3600, "mo"),
+        (24 * 3600, "d"),
+        (3600, "h"),
+        (60, "min"),
+        (1, "s"),
+    ];
+    let secs = match now.duration_since(from) {
+        Ok(age) => age.as_secs(),
+        // Clocks can be off, or the file was touched with a future date
+        Err(_) => return "in the future".to_string(),
+    };
+    UNITS
+        .iter()
+        .find(|(unit, _)| secs >= *unit)
+        .map_or("0s ago".to_string(), |(unit, name)| {
+            format!("{}{} ago", secs / unit, name)
+        })
+}
+
+#[cfg(test)]
+mod time_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn format_time_test() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_641_131_100);
+        assert_eq!("2022-01-02 13:45", format_time_in(time, &Utc));
+        let tz = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        assert_eq!("2022-01-02 15:45", format_time_in(time, &tz));
+    }
+
+    #[test]
+    fn humanize_age_test() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_641_131_100);
+        let ago = |secs: u64| humanize_age(now - Duration::from_secs(secs), now);
+        assert_eq!("0s ago", ago(0));
+        assert_eq!("5s ago", ago(5));
+        assert_eq!("2min ago", ago(150));
+        assert_eq!("3d ago", ago(3 * 24 * 3600 + 3600));
+        assert_eq!("2mo ago", ago(65 * 24 * 3600));
+        assert_eq!("1y ago", ago(400 * 24 * 3600));
+        assert_eq!(
+            "in the future",
+            humanize_age(now + Duration::from_secs(1), now)
+        );
+    }
+}